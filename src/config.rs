@@ -4,16 +4,41 @@
 //! to allow easy access throughout the server code with [`config()`].
 //! 
 //! The configuration can be loaded from a TOML file using [`ServerConfig::from_file()`].
-//! If loading fails, a default configuration is used.
+//! The default configuration is only used when the default file is missing.
+//!
+//! The global configuration can be replaced while the server runs with [`reload()`],
+//! [`config()`] returning a snapshot which stays the same for the whole request
+//! that holds it.
+//!
+//! Code which may run before the configuration is set, like the HTTP parsing
+//! and the responses, reads it with [`config_or_default()`] instead.
 
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
-use std::sync::OnceLock;
+use std::sync::{Arc, LazyLock, RwLock};
 use std::time::Duration;
 
+use crate::handler::cors::CorsConfig;
+use crate::handler::proxy::Upstream;
+use crate::handler::responses::ErrorFormat;
+use crate::handler::router::TrailingSlash;
 use crate::http::HttpVersion;
+use crate::http::request::parse_host;
+use crate::net::access_log::AccessLogFormat;
+use crate::net::forwarded::Cidr;
+use crate::net::rate_limit::RateLimitConfig;
+use crate::net::tunnel::TunnelConfig;
+#[cfg(feature = "tls")]
+use crate::net::tls::TlsConfig;
 
-static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
+/// Path of the configuration file when none is given
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+/// Environment variable giving the path of the configuration file
+pub const CONFIG_PATH_ENV: &str = "RUSTYNET_CONFIG";
+
+static CONFIG: RwLock<Option<Arc<ServerConfig>>> = RwLock::new(None);
+static DEFAULT_CONFIG: LazyLock<Arc<ServerConfig>> = LazyLock::new(|| Arc::new(ServerConfig::default()));
 
 /// Server configuration structure
 /// This struct holds all configurable parameters for the HTTP server.
@@ -21,28 +46,186 @@ static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
 ///
 /// As [`Duration`] does not implement `Deserialize` by default,
 /// a custom deserializer is provided for the timeout fields.
+/// The size fields accept a number of bytes or a string such as `"1MB"` or `"512KiB"`,
+/// see [`parse_size`].
+///
+/// Fields missing from the file take their value from [`ServerConfig::default()`].
 #[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
     pub address: IpAddr,
     pub port: u16,
+    #[serde(deserialize_with = "deserialize_size")]
     pub buffer_size: usize,
 
+    /// Maximum number of connections handled simultaneously, 0 meaning unlimited
+    pub max_connections: usize,
+
+    /// Threads of the executor handling the connections, 0 meaning one per CPU, see [`runtime`]
+    ///
+    /// [`runtime`]: crate::net::runtime
+    pub worker_threads: usize,
+
+    /// Requests allowed per client IP address, unlimited when unset
+    pub rate_limit: Option<RateLimitConfig>,
+
+    /// Proxies (e.g. `"10.0.0.0/8"`) whose `X-Forwarded-For` header gives the address
+    /// of the client for the access log and the rate limiting
+    pub trusted_proxies: Vec<Cidr>,
+
+    /// Path of a Unix socket listened on in addition to the TCP address
+    #[cfg(unix)]
+    pub unix_socket: Option<String>,
+
+    /// Certificate used to serve HTTPS, plaintext HTTP is served when unset
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+
     pub http_version: HttpVersion,
+
+    /// Maximum length of the request line, excluding its terminating CRLF
+    #[serde(deserialize_with = "deserialize_size")]
     pub max_request_line_size: usize,
+    #[serde(deserialize_with = "deserialize_size")]
     pub max_uri_size: usize,
+    #[serde(deserialize_with = "deserialize_size")]
     pub max_header_size: usize,
+    pub max_header_count: usize,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_host_size: usize,
+    #[serde(deserialize_with = "deserialize_size")]
     pub max_body_size: usize,
 
+    /// Maximum size of the content of a `multipart/form-data` part, see [`HttpRequest::multipart`]
+    ///
+    /// [`HttpRequest::multipart`]: crate::http::request::HttpRequest::multipart
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_multipart_part_size: usize,
+    /// Maximum size of the content of all the parts of a `multipart/form-data` body
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_multipart_size: usize,
+
+    /// Maximum number of `Set-Cookie` headers sent in a response, the extra ones are dropped
+    pub max_set_cookies: usize,
+
+    /// Time allowed to each read of a request body, and to the upstreams and tunnel targets to answer
     #[serde(deserialize_with = "deserialize_duration")]
     pub read_timeout: Duration,
 
+    /// Time allowed to receive the request line and headers, the body being excluded
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub header_timeout: Duration,
+
+    /// Time allowed to the client to receive each `buffer_size` bytes of a response
     #[serde(deserialize_with = "deserialize_duration")]
     pub write_timeout: Duration,
 
+    /// Disable Nagle's algorithm on the accepted connections, see [`socket`]
+    ///
+    /// [`socket`]: crate::net::socket
+    pub tcp_nodelay: bool,
+    /// Idle time of a connection before the TCP keep-alive probes, zero disabling them
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub tcp_keepalive: Duration,
+
     pub static_files_root: String,
 
+    /// Decode `%2F` in request paths into a segment separator instead of answering 400
+    pub allow_encoded_slash: bool,
+
+    /// List the content of the directories without an `index.html` instead of answering 404
+    pub autoindex: bool,
+
+    /// Redirection of the requests for the registered routes to the canonical form of their
+    /// path, without (`strip`) or with (`append`) a trailing slash, or none (`off`)
+    pub trailing_slash: TrailingSlash,
+
+    /// Custom error pages by status code (e.g. `404 = "./errors/404.html"`), read at startup.
+    /// The built-in pages are used for the codes missing from the map or whose file is unreadable.
+    pub error_pages: HashMap<u16, String>,
+
+    /// Format of the built-in error bodies (`html` or `json`) when the request `Accept`
+    /// header is absent or prefers neither
+    pub default_error_format: ErrorFormat,
+
+    /// Static files roots by hostname, selected from the request `Host` header.
+    /// Hosts missing from the map are served from `static_files_root`.
+    pub vhosts: HashMap<String, String>,
+
+    /// Answer `406 Not Acceptable` when a file type is excluded by the request `Accept` header
+    /// instead of ignoring the header
+    pub strict_accept: bool,
+
+    /// Default `Cache-Control` header value of static files, none is emitted when unset
+    pub static_cache_control: Option<String>,
+
+    /// `Cache-Control` header values of static files by extension,
+    /// overriding [`static_cache_control`](Self::static_cache_control)
+    pub cache_control: HashMap<String, String>,
+
+    /// Number of small static files kept in memory, 0 disables the cache
+    pub static_cache_size: usize,
+
+    /// Size in bytes above which static files are never cached
+    #[serde(deserialize_with = "deserialize_size")]
+    pub static_cache_max_file_size: usize,
+
+    /// Total size in bytes of the cached static files, the least recently used being evicted beyond
+    #[serde(deserialize_with = "deserialize_size")]
+    pub static_cache_max_size: usize,
+
+    /// Use ETags computed from the file content rather than its size and modification time
+    pub strong_etags: bool,
+
+    /// Serve the pre-compressed `<file>.gz` next to a static file, when it exists,
+    /// to the clients accepting gzip
+    pub gzip_sidecars: bool,
+
     pub server_name: String,
+
+    /// Path of the load balancers health check, answering 200 without being logged.
+    /// An empty path disables it.
+    pub health_check_path: String,
+
+    /// Path of the liveness probe, always answering 200 while the process runs
+    pub livez_path: String,
+
+    /// Path of the readiness probe, answering 503 until the server is ready to serve
+    pub readyz_path: String,
+
+    /// Serve the request metrics in the Prometheus format on `metrics_path`
+    pub metrics: bool,
+    pub metrics_path: String,
+    /// Port serving the metrics instead of the main one, e.g. to keep them private
+    pub metrics_port: Option<u16>,
+
+    /// Answer TRACE requests with an echo of the request, disabled by default
+    /// as it may expose headers added by intermediaries
+    pub allow_trace: bool,
+
+    /// Cross-origin requests policy, CORS headers are never sent when unset
+    pub cors: Option<CorsConfig>,
+
+    /// Targets of the CONNECT tunnels, CONNECT requests are answered 501 when unset
+    pub connect_tunnel: Option<TunnelConfig>,
+
+    /// Upstream URLs by path prefix (e.g. `"/api" = "http://127.0.0.1:3000"`),
+    /// to which the matching requests are forwarded
+    pub reverse_proxy: HashMap<String, String>,
+
+    /// Size of the upstream responses relayed by the reverse proxy, larger ones being answered `502 Bad Gateway`
+    #[serde(deserialize_with = "deserialize_size")]
+    pub max_upstream_response_size: usize,
+
+    /// Format of the line logged after each response (`off`, `common` or `json`)
+    pub access_log: AccessLogFormat,
+
+    /// Send a `Server-Timing` header with the time spent reading and handling each request
+    pub server_timing: bool,
+
+    /// Virtual hosts accepted in the `Host` header. An empty list accepts any host.
+    pub allowed_hosts: Vec<String>,
 }
 
 impl Default for ServerConfig {
@@ -51,61 +234,531 @@ impl Default for ServerConfig {
             address: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
             port: 8080,
             buffer_size: 4096,
+            max_connections: 1024,
+            worker_threads: 0,
+            rate_limit: None,
+            trusted_proxies: Vec::new(),
+            #[cfg(unix)]
+            unix_socket: None,
+            #[cfg(feature = "tls")]
+            tls: None,
 
             http_version: HttpVersion::V1_1,
             max_uri_size: 1024,
-            max_request_line_size: 8 + 2 + 1024 + 1 + 8, // METHOD + ' ' + URI + ' ' + HTTP/VERSION
+            // METHOD (8 bytes, the longest known ones having 7) + SP + URI + SP + HTTP/x.y
+            max_request_line_size: 8 + 1 + 1024 + 1 + 8,
             max_header_size: 8192,
+            max_header_count: 100,
+            max_host_size: 255,
             max_body_size: 1024 * 1024, // 1 MB
+            max_multipart_part_size: 512 * 1024, // 512 KB
+            max_multipart_size: 1024 * 1024,     // 1 MB
+            max_set_cookies: 50,
 
             read_timeout: Duration::from_secs(5),
+            header_timeout: Duration::from_secs(10),
             write_timeout: Duration::from_secs(5),
+            tcp_nodelay: true,
+            tcp_keepalive: Duration::ZERO,
 
             static_files_root: "./static".to_string(),
+            allow_encoded_slash: false,
+            autoindex: false,
+            trailing_slash: TrailingSlash::Off,
+            error_pages: HashMap::new(),
+            default_error_format: ErrorFormat::Html,
+            vhosts: HashMap::new(),
+            static_cache_control: None,
+            cache_control: HashMap::new(),
+            static_cache_size: 0,
+            static_cache_max_file_size: 64 * 1024, // 64 KB
+            static_cache_max_size: 16 * 1024 * 1024, // 16 MB
+            strict_accept: false,
+            strong_etags: false,
+            gzip_sidecars: false,
 
             server_name: "rustynet/0.1".to_string(),
+            health_check_path: "/healthz".to_string(),
+            livez_path: "/livez".to_string(),
+            readyz_path: "/readyz".to_string(),
+            metrics: false,
+            metrics_path: "/metrics".to_string(),
+            metrics_port: None,
+
+            allow_trace: false,
+            cors: None,
+            connect_tunnel: None,
+            reverse_proxy: HashMap::new(),
+            max_upstream_response_size: 10 * 1024 * 1024, // 10 MB
+            access_log: AccessLogFormat::Common,
+            server_timing: false,
+
+            allowed_hosts: Vec::new(),
         }
     }
 }
 
 impl ServerConfig {
 
-    /// Loads the server configuration from a TOML file at the given path.
-    /// If reading or deserialization fails, the default configuration is returned.
-    pub fn from_file(path: &str) -> Self {
-        let content = match std::fs::read_to_string(path) {
-            Ok(content) => content,
-            Err(err) => {
-                eprintln!("Fail to read {}: {err}", path);
-                eprintln!("Fall back to default config");
-                return ServerConfig::default();
+    /// Loads the server configuration read at startup from a TOML file at the given path.
+    /// The default configuration is only returned when [`DEFAULT_CONFIG_PATH`] is missing,
+    /// an invalid file or another missing one being an error.
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        if path == DEFAULT_CONFIG_PATH && !std::path::Path::new(path).exists() {
+            eprintln!("No {path} found, using the default config");
+            return Ok(ServerConfig::default());
+        }
+        Self::load(path)
+    }
+
+    /// Loads the server configuration from a TOML file at the given path,
+    /// without falling back to the default configuration.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|err| format!("Fail to read {}: {err}", path))?;
+        toml::from_str::<ServerConfig>(content.as_str())
+            .map_err(|err| format!("Fail to deserialize config file {}: {err}", path))
+    }
+
+    /// Restores the fields of a reloaded configuration which are only read at startup,
+    /// such as the listening address, from the current configuration.
+    /// Returns the names of the fields whose change was discarded.
+    pub fn keep_startup_fields(&mut self, current: &ServerConfig) -> Vec<&'static str> {
+        fn keep<T: PartialEq + Clone>(name: &'static str, new: &mut T, current: &T, kept: &mut Vec<&'static str>) {
+            if new != current {
+                *new = current.clone();
+                kept.push(name);
             }
-        };
+        }
+
+        let mut kept = Vec::new();
+        keep("address", &mut self.address, &current.address, &mut kept);
+        keep("port", &mut self.port, &current.port, &mut kept);
+        keep("max_connections", &mut self.max_connections, &current.max_connections, &mut kept);
+        keep("worker_threads", &mut self.worker_threads, &current.worker_threads, &mut kept);
+        keep("rate_limit", &mut self.rate_limit, &current.rate_limit, &mut kept);
+        #[cfg(unix)]
+        keep("unix_socket", &mut self.unix_socket, &current.unix_socket, &mut kept);
+        #[cfg(feature = "tls")]
+        keep("tls", &mut self.tls, &current.tls, &mut kept);
+        keep("metrics_port", &mut self.metrics_port, &current.metrics_port, &mut kept);
+        keep("error_pages", &mut self.error_pages, &current.error_pages, &mut kept);
+        keep("static_cache_size", &mut self.static_cache_size, &current.static_cache_size, &mut kept);
+        keep(
+            "static_cache_max_file_size",
+            &mut self.static_cache_max_file_size,
+            &current.static_cache_max_file_size,
+            &mut kept,
+        );
+        keep("static_cache_max_size", &mut self.static_cache_max_size, &current.static_cache_max_size, &mut kept);
+        kept
+    }
 
-        match toml::from_str::<ServerConfig>(content.as_str()) {
-            Ok(server_config) => server_config,
-            Err(err) => {
-                eprintln!("Fail to deserialize config file {}: {err}", path);
-                eprintln!("Fall back to default config");
-                ServerConfig::default()
+    /// Checks the invariants the server relies on, which deserialization alone
+    /// cannot enforce, returning a description of the first one violated.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port == 0 {
+            return Err("port must not be 0".to_string());
+        }
+        if self.buffer_size == 0 {
+            return Err("buffer_size must not be 0".to_string());
+        }
+        if self.max_body_size == 0 {
+            return Err("max_body_size must not be 0".to_string());
+        }
+        if self.max_upstream_response_size == 0 {
+            return Err("max_upstream_response_size must not be 0".to_string());
+        }
+        if self.max_multipart_part_size > self.max_multipart_size {
+            return Err(format!(
+                "max_multipart_part_size ({}) must not exceed max_multipart_size ({})",
+                self.max_multipart_part_size, self.max_multipart_size
+            ));
+        }
+        if self.max_header_size == 0 {
+            return Err("max_header_size must not be 0".to_string());
+        }
+        // a longer line would fill the buffer before being found too long
+        if self.buffer_size <= self.max_request_line_size {
+            return Err(format!(
+                "buffer_size ({}) must be larger than max_request_line_size ({})",
+                self.buffer_size, self.max_request_line_size
+            ));
+        }
+        if self.max_request_line_size < self.max_uri_size {
+            return Err(format!(
+                "max_request_line_size ({}) must not be smaller than max_uri_size ({})",
+                self.max_request_line_size, self.max_uri_size
+            ));
+        }
+        for (name, timeout) in [
+            ("read_timeout", self.read_timeout),
+            ("header_timeout", self.header_timeout),
+            ("write_timeout", self.write_timeout),
+        ] {
+            if timeout.is_zero() {
+                return Err(format!("{name} must be greater than 0"));
             }
         }
+        if !std::path::Path::new(&self.static_files_root).is_dir() {
+            return Err(format!("static_files_root {} is not a directory", self.static_files_root));
+        }
+        if let Some(rate_limit) = &self.rate_limit
+            && (!rate_limit.requests_per_second.is_finite()
+                || rate_limit.requests_per_second <= 0.0
+                || rate_limit.burst == 0)
+        {
+            return Err("rate_limit.requests_per_second and rate_limit.burst must be finite and greater than 0".to_string());
+        }
+        if let Some(cors) = &self.cors
+            && cors.allow_credentials
+            && cors.allows_any_origin()
+        {
+            return Err("cors.allow_credentials cannot be enabled with the * origin".to_string());
+        }
+        if let Some(tunnel) = &self.connect_tunnel
+            && let Some(target) = tunnel.allowed_targets.iter().find(|t| !matches!(parse_host(t), Some((_, Some(_)))))
+        {
+            return Err(format!("connect_tunnel.allowed_targets entry {target} is not host:port"));
+        }
+        if let Some(tunnel) = &self.connect_tunnel
+            && tunnel.idle_timeout.is_zero()
+        {
+            return Err("connect_tunnel.idle_timeout must be greater than 0".to_string());
+        }
+        for (prefix, url) in &self.reverse_proxy {
+            if !prefix.starts_with('/') || Upstream::parse(url).is_none() {
+                return Err(format!("reverse_proxy entry {prefix} = {url} is not a path prefix and an http:// URL"));
+            }
+        }
+        Ok(())
     }
 }
 
+/// Chooses the configuration file from the first command line argument,
+/// then the [`CONFIG_PATH_ENV`] environment variable, then [`DEFAULT_CONFIG_PATH`].
+/// Empty values are ignored.
+pub fn config_path(arg: Option<String>, env: Option<String>) -> String {
+    let non_empty = |path: &String| !path.is_empty();
+    arg.filter(non_empty)
+        .or(env.filter(non_empty))
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string())
+}
+
 pub fn set_config(cfg: ServerConfig) {
-    CONFIG.set(cfg).expect("Config already set");
+    let mut global = CONFIG.write().unwrap();
+    assert!(global.is_none(), "Config already set");
+    *global = Some(Arc::new(cfg));
 }
 
-pub fn config() -> &'static ServerConfig {
-    CONFIG.get().expect("Config not initialized")
+/// Replaces the global configuration, the requests being handled keeping
+/// the snapshot they already hold.
+pub fn replace_config(cfg: ServerConfig) {
+    replace_in(&CONFIG, cfg);
+}
+
+fn replace_in(global: &RwLock<Option<Arc<ServerConfig>>>, cfg: ServerConfig) {
+    *global.write().unwrap() = Some(Arc::new(cfg));
+}
+
+/// Reloads the configuration file and swaps it in once validated,
+/// the current configuration being kept when the file is invalid.
+/// The changes of the fields only read at startup are discarded with a warning.
+pub fn reload(path: &str) -> Result<(), String> {
+    replace_config(reloaded(&config(), path)?);
+    Ok(())
+}
+
+/// Loads and validates the configuration file replacing `current`.
+fn reloaded(current: &ServerConfig, path: &str) -> Result<ServerConfig, String> {
+    let mut cfg = ServerConfig::load(path)?;
+    cfg.validate()?;
+    let kept = cfg.keep_startup_fields(current);
+    if !kept.is_empty() {
+        eprintln!("Ignoring the changes of {} until the server restarts", kept.join(", "));
+    }
+    Ok(cfg)
+}
+
+/// Returns a snapshot of the global configuration.
+///
+/// # Panics
+///
+/// Panics when the configuration has not been set with [`set_config()`],
+/// see [`try_config()`] and [`config_or_default()`] otherwise.
+pub fn config() -> Arc<ServerConfig> {
+    try_config().expect("Config not initialized")
+}
+
+/// Returns a snapshot of the global configuration, or `None` when it has not been set yet.
+pub fn try_config() -> Option<Arc<ServerConfig>> {
+    CONFIG.read().unwrap().clone()
+}
+
+/// Returns a snapshot of the global configuration, or the default one when it has not been set yet.
+pub fn config_or_default() -> Arc<ServerConfig> {
+    try_config().unwrap_or_else(|| DEFAULT_CONFIG.clone())
+}
+
+/// Initializes the global configuration with default values for unit tests.
+/// Safe to call from any number of tests as the configuration is only set once.
+#[cfg(test)]
+pub fn init_test_config() {
+    CONFIG.write().unwrap().get_or_insert_with(|| Arc::new(ServerConfig::default()));
 }
 
 /// Custom deserializer for `Duration` from floating point seconds
-fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+pub(crate) fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
     let secs = f64::deserialize(deserializer)?;
-    Ok(Duration::from_secs_f64(secs))
+    // negative, infinite or NaN seconds
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+/// Custom deserializer for sizes given as a number of bytes or as a string, see [`parse_size`]
+fn deserialize_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(usize),
+        Text(String),
+    }
+
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(bytes) => Ok(bytes),
+        Size::Text(text) => parse_size(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+/// Parses a size such as `"4096"`, `"8kb"`, `"1MB"` or `"512KiB"`.
+/// The SI suffixes (`kB`, `MB`, `GB`) are powers of 1000 and the binary ones
+/// (`KiB`, `MiB`, `GiB`) powers of 1024, all of them case-insensitive.
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let digits_end = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (number, suffix) = size.split_at(digits_end);
+    let number: usize = number.parse().map_err(|_| format!("invalid size `{size}`"))?;
+
+    let multiplier: usize = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000 * 1000,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(format!("unknown suffix `{}` in size `{size}`", suffix.trim())),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{size}` is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid_config() -> ServerConfig {
+        ServerConfig {
+            static_files_root: "src".to_string(),
+            ..ServerConfig::default()
+        }
+    }
+
+    fn validation_error(cfg: ServerConfig) -> String {
+        cfg.validate().unwrap_err()
+    }
+
+    #[test]
+    fn valid_configs() {
+        assert_eq!(valid_config().validate(), Ok(()));
+        let cfg = ServerConfig {
+            rate_limit: Some(RateLimitConfig::default()),
+            ..valid_config()
+        };
+        assert_eq!(cfg.validate(), Ok(()));
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert_eq!(validation_error(ServerConfig { port: 0, ..valid_config() }), "port must not be 0");
+        assert_eq!(
+            validation_error(ServerConfig { buffer_size: 0, ..valid_config() }),
+            "buffer_size must not be 0"
+        );
+        assert_eq!(
+            validation_error(ServerConfig { max_body_size: 0, ..valid_config() }),
+            "max_body_size must not be 0"
+        );
+        assert_eq!(
+            validation_error(ServerConfig { max_upstream_response_size: 0, ..valid_config() }),
+            "max_upstream_response_size must not be 0"
+        );
+        assert_eq!(
+            validation_error(ServerConfig { max_multipart_part_size: 2048, max_multipart_size: 1024, ..valid_config() }),
+            "max_multipart_part_size (2048) must not exceed max_multipart_size (1024)"
+        );
+        assert_eq!(
+            validation_error(ServerConfig { buffer_size: 1024, max_request_line_size: 1024, ..valid_config() }),
+            "buffer_size (1024) must be larger than max_request_line_size (1024)"
+        );
+        assert_eq!(
+            validation_error(ServerConfig { max_request_line_size: 100, max_uri_size: 200, ..valid_config() }),
+            "max_request_line_size (100) must not be smaller than max_uri_size (200)"
+        );
+        assert_eq!(
+            validation_error(ServerConfig { header_timeout: Duration::ZERO, ..valid_config() }),
+            "header_timeout must be greater than 0"
+        );
+        assert_eq!(
+            validation_error(ServerConfig { static_files_root: "./missing-root".to_string(), ..valid_config() }),
+            "static_files_root ./missing-root is not a directory"
+        );
+        let rate_limit = Some(RateLimitConfig { burst: 0, ..RateLimitConfig::default() });
+        assert!(validation_error(ServerConfig { rate_limit, ..valid_config() }).starts_with("rate_limit"));
+        for requests_per_second in [f64::NAN, f64::INFINITY] {
+            let rate_limit = Some(RateLimitConfig { requests_per_second, ..RateLimitConfig::default() });
+            assert!(validation_error(ServerConfig { rate_limit, ..valid_config() }).starts_with("rate_limit"));
+        }
+        let cors = Some(CorsConfig { allowed_origins: vec!["*".to_string()], allow_credentials: true, ..CorsConfig::default() });
+        assert_eq!(
+            validation_error(ServerConfig { cors, ..valid_config() }),
+            "cors.allow_credentials cannot be enabled with the * origin"
+        );
+        let connect_tunnel = Some(TunnelConfig { idle_timeout: Duration::ZERO, ..TunnelConfig::default() });
+        assert_eq!(
+            validation_error(ServerConfig { connect_tunnel, ..valid_config() }),
+            "connect_tunnel.idle_timeout must be greater than 0"
+        );
+        let connect_tunnel =
+            Some(TunnelConfig { allowed_targets: vec!["example.com".to_string()], ..TunnelConfig::default() });
+        assert_eq!(
+            validation_error(ServerConfig { connect_tunnel, ..valid_config() }),
+            "connect_tunnel.allowed_targets entry example.com is not host:port"
+        );
+        let reverse_proxy = HashMap::from([("/api".to_string(), "https://backend".to_string())]);
+        assert_eq!(
+            validation_error(ServerConfig { reverse_proxy, ..valid_config() }),
+            "reverse_proxy entry /api = https://backend is not a path prefix and an http:// URL"
+        );
+    }
+
+    #[test]
+    fn replaced_config() {
+        // the global configuration is shared by the whole suite
+        let global = RwLock::new(Some(Arc::new(ServerConfig::default())));
+        let snapshot = global.read().unwrap().clone().unwrap();
+        let read_timeout = snapshot.read_timeout;
+
+        replace_in(&global, ServerConfig { read_timeout: Duration::from_secs(42), ..(*snapshot).clone() });
+        assert_eq!(global.read().unwrap().as_ref().unwrap().read_timeout, Duration::from_secs(42));
+        // a request holding the previous snapshot keeps seeing it
+        assert_eq!(snapshot.read_timeout, read_timeout);
+    }
+
+    #[test]
+    fn reloaded_config() {
+        let path = std::env::temp_dir().join("rustynet-reload.toml");
+        std::fs::write(&path, "port = 9090\nread_timeout = 42.0\nstatic_files_root = \"src\"\n").unwrap();
+
+        let reloaded = reloaded(&ServerConfig::default(), path.to_str().unwrap()).unwrap();
+        assert_eq!(reloaded.read_timeout, Duration::from_secs(42));
+        // only read at startup
+        assert_eq!(reloaded.port, ServerConfig::default().port);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn invalid_reload() {
+        let path = std::env::temp_dir().join("rustynet-invalid-reload.toml");
+        std::fs::write(&path, "port = 0\nstatic_files_root = \"src\"\n").unwrap();
+
+        let err = reloaded(&ServerConfig::default(), path.to_str().unwrap()).unwrap_err();
+        assert_eq!(err, "port must not be 0");
+        assert!(reloaded(&ServerConfig::default(), "./missing-config.toml").is_err());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn startup_fields_kept() {
+        let current = valid_config();
+        let mut reloaded = ServerConfig {
+            port: 9090,
+            max_connections: 1,
+            server_name: "reloaded".to_string(),
+            ..valid_config()
+        };
+        assert_eq!(reloaded.keep_startup_fields(&current), ["port", "max_connections"]);
+        assert_eq!(reloaded.port, current.port);
+        assert_eq!(reloaded.max_connections, current.max_connections);
+        assert_eq!(reloaded.server_name, "reloaded");
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1MB"), Ok(1_000_000));
+        assert_eq!(parse_size("1MiB"), Ok(1024 * 1024));
+        assert_eq!(parse_size("512KiB"), Ok(512 * 1024));
+        assert_eq!(parse_size("8kb"), Ok(8000));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("2 GB"), Ok(2_000_000_000));
+        assert_eq!(parse_size("1GG"), Err("unknown suffix `GG` in size `1GG`".to_string()));
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn sizes_in_config_file() {
+        let cfg: ServerConfig = toml::from_str("buffer_size = 8192\nmax_body_size = \"10MiB\"").unwrap();
+        assert_eq!(cfg.buffer_size, 8192);
+        assert_eq!(cfg.max_body_size, 10 * 1024 * 1024);
+
+        let err = toml::from_str::<ServerConfig>("max_body_size = \"1GG\"").unwrap_err();
+        assert!(err.to_string().contains("unknown suffix `GG`"), "{err}");
+    }
+
+    #[test]
+    fn durations_in_config_file() {
+        let cfg: ServerConfig = toml::from_str("read_timeout = 2.5").unwrap();
+        assert_eq!(cfg.read_timeout, Duration::from_millis(2500));
+
+        for invalid in ["read_timeout = -1.0", "read_timeout = inf", "read_timeout = nan"] {
+            assert!(toml::from_str::<ServerConfig>(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn trusted_proxies_in_config_file() {
+        let cfg: ServerConfig = toml::from_str("trusted_proxies = [\"10.0.0.0/8\", \"::1\"]").unwrap();
+        assert_eq!(cfg.trusted_proxies, ["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()]);
+
+        let err = toml::from_str::<ServerConfig>("trusted_proxies = [\"10.0.0.0/40\"]").unwrap_err();
+        assert!(err.to_string().contains("invalid CIDR block `10.0.0.0/40`"), "{err}");
+    }
+
+    #[test]
+    fn startup_config_file() {
+        let path = std::env::temp_dir().join("rustynet-typo.toml");
+        std::fs::write(&path, "port = 80 80\n").unwrap();
+        let err = ServerConfig::from_file(path.to_str().unwrap()).unwrap_err();
+        assert!(err.starts_with("Fail to deserialize config file"), "{err}");
+        let _ = std::fs::remove_file(path);
+
+        let err = ServerConfig::from_file("./missing-config.toml").unwrap_err();
+        assert!(err.starts_with("Fail to read ./missing-config.toml"), "{err}");
+    }
+
+    #[test]
+    fn config_path_precedence() {
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(config_path(some("cli.toml"), some("env.toml")), "cli.toml");
+        assert_eq!(config_path(None, some("env.toml")), "env.toml");
+        assert_eq!(config_path(None, None), DEFAULT_CONFIG_PATH);
+        assert_eq!(config_path(some(""), some("env.toml")), "env.toml");
+        assert_eq!(config_path(None, some("")), DEFAULT_CONFIG_PATH);
+    }
 }
@@ -0,0 +1,192 @@
+//! Cross-Origin Resource Sharing (CORS) support.
+//!
+//! When a [`cors`](crate::config::ServerConfig::cors) section is configured,
+//! preflight requests are answered directly with `204 No Content`, and the
+//! `Access-Control-Allow-*` headers are added to the responses of requests
+//! coming from an allowed origin.
+
+use serde::Deserialize;
+
+use crate::http::HttpMethod;
+use crate::http::request::HttpRequest;
+use crate::http::response::{HttpResponse, ResponseHeader};
+use crate::http::status::HttpStatus;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins allowed to access the server, `*` allows any origin
+    pub allowed_origins: Vec<String>,
+    /// Methods announced to preflight requests
+    pub allowed_methods: Vec<String>,
+    /// Request headers announced to preflight requests
+    pub allowed_headers: Vec<String>,
+    /// Time in seconds a preflight response can be cached
+    pub max_age: Option<u64>,
+    /// Allow requests with credentials (cookies, authorization headers)
+    pub allow_credentials: bool,
+}
+
+impl CorsConfig {
+    /// Returns the value of `Access-Control-Allow-Origin` for a request origin,
+    /// or `None` if the origin is not allowed.
+    ///
+    /// Any origin is answered `*` rather than echoed, so that it is never granted the
+    /// credentials, see [`validate`](crate::config::ServerConfig::validate).
+    fn allow_origin<'a>(&'a self, origin: &'a str) -> Option<&'a str> {
+        if self.allowed_origins.iter().any(|o| o == origin) {
+            Some(origin)
+        } else if self.allows_any_origin() {
+            Some("*")
+        } else {
+            None
+        }
+    }
+
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|o| o == "*")
+    }
+}
+
+/// Answers a preflight request (`OPTIONS` with `Origin` and `Access-Control-Request-Method`)
+/// from an allowed origin, or returns `None` to let the request be routed as usual.
+pub fn preflight(cfg: &CorsConfig, req: &HttpRequest) -> Option<HttpResponse> {
+    if req.method != HttpMethod::Options
+        || req.headers.get("Access-Control-Request-Method").is_none()
+    {
+        return None;
+    }
+
+    let origin = req.headers.get("Origin")?;
+    let allow_origin = cfg.allow_origin(origin)?;
+
+    let mut res = HttpResponse::new();
+    res.status = HttpStatus::NoContent;
+    set_origin_headers(cfg, allow_origin, &mut res);
+
+    if !cfg.allowed_methods.is_empty() {
+        res.set_header(ResponseHeader::AccessControlAllowMethods, &cfg.allowed_methods.join(", "));
+    }
+    if !cfg.allowed_headers.is_empty() {
+        res.set_header(ResponseHeader::AccessControlAllowHeaders, &cfg.allowed_headers.join(", "));
+    }
+    if let Some(max_age) = cfg.max_age {
+        res.set_header(ResponseHeader::AccessControlMaxAge, &max_age.to_string());
+    }
+    Some(res)
+}
+
+/// Adds the CORS headers to the response of a request from an allowed origin.
+pub fn apply(cfg: &CorsConfig, req: &HttpRequest, res: &mut HttpResponse) {
+    let Some(origin) = req.headers.get("Origin") else {
+        return;
+    };
+
+    if let Some(allow_origin) = cfg.allow_origin(origin) {
+        set_origin_headers(cfg, allow_origin, res);
+    }
+}
+
+fn set_origin_headers(cfg: &CorsConfig, allow_origin: &str, res: &mut HttpResponse) {
+    res.set_header(ResponseHeader::AccessControlAllowOrigin, allow_origin);
+    if allow_origin != "*" {
+        // the response depends on the request origin
+        res.append_header(ResponseHeader::Vary, "Origin");
+    }
+    // browsers reject credentials granted to any origin
+    if cfg.allow_credentials && allow_origin != "*" {
+        res.set_header(ResponseHeader::AccessControlAllowCredentials, "true");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+
+    fn cors_config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["Content-Type".to_string()],
+            max_age: Some(600),
+            allow_credentials: false,
+        }
+    }
+
+    fn request(method: HttpMethod, origin: &str) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.method = method;
+        req.headers.set_raw("Origin", origin);
+        req
+    }
+
+    #[test]
+    fn allowed_origin() {
+        init_test_config();
+        let req = request(HttpMethod::Get, "https://app.example.com");
+        let mut res = HttpResponse::new();
+        apply(&cors_config(), &req, &mut res);
+
+        assert_eq!(
+            res.headers.get("Access-Control-Allow-Origin").unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(res.headers.get("Vary").unwrap(), "Origin");
+    }
+
+    #[test]
+    fn disallowed_origin() {
+        init_test_config();
+        let req = request(HttpMethod::Get, "https://evil.example.com");
+        let mut res = HttpResponse::new();
+        apply(&cors_config(), &req, &mut res);
+        assert!(res.headers.get("Access-Control-Allow-Origin").is_none());
+
+        let mut req = request(HttpMethod::Options, "https://evil.example.com");
+        req.headers.set_raw("Access-Control-Request-Method", "POST");
+        assert!(preflight(&cors_config(), &req).is_none());
+    }
+
+    #[test]
+    fn wildcard_origin() {
+        init_test_config();
+        let cfg = CorsConfig { allowed_origins: vec!["*".to_string()], ..cors_config() };
+        let req = request(HttpMethod::Get, "https://any.example.com");
+        let mut res = HttpResponse::new();
+        apply(&cfg, &req, &mut res);
+        assert_eq!(res.headers.get("Access-Control-Allow-Origin").unwrap(), "*");
+
+        // the origin is never echoed with the credentials
+        let cfg = CorsConfig { allow_credentials: true, ..cfg };
+        let mut res = HttpResponse::new();
+        apply(&cfg, &req, &mut res);
+        assert_eq!(res.headers.get("Access-Control-Allow-Origin").unwrap(), "*");
+        assert!(res.headers.get("Access-Control-Allow-Credentials").is_none());
+    }
+
+    #[test]
+    fn preflight_request() {
+        init_test_config();
+        let mut req = request(HttpMethod::Options, "https://app.example.com");
+        req.headers.set_raw("Access-Control-Request-Method", "POST");
+        req.headers.set_raw("Access-Control-Request-Headers", "Content-Type");
+
+        let res = preflight(&cors_config(), &req).unwrap();
+        assert_eq!(res.status, HttpStatus::NoContent);
+        assert_eq!(
+            res.headers.get("Access-Control-Allow-Origin").unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(res.headers.get("Access-Control-Allow-Methods").unwrap(), "GET, POST");
+        assert_eq!(res.headers.get("Access-Control-Allow-Headers").unwrap(), "Content-Type");
+        assert_eq!(res.headers.get("Access-Control-Max-Age").unwrap(), "600");
+    }
+
+    #[test]
+    fn plain_options_is_not_preflight() {
+        init_test_config();
+        let req = request(HttpMethod::Options, "https://app.example.com");
+        assert!(preflight(&cors_config(), &req).is_none());
+    }
+}
@@ -0,0 +1,304 @@
+//! In-memory cache of small static files.
+//!
+//! Hot assets are kept in memory to avoid reading them from disk on every request.
+//! An entry is reused only while the modification time of the file on disk is
+//! unchanged, and the least recently used entries are evicted once the cache holds
+//! [`static_cache_size`](crate::config::ServerConfig::static_cache_size) files or
+//! [`static_cache_max_size`](crate::config::ServerConfig::static_cache_max_size) bytes.
+//! Files larger than [`static_cache_max_file_size`](crate::config::ServerConfig::static_cache_max_file_size)
+//! are always read from disk.
+//!
+//! Concurrent requests for a file missing from the cache are coalesced: the first one
+//! reads it from disk while the others wait for its content.
+
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Content of a static file along with its modification time.
+#[derive(Clone)]
+pub struct StaticFile {
+    pub body: Arc<Vec<u8>>,
+    pub modified: SystemTime,
+}
+
+/// Cached files ordered from the least to the most recently used, with their total size.
+#[derive(Default)]
+struct Entries {
+    files: IndexMap<String, StaticFile>,
+    size: usize,
+}
+
+impl Entries {
+    fn remove(&mut self, path: &str) {
+        if let Some(file) = self.files.shift_remove(path) {
+            self.size -= file.body.len();
+        }
+    }
+
+    fn remove_least_recently_used(&mut self) {
+        if let Some((_, file)) = self.files.shift_remove_index(0) {
+            self.size -= file.body.len();
+        }
+    }
+}
+
+/// File being read from disk, filled by the request reading it for the ones waiting on it.
+type Loading = Arc<Mutex<Option<StaticFile>>>;
+
+/// Removes the slot of a file from the files being read once it is read, or failed to be.
+struct LoadingSlot<'a> {
+    all: &'a Mutex<HashMap<String, Loading>>,
+    path: &'a str,
+    loading: &'a Loading,
+}
+
+impl Drop for LoadingSlot<'_> {
+    fn drop(&mut self) {
+        let mut all = self.all.lock().unwrap();
+        // a later slot of the same path is left to its own reader
+        if all.get(self.path).is_some_and(|l| Arc::ptr_eq(l, self.loading)) {
+            all.remove(self.path);
+        }
+    }
+}
+
+pub struct FileCache {
+    capacity: usize,
+    max_file_size: usize,
+    max_size: usize,
+
+    entries: Mutex<Entries>,
+
+    /// Files being read from disk, by path.
+    loading: Mutex<HashMap<String, Loading>>,
+
+    /// Number of files read from disk, exposed for the tests.
+    loads: AtomicUsize,
+}
+
+impl FileCache {
+    /// Creates a cache holding up to `capacity` files of at most `max_file_size` bytes,
+    /// `max_size` bytes in total. A zero capacity disables the cache.
+    pub fn new(capacity: usize, max_file_size: usize, max_size: usize) -> Self {
+        Self {
+            capacity,
+            max_file_size: max_file_size.min(max_size),
+            max_size,
+            entries: Mutex::new(Entries::default()),
+            loading: Mutex::new(HashMap::new()),
+            loads: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the content of a file, from memory if the cached copy is still fresh.
+    pub fn read(&self, path: &str) -> std::io::Result<StaticFile> {
+        if self.capacity == 0 {
+            return self.load(path);
+        }
+
+        // only the metadata is needed to check the freshness of a cached copy
+        let modified = std::fs::metadata(path)?.modified().unwrap_or(UNIX_EPOCH);
+        if let Some(file) = self.get(path, modified) {
+            return Ok(file);
+        }
+
+        let loading = self.loading.lock().unwrap().entry(path.to_string()).or_default().clone();
+        // declared first to be dropped last, once the slot is filled and unlocked
+        let _slot = LoadingSlot { all: &self.loading, path, loading: &loading };
+        let mut loaded = loading.lock().unwrap();
+        // read by the request which held the lock meanwhile
+        if let Some(file) = &*loaded
+            && file.modified == modified
+        {
+            return Ok(file.clone());
+        }
+
+        let file = self.load(path)?;
+        self.insert(path, &file);
+        *loaded = Some(file.clone());
+        Ok(file)
+    }
+
+    /// Returns the cached copy of a file if it is still fresh, marking it as the most recently used.
+    fn get(&self, path: &str, modified: SystemTime) -> Option<StaticFile> {
+        let mut entries = self.entries.lock().unwrap();
+        let (index, _, file) = entries.files.get_full(path)?;
+        if file.modified != modified {
+            return None;
+        }
+        let file = file.clone();
+        let last = entries.files.len() - 1;
+        entries.files.move_index(index, last);
+        Some(file)
+    }
+
+    /// Caches a file read from disk, evicting the least recently used ones to make room for it.
+    fn insert(&self, path: &str, file: &StaticFile) {
+        let size = file.body.len();
+        if size > self.max_file_size {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(path);
+        while entries.files.len() >= self.capacity || entries.size + size > self.max_size {
+            entries.remove_least_recently_used();
+        }
+        entries.size += size;
+        entries.files.insert(path.to_string(), file.clone());
+    }
+
+    fn load(&self, path: &str) -> std::io::Result<StaticFile> {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+
+        let mut file = File::open(path)?;
+        let modified = file.metadata()?.modified().unwrap_or(UNIX_EPOCH);
+        let mut body = Vec::new();
+        file.read_to_end(&mut body)?;
+
+        Ok(StaticFile {
+            body: Arc::new(body),
+            modified,
+        })
+    }
+
+    #[cfg(test)]
+    fn loads(&self) -> usize {
+        self.loads.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn write_temp(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("rustynet-cache-{}", std::process::id()))
+            .join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn cached_file_is_not_reloaded() {
+        let cache = FileCache::new(8, 1024, 1024 * 1024);
+        let path = write_temp("hit.txt", b"hello");
+        let path = path.to_str().unwrap();
+
+        assert_eq!(*cache.read(path).unwrap().body, b"hello");
+        assert_eq!(*cache.read(path).unwrap().body, b"hello");
+        assert_eq!(cache.loads(), 1);
+    }
+
+    #[test]
+    fn modified_file_is_reloaded() {
+        let cache = FileCache::new(8, 1024, 1024 * 1024);
+        let path = write_temp("modified.txt", b"old");
+        cache.read(path.to_str().unwrap()).unwrap();
+
+        std::fs::write(&path, b"new").unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+
+        assert_eq!(*cache.read(path.to_str().unwrap()).unwrap().body, b"new");
+        assert_eq!(cache.loads(), 2);
+    }
+
+    #[test]
+    fn large_file_bypasses_cache() {
+        let cache = FileCache::new(8, 4, 1024);
+        let path = write_temp("large.txt", b"too large");
+        let path = path.to_str().unwrap();
+
+        cache.read(path).unwrap();
+        cache.read(path).unwrap();
+        assert_eq!(cache.loads(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let cache = FileCache::new(2, 1024, 1024 * 1024);
+        let a = write_temp("lru-a.txt", b"a");
+        let b = write_temp("lru-b.txt", b"b");
+        let c = write_temp("lru-c.txt", b"c");
+        let (a, b, c) = (a.to_str().unwrap(), b.to_str().unwrap(), c.to_str().unwrap());
+
+        cache.read(a).unwrap();
+        cache.read(b).unwrap();
+        cache.read(a).unwrap(); // b is now the least recently used
+        cache.read(c).unwrap();
+        assert_eq!(cache.loads(), 3);
+
+        cache.read(a).unwrap();
+        assert_eq!(cache.loads(), 3);
+        cache.read(b).unwrap();
+        assert_eq!(cache.loads(), 4);
+    }
+
+    #[test]
+    fn total_size_is_bounded() {
+        let cache = FileCache::new(8, 1024, 10);
+        let a = write_temp("size-a.txt", b"aaaa");
+        let b = write_temp("size-b.txt", b"bbbb");
+        let c = write_temp("size-c.txt", b"cccc");
+        let (a, b, c) = (a.to_str().unwrap(), b.to_str().unwrap(), c.to_str().unwrap());
+
+        cache.read(a).unwrap();
+        cache.read(b).unwrap();
+        // 12 bytes would exceed the cap, a is evicted
+        cache.read(c).unwrap();
+        assert_eq!(cache.entries.lock().unwrap().size, 8);
+
+        cache.read(b).unwrap();
+        cache.read(c).unwrap();
+        assert_eq!(cache.loads(), 3);
+        cache.read(a).unwrap();
+        assert_eq!(cache.loads(), 4);
+    }
+
+    #[test]
+    fn concurrent_misses_are_coalesced() {
+        let cache = FileCache::new(8, 1024 * 1024, 1024 * 1024);
+        let path = write_temp("coalesced.txt", &vec![b'x'; 512 * 1024]);
+        let path = path.to_str().unwrap();
+
+        let barrier = std::sync::Barrier::new(8);
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    barrier.wait();
+                    assert_eq!(cache.read(path).unwrap().body.len(), 512 * 1024);
+                });
+            }
+        });
+        assert_eq!(cache.loads(), 1);
+    }
+
+    #[test]
+    fn failed_reads_are_forgotten() {
+        let cache = FileCache::new(8, 1024, 1024);
+        // a directory has metadata but cannot be read
+        let dir = write_temp("unreadable/file.txt", b"").parent().unwrap().to_path_buf();
+        assert!(cache.read(dir.to_str().unwrap()).is_err());
+        assert!(cache.loading.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn disabled_cache() {
+        let cache = FileCache::new(0, 1024, 1024 * 1024);
+        let path = write_temp("disabled.txt", b"hello");
+        let path = path.to_str().unwrap();
+
+        cache.read(path).unwrap();
+        cache.read(path).unwrap();
+        assert_eq!(cache.loads(), 2);
+    }
+}
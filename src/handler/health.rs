@@ -0,0 +1,77 @@
+//! Probes answering orchestrators such as Kubernetes.
+//!
+//! The liveness probe ([`livez_path`](crate::config::ServerConfig::livez_path)) succeeds
+//! as long as the process answers, while the readiness probe
+//! ([`readyz_path`](crate::config::ServerConfig::readyz_path)) answers
+//! `503 Service Unavailable` until [`mark_ready`] is called, e.g. once warmup is done,
+//! and again after [`mark_unready`], e.g. during maintenance.
+//!
+//! The health check of load balancers
+//! ([`health_check_path`](crate::config::ServerConfig::health_check_path)) is a liveness
+//! probe polled so often that its responses are neither compressed nor logged.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::config::config;
+use crate::http::HttpMethod;
+use crate::http::request::HttpRequest;
+use crate::http::response::{HttpResponse, ResponseHeader};
+use crate::http::status::HttpStatus;
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Reports the server as ready to serve traffic.
+pub fn mark_ready() {
+    READY.store(true, Ordering::Relaxed);
+}
+
+/// Reports the server as temporarily unable to serve traffic, without failing the liveness probe.
+#[allow(dead_code)]
+pub fn mark_unready() {
+    READY.store(false, Ordering::Relaxed);
+}
+
+fn probe(status: HttpStatus, body: &str) -> HttpResponse {
+    HttpResponse::builder()
+        .status(status)
+        .header(ResponseHeader::ContentType, "text/plain")
+        .body_text(body)
+        .build()
+}
+
+/// Whether the request targets the health check, unless it is disabled.
+pub fn is_health_check(req: &HttpRequest) -> bool {
+    is_health_check_with(&config().health_check_path, req)
+}
+
+fn is_health_check_with(path: &str, req: &HttpRequest) -> bool {
+    !path.is_empty() && matches!(req.method, HttpMethod::Get | HttpMethod::Head) && req.path == path
+}
+
+pub fn livez() -> HttpResponse {
+    probe(HttpStatus::Ok, "OK")
+}
+
+pub fn readyz() -> HttpResponse {
+    match READY.load(Ordering::Relaxed) {
+        true => probe(HttpStatus::Ok, "OK"),
+        false => probe(HttpStatus::ServiceUnavailable, "Not Ready"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_check_disabled() {
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Get;
+        req.set_target("/healthz").unwrap();
+        assert!(is_health_check_with("/healthz", &req));
+        assert!(!is_health_check_with("", &req));
+
+        req.method = HttpMethod::Post;
+        assert!(!is_health_check_with("/healthz", &req));
+    }
+}
@@ -1,9 +1,16 @@
 use flate2::Compression;
 use flate2::write::{DeflateEncoder, GzEncoder};
+use async_std::task;
 use std::io::Write;
+use std::sync::{LazyLock, RwLock};
 
+use crate::config::config;
+use crate::handler::cors;
+use crate::http::HttpMethod;
+use crate::http::negotiation;
 use crate::http::request::HttpRequest;
 use crate::http::response::{HttpResponse, ResponseHeader};
+use crate::http::status::HttpStatus;
 
 // Algorithm used for body compression as listed in MDN
 #[allow(dead_code)]
@@ -39,38 +46,440 @@ pub enum CompressionError {
     UnsupportedAlgorithm,
 }
 
-pub fn apply(req: &HttpRequest, res: &mut HttpResponse) {
-    if req.headers.get("Accept-Encoding").is_none() {
+/// Function rewriting a response body, given its content type.
+pub type BodyTransformFn = dyn Fn(&str, Vec<u8>) -> Vec<u8> + Send + Sync;
+
+/// A body transform applied to the responses of a given content type.
+pub struct BodyTransform {
+    content_type: String,
+    transform: Box<BodyTransformFn>,
+}
+
+impl BodyTransform {
+    pub fn new(
+        content_type: &str,
+        transform: impl Fn(&str, Vec<u8>) -> Vec<u8> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            content_type: content_type.to_string(),
+            transform: Box::new(transform),
+        }
+    }
+
+    /// Matches the media type of the response, ignoring parameters such as charset.
+    fn matches(&self, content_type: &str) -> bool {
+        let essence = content_type.split(';').next().unwrap_or("").trim();
+        essence.eq_ignore_ascii_case(&self.content_type)
+    }
+}
+
+static BODY_TRANSFORMS: LazyLock<RwLock<Vec<BodyTransform>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+/// Registers a transform rewriting the body of every response of the given content type
+/// (templating, minification...). Transforms run in registration order, before compression.
+#[allow(dead_code)]
+pub fn register_body_transform(
+    content_type: &str,
+    transform: impl Fn(&str, Vec<u8>) -> Vec<u8> + Send + Sync + 'static,
+) {
+    BODY_TRANSFORMS
+        .write()
+        .unwrap()
+        .push(BodyTransform::new(content_type, transform));
+}
+
+/// Applies the middlewares to a response, asynchronous so that they can await
+/// without blocking the executor. The registered transforms are called without
+/// awaiting anything, the lock guarding them never being held across an `.await`.
+pub async fn apply(req: &HttpRequest, res: &mut HttpResponse) {
+    // the file sent as the body of a response to transform is read beforehand
+    #[cfg(feature = "sendfile")]
+    if res.file.is_some()
+        && is_transformed(res)
+        && let Err(err) = res.load_file().await
+    {
+        eprintln!("Fail to read the body to transform: {}", err);
+    }
+    rewrite(&BODY_TRANSFORMS.read().unwrap(), req, res);
+    compress(req, res).await;
+}
+
+/// Applies the middlewares preceding the compression.
+fn rewrite(transforms: &[BodyTransform], req: &HttpRequest, res: &mut HttpResponse) {
+    if let Some(cfg) = &config().cors {
+        cors::apply(cfg, req, res);
+    }
+    not_modified(req, res);
+    transform_body(transforms, res);
+}
+
+/// Turns a successful GET/HEAD response carrying a `Last-Modified` header into
+/// a `304 Not Modified` when the request `If-Modified-Since` date is not older.
+/// As required by RFC 7232 section 3.3, the date is ignored when `If-None-Match` is sent.
+fn not_modified(req: &HttpRequest, res: &mut HttpResponse) {
+    if !matches!(req.method, HttpMethod::Get | HttpMethod::Head)
+        || res.status != HttpStatus::Ok
+        || req.headers.get("If-None-Match").is_some()
+    {
+        return;
+    }
+
+    let parse = |date: Option<&String>| date.and_then(|d| httpdate::parse_http_date(d).ok());
+    let (Some(last_modified), Some(since)) = (
+        parse(res.headers.get("Last-Modified")),
+        parse(req.headers.get("If-Modified-Since")),
+    ) else {
+        return;
+    };
+
+    if last_modified <= since {
+        res.status = HttpStatus::NotModified;
+        res.clear_body();
+        res.headers.remove("Content-Length");
+    }
+}
+
+/// Whether a registered transform applies to the response.
+#[cfg(feature = "sendfile")]
+fn is_transformed(res: &HttpResponse) -> bool {
+    let transforms = BODY_TRANSFORMS.read().unwrap();
+    res.headers
+        .get("Content-Type")
+        .is_some_and(|content_type| transforms.iter().any(|t| t.matches(content_type)))
+}
+
+fn transform_body(transforms: &[BodyTransform], res: &mut HttpResponse) {
+    let Some(content_type) = res.headers.get("Content-Type").cloned() else {
+        return;
+    };
+
+    // the file could not be read, see `apply`, and is sent as is
+    #[cfg(feature = "sendfile")]
+    if res.file.is_some() {
+        return;
+    }
+
+    for t in transforms.iter().filter(|t| t.matches(&content_type)) {
+        let body = (t.transform)(&content_type, std::mem::take(&mut res.body));
+        res.set_body(body);
+    }
+}
+
+/// Compresses the body on a thread of the blocking pool, so that encoding a large body
+/// does not stall the other connections handled by the executor thread.
+async fn compress(req: &HttpRequest, res: &mut HttpResponse) {
+    // gzip is only applied when the client accepts it: `gzip;q=0` forbids it
+    let accepts_gzip = req
+        .headers
+        .get("Accept-Encoding")
+        .is_some_and(|ae| negotiation::accepts_encoding(ae, CompressionAlgorithm::Gzip.as_str()));
+
+    // a byte range is a slice of the identity representation, encoding it
+    // would make the Content-Range positions meaningless
+    if res.status == HttpStatus::PartialContent {
+        return;
+    }
+
+    // there is no body to encode
+    if matches!(res.status, HttpStatus::NoContent | HttpStatus::NotModified) {
         return;
     }
-    match compress_body(res, CompressionAlgorithm::Gzip) {
-        Ok(_) => (),
-        Err(CompressionError::Io(err)) => eprintln!("Compression IO error: {}", err),
-        Err(CompressionError::UnsupportedAlgorithm) => {
-            eprintln!("Unsupported compression algorithm")
+
+    // already encoded, e.g. a pre-compressed static file
+    if res.headers.get("Content-Encoding").is_some() {
+        return;
+    }
+
+    // sent as is with sendfile, compressing it would read the whole file into memory
+    #[cfg(feature = "sendfile")]
+    if res.file.is_some() {
+        return;
+    }
+
+    // the response is compressed or not depending on the request, which caches must know
+    vary_on_accept_encoding(res);
+    if !accepts_gzip {
+        return;
+    }
+
+    // The body of a HEAD response is discarded, so compressing it only to advertise
+    // the encoded length is wasted work: the encoding is announced and the
+    // Content-Length, unknown without compressing, is omitted as allowed by RFC 7230.
+    if req.method == HttpMethod::Head {
+        res.set_header(ResponseHeader::ContentEncoding, CompressionAlgorithm::Gzip.as_str());
+        res.headers.remove("Content-Length");
+        set_gzip_etag(res);
+        return;
+    }
+
+    let algo = CompressionAlgorithm::Gzip;
+    let body = std::mem::take(&mut res.body);
+    let (body, encoded) = task::spawn_blocking(move || {
+        let encoded = encode(&body, &algo);
+        (body, encoded.map(|encoded| (encoded, algo)))
+    })
+    .await;
+
+    match encoded {
+        Ok((encoded, algo)) => {
+            // a body whose length is not known stays chunked (see `set_body`)
+            res.set_body(encoded);
+            res.set_header(ResponseHeader::ContentEncoding, algo.as_str());
+            set_gzip_etag(res);
+        }
+        // sent as is
+        Err(err) => {
+            res.body = body;
+            match err {
+                CompressionError::Io(err) => eprintln!("Compression IO error: {}", err),
+                CompressionError::UnsupportedAlgorithm => eprintln!("Unsupported compression algorithm"),
+            }
         }
     }
 }
 
-fn compress_body(
-    res: &mut HttpResponse,
-    algo: CompressionAlgorithm,
-) -> Result<(), CompressionError> {
+/// Adds `Accept-Encoding` to the `Vary` header, unless it is already listed.
+fn vary_on_accept_encoding(res: &mut HttpResponse) {
+    let listed = res
+        .headers
+        .get_all("Vary")
+        .flat_map(|vary| vary.split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("Accept-Encoding"));
+    if !listed {
+        res.append_header(ResponseHeader::Vary, "Accept-Encoding");
+    }
+}
+
+/// Entity tag of the gzip variant of a representation, e.g. `"abc-gzip"` for `"abc"`,
+/// as both variants must not share one.
+pub fn gzip_etag(etag: &str) -> String {
+    match etag.strip_suffix('"') {
+        Some(opaque) => format!("{opaque}-gzip\""),
+        None => etag.to_string(),
+    }
+}
+
+fn set_gzip_etag(res: &mut HttpResponse) {
+    if let Some(etag) = res.headers.get("ETag") {
+        let etag = gzip_etag(etag);
+        res.set_header(ResponseHeader::ETag, &etag);
+    }
+}
+
+fn encode(body: &[u8], algo: &CompressionAlgorithm) -> Result<Vec<u8>, CompressionError> {
     match algo {
         CompressionAlgorithm::Gzip => {
             let mut e = GzEncoder::new(Vec::new(), Compression::default());
-            e.write_all(&res.body).map_err(CompressionError::Io)?;
-            res.body = e.finish().map_err(CompressionError::Io)?;
+            e.write_all(body).map_err(CompressionError::Io)?;
+            e.finish().map_err(CompressionError::Io)
         }
         CompressionAlgorithm::Deflate => {
             let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
-            e.write_all(&res.body).map_err(CompressionError::Io)?;
-            res.body = e.finish().map_err(CompressionError::Io)?;
+            e.write_all(body).map_err(CompressionError::Io)?;
+            e.finish().map_err(CompressionError::Io)
         }
-        _ => return Err(CompressionError::UnsupportedAlgorithm),
+        _ => Err(CompressionError::UnsupportedAlgorithm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use async_std::task::block_on;
+    use std::io::Read;
+
+    fn compressible_response() -> HttpResponse {
+        let mut res = HttpResponse::new();
+        res.set_body(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec());
+        res
+    }
+
+    fn request(method: HttpMethod) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.method = method;
+        req.headers.set_raw("Accept-Encoding", "gzip");
+        req
+    }
+
+    #[test]
+    fn transform_before_compression() {
+        init_test_config();
+        let transforms = [BodyTransform::new("text/plain", |_, body| body.to_ascii_uppercase())];
+
+        let mut res = HttpResponse::new();
+        res.set_header(ResponseHeader::ContentType, "text/plain; charset=utf-8");
+        res.body = b"hello".to_vec();
+        let req = request(HttpMethod::Get);
+        rewrite(&transforms, &req, &mut res);
+        block_on(compress(&req, &mut res));
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(res.body.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "HELLO");
+    }
+
+    #[test]
+    fn transform_other_content_type() {
+        init_test_config();
+        let transforms = [BodyTransform::new("text/plain", |_, body| body.to_ascii_uppercase())];
+
+        let mut res = HttpResponse::new();
+        res.set_header(ResponseHeader::ContentType, "text/html");
+        res.body = b"hello".to_vec();
+        rewrite(&transforms, &HttpRequest::new(), &mut res);
+        assert_eq!(res.body, b"hello");
+    }
+
+    #[test]
+    fn get_is_compressed() {
+        init_test_config();
+        let mut res = compressible_response();
+        block_on(apply(&request(HttpMethod::Get), &mut res));
+
+        assert_eq!(res.headers.get("Content-Encoding").unwrap(), "gzip");
+        assert_eq!(res.headers.get("Content-Length").unwrap(), &res.body.len().to_string());
+        assert_eq!(&res.body[..2], [0x1f, 0x8b]); // gzip magic number
     }
 
-    res.set_header(ResponseHeader::ContentEncoding, algo.as_str());
-    res.set_header(ResponseHeader::ContentLength, &res.body.len().to_string());
-    Ok(())
+    #[test]
+    fn large_body_does_not_block_the_executor() {
+        use std::future::Future;
+        use std::task::Poll;
+
+        init_test_config();
+        let body: Vec<u8> = (0..10 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let mut res = HttpResponse::new();
+        res.set_body(body.clone());
+
+        // an encoding blocking the executor thread would complete on the first poll
+        let mut compressing = std::pin::pin!(async move {
+            compress(&request(HttpMethod::Get), &mut res).await;
+            res
+        });
+        let pending = block_on(std::future::poll_fn(|cx| Poll::Ready(compressing.as_mut().poll(cx).is_pending())));
+        assert!(pending);
+        let res = block_on(compressing);
+        assert_eq!(res.headers.get("Content-Encoding").unwrap(), "gzip");
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(res.body.as_slice()).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[cfg(feature = "sendfile")]
+    #[test]
+    fn file_body_is_not_compressed() {
+        init_test_config();
+        let path = std::env::temp_dir().join(format!("rustynet-compress-{}.txt", std::process::id()));
+        std::fs::write(&path, [b'a'; 1024]).unwrap();
+        let mut res = HttpResponse::new();
+        res.set_header(ResponseHeader::ContentType, "text/plain");
+        res.set_file(std::fs::File::open(&path).unwrap(), 1024);
+        block_on(apply(&request(HttpMethod::Get), &mut res));
+
+        assert!(res.file.is_some());
+        assert!(res.body.is_empty());
+        assert!(res.headers.get("Content-Encoding").is_none());
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "1024");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn head_is_not_compressed() {
+        init_test_config();
+        let mut res = compressible_response();
+        let body = res.body.clone();
+        block_on(apply(&request(HttpMethod::Head), &mut res));
+
+        assert_eq!(res.headers.get("Content-Encoding").unwrap(), "gzip");
+        assert!(res.headers.get("Content-Length").is_none());
+        assert_eq!(res.body, body);
+    }
+
+    #[test]
+    fn compressed_framing() {
+        init_test_config();
+        let mut buffered = compressible_response();
+        block_on(apply(&request(HttpMethod::Get), &mut buffered));
+        let headers = buffered.build_headers();
+        assert!(headers.contains(&format!("Content-Length: {}\r\n", buffered.body.len())));
+        assert!(!headers.contains("Transfer-Encoding"));
+
+        let mut unknown_length = compressible_response();
+        unknown_length.headers.remove("Content-Length");
+        unknown_length.length_known = false;
+        block_on(apply(&request(HttpMethod::Get), &mut unknown_length));
+        assert_eq!(unknown_length.headers.get("Content-Encoding").unwrap(), "gzip");
+        let headers = unknown_length.build_headers();
+        assert!(headers.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!headers.contains("Content-Length"));
+    }
+
+    #[test]
+    fn gzip_forbidden() {
+        init_test_config();
+        let mut req = request(HttpMethod::Get);
+        req.headers.set_raw("Accept-Encoding", "identity;q=1, gzip;q=0");
+
+        let mut res = compressible_response();
+        let body = res.body.clone();
+        block_on(apply(&req, &mut res));
+
+        assert!(res.headers.get("Content-Encoding").is_none());
+        assert_eq!(res.body, body);
+        // another request may get it compressed
+        assert_eq!(res.headers.get("Vary").unwrap(), "Accept-Encoding");
+    }
+
+    #[test]
+    fn gzip_variant_headers() {
+        init_test_config();
+        let mut res = compressible_response();
+        res.set_header(ResponseHeader::ETag, "\"0123456789abcdef\"");
+        res.append_header(ResponseHeader::Vary, "Origin, accept-encoding");
+        block_on(apply(&request(HttpMethod::Get), &mut res));
+
+        assert_eq!(res.headers.get("ETag").unwrap(), "\"0123456789abcdef-gzip\"");
+        assert_eq!(res.headers.get_all("Vary").collect::<Vec<_>>(), ["Origin, accept-encoding"]);
+
+        let mut res = compressible_response();
+        res.set_header(ResponseHeader::ETag, "W/\"a-1\"");
+        block_on(apply(&request(HttpMethod::Head), &mut res));
+        assert_eq!(res.headers.get("ETag").unwrap(), "W/\"a-1-gzip\"");
+        assert_eq!(res.headers.get("Vary").unwrap(), "Accept-Encoding");
+    }
+
+    #[test]
+    fn dynamic_not_modified() {
+        init_test_config();
+        let last_modified = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let dynamic_response = || {
+            let mut res = compressible_response();
+            res.set_header(ResponseHeader::LastModified, last_modified);
+            res
+        };
+
+        let mut req = request(HttpMethod::Get);
+        req.headers.set_raw("If-Modified-Since", last_modified);
+        let mut res = dynamic_response();
+        block_on(apply(&req, &mut res));
+        assert_eq!(res.status, HttpStatus::NotModified);
+        assert!(res.body.is_empty());
+        assert!(res.headers.get("Content-Encoding").is_none());
+
+        // modified after the date known by the client
+        req.headers.set_raw("If-Modified-Since", "Sat, 05 Nov 1994 08:49:37 GMT");
+        let mut res = dynamic_response();
+        block_on(apply(&req, &mut res));
+        assert_eq!(res.status, HttpStatus::Ok);
+
+        req.headers.set_raw("If-Modified-Since", "not a date");
+        let mut res = dynamic_response();
+        block_on(apply(&req, &mut res));
+        assert_eq!(res.status, HttpStatus::Ok);
+    }
 }
@@ -1,18 +1,82 @@
+pub mod cors;
+mod file_cache;
+pub mod health;
 mod middleware;
-mod responses;
-mod router;
+pub mod proxy;
+pub mod responses;
+pub mod router;
 mod static_files;
 
+use crate::config::config;
+use crate::http::HttpMethod;
 use crate::http::request::HttpRequest;
 use crate::http::response::HttpResponse;
 use crate::http::status::HttpStatus;
 
-pub fn handle_request(req: &HttpRequest) -> HttpResponse {
-    let mut res = router::route(req);
-    middleware::apply(req, &mut res);
+pub async fn handle_request(req: &HttpRequest) -> HttpResponse {
+    if let Some(cors) = &config().cors
+        && let Some(res) = cors::preflight(cors, req)
+    {
+        return res;
+    }
+
+    let mut res = router::route(req).await;
+    res.version = match req.http_version {
+        (1, 0) => (1, 0),
+        _ => (1, 1),
+    };
+    // health checks are answered as is, the response being tiny and polled often
+    if !health::is_health_check(req) {
+        middleware::apply(req, &mut res).await;
+    }
+
+    // HEAD responses carry the same headers as GET ones but never a body
+    if req.method == HttpMethod::Head {
+        res.clear_body();
+    }
     res
 }
 
+/// Loads what the handlers read from disk once, such as the custom error pages.
+pub fn init() {
+    responses::preload_error_pages();
+}
+
+/// Builds the response to a request which could not be parsed, and whose
+/// `Accept` header is thus unknown.
 pub fn handle_error(err: HttpStatus) -> HttpResponse {
-    responses::any_error(err)
+    responses::any_error(err, config().default_error_format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use async_std::task::block_on;
+
+    fn get(target: &str) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Get;
+        req.http_version = (1, 1);
+        req.set_target(target).unwrap();
+        req
+    }
+
+    #[test]
+    fn welcome_page() {
+        init_test_config();
+        let res = block_on(handle_request(&get("/")));
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.version, (1, 1));
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "text/html; charset=utf-8");
+        let expected = format!("<h1>Welcome to {}!</h1>", config().server_name);
+        assert_eq!(res.body, expected.as_bytes());
+
+        // the same headers without the body
+        let mut head = get("/");
+        head.method = HttpMethod::Head;
+        let head = block_on(handle_request(&head));
+        assert_eq!(head.headers.get("Content-Length"), res.headers.get("Content-Length"));
+        assert!(head.body.is_empty());
+    }
 }
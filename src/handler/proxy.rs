@@ -0,0 +1,379 @@
+//! Reverse proxy forwarding the requests of configured path prefixes to upstream servers.
+//!
+//! The [`reverse_proxy`](crate::config::ServerConfig::reverse_proxy) map gives the URL of
+//! the upstream (`http://host:port`, optionally followed by a base path) serving each path
+//! prefix, the longest matching prefix being used. A prefix matches the paths equal to it or
+//! continuing it with a new segment: `/api` matches `/api` and `/api/users` but not `/apis`.
+//!
+//! The request is sent on a new connection, its target being appended as received to the
+//! base path of the upstream. Its headers are forwarded except the hop-by-hop ones
+//! (`Connection`, the headers it lists, `Keep-Alive`, `Transfer-Encoding`...), and the
+//! client address is appended to `X-Forwarded-For`. The upstream response is relayed
+//! the same way, a chunked body being decoded. Unreachable upstreams and invalid responses
+//! are answered `502 Bad Gateway`, as well as the responses larger than
+//! [`max_upstream_response_size`](crate::config::ServerConfig::max_upstream_response_size),
+//! and those not answering within
+//! [`read_timeout`](crate::config::ServerConfig::read_timeout) `504 Gateway Timeout`.
+
+use std::collections::{HashMap, HashSet};
+
+use async_std::io::Read;
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+
+use crate::config::config;
+use crate::handler::responses;
+use crate::http::HttpMethod;
+use crate::http::headers::HttpHeaders;
+use crate::http::request::{HttpRequest, origin_form};
+use crate::http::response::{self, HttpResponse};
+use crate::http::status::HttpStatus;
+
+/// Headers describing a single connection, never forwarded (RFC 9110 section 7.6.1)
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Transfer-Encoding",
+    "TE",
+    "Trailer",
+    "Upgrade",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+];
+
+/// Location of an upstream server, parsed from its URL by [`Upstream::parse`].
+#[derive(Debug, PartialEq)]
+pub struct Upstream<'a> {
+    /// `host:port` connected to
+    pub authority: &'a str,
+    /// Path prepended to the forwarded targets, without trailing slash
+    pub base_path: &'a str,
+}
+
+impl<'a> Upstream<'a> {
+    /// Parses an `http://host:port[/path]` URL, the port defaulting to 80.
+    /// Other schemes, including `https`, are not supported.
+    pub fn parse(url: &'a str) -> Option<Self> {
+        let rest = url.get(..7).filter(|s| s.eq_ignore_ascii_case("http://")).map(|_| &url[7..])?;
+        let (authority, base_path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if authority.is_empty() || authority.contains(['@', '?', '#']) {
+            return None;
+        }
+        Some(Self {
+            authority,
+            base_path: base_path.trim_end_matches('/'),
+        })
+    }
+}
+
+/// Returns the URL of the upstream serving a path, from the longest matching prefix.
+pub fn upstream_for<'a>(routes: &'a HashMap<String, String>, path: &str) -> Option<&'a str> {
+    routes
+        .iter()
+        .filter(|(prefix, _)| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, url)| url.as_str())
+}
+
+/// Forwards a request to the upstream at `url` and relays its response.
+pub async fn forward(req: &HttpRequest, url: &str) -> HttpResponse {
+    let error = |status| responses::any_error(status, responses::error_format(req));
+    let Some(upstream) = Upstream::parse(url) else {
+        return error(HttpStatus::BadGateway);
+    };
+
+    let max_size = config().max_upstream_response_size;
+    let exchange = async {
+        let mut stream = TcpStream::connect(upstream.authority).await?;
+        stream.write_all(&build_request(req, &upstream)).await?;
+        read_limited(stream, max_size).await
+    };
+
+    match async_std::future::timeout(config().read_timeout, exchange).await {
+        Ok(Ok(None)) => {
+            eprintln!("Response of the upstream {url} larger than {max_size} bytes");
+            error(HttpStatus::BadGateway)
+        }
+        Ok(Ok(Some(raw))) => parse_response(&raw, req.method == HttpMethod::Head).unwrap_or_else(|| {
+            eprintln!("Invalid response from the upstream {url}");
+            error(HttpStatus::BadGateway)
+        }),
+        Ok(Err(err)) => {
+            eprintln!("Fail to forward the request to the upstream {url}: {err}");
+            error(HttpStatus::BadGateway)
+        }
+        Err(_) => error(HttpStatus::GatewayTimeout),
+    }
+}
+
+/// Reads until the end of the stream, returning `None` beyond `max_size` bytes.
+async fn read_limited<R: Read + Unpin>(reader: R, max_size: usize) -> std::io::Result<Option<Vec<u8>>> {
+    let mut raw = Vec::new();
+    // the byte read beyond the limit tells a larger response
+    reader.take(max_size as u64 + 1).read_to_end(&mut raw).await?;
+    Ok(Some(raw).filter(|raw| raw.len() <= max_size))
+}
+
+/// Names of the hop-by-hop headers of a message, including those listed by its `Connection` header.
+fn hop_by_hop(headers: &HttpHeaders) -> Vec<String> {
+    let listed = headers
+        .get_all("Connection")
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    HOP_BY_HOP_HEADERS.iter().map(|name| name.to_string()).chain(listed).collect()
+}
+
+/// Serializes the request sent to the upstream, on a connection closed after the response.
+fn build_request(req: &HttpRequest, upstream: &Upstream) -> Vec<u8> {
+    let target = format!("{}{}", upstream.base_path, origin_form(req.raw_target()));
+    let mut head = format!("{} {} HTTP/1.1\r\n", req.method.as_str(), target);
+
+    let skipped = hop_by_hop(&req.headers);
+    let is_skipped = |name: &str| {
+        skipped.iter().any(|s| s.eq_ignore_ascii_case(name))
+            || name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("X-Forwarded-For")
+            // answered by the server, the body being sent along with the headers
+            || name.eq_ignore_ascii_case("Expect")
+    };
+    for (name, value) in req.headers.iter().filter(|(name, _)| !is_skipped(name)) {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if req.headers.get("Host").is_none() {
+        head.push_str(&format!("Host: {}\r\n", upstream.authority));
+    }
+
+    let forwarded_for: Vec<String> = req
+        .headers
+        .get_all("X-Forwarded-For")
+        .cloned()
+        .chain(req.peer_addr.map(|ip| ip.to_string()))
+        .collect();
+    if !forwarded_for.is_empty() {
+        head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded_for.join(", ")));
+    }
+
+    if !req.body.is_empty() || matches!(req.method, HttpMethod::Post | HttpMethod::Put) {
+        head.push_str(&format!("Content-Length: {}\r\n", req.body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+
+    let mut request = head.into_bytes();
+    request.extend_from_slice(&req.body);
+    request
+}
+
+/// Parses the response of the upstream, read until it closed the connection.
+/// The response to a HEAD request keeps the `Content-Length` of the upstream, without body.
+/// Returns `None` when it is malformed, such as a status code not made of three digits.
+fn parse_response(raw: &[u8], head_request: bool) -> Option<HttpResponse> {
+    let head_end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..head_end]).ok()?;
+    let mut lines = head.split("\r\n");
+
+    // HTTP/1.x <code> <reason>
+    let mut status_line = lines.next()?.splitn(3, ' ');
+    if !status_line.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    let code = status_line.next()?.parse::<u16>().ok()?;
+
+    let mut headers = HttpHeaders::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        headers.append_raw(name.trim(), value.trim_matches([' ', '\t']));
+    }
+
+    let mut res = HttpResponse::new();
+    res.status = HttpStatus::from_code(code)?;
+    let skipped = hop_by_hop(&headers);
+    // the upstream values replace those of the server, such as its `Date`
+    let mut replaced = HashSet::new();
+    for (name, value) in headers.iter() {
+        if skipped.iter().any(|s| s.eq_ignore_ascii_case(name)) || name.eq_ignore_ascii_case("Content-Length") {
+            continue;
+        }
+        if replaced.insert(name.to_ascii_lowercase()) {
+            res.headers.set_raw(name, value);
+        } else {
+            res.headers.append_raw(name, value);
+        }
+    }
+
+    let content_length = headers.get("Content-Length");
+    if head_request || matches!(res.status, HttpStatus::NoContent | HttpStatus::NotModified) {
+        if let Some(len) = content_length {
+            res.headers.set_raw("Content-Length", len);
+        }
+        return Some(res);
+    }
+
+    let body = &raw[head_end + 4..];
+    let chunked = headers
+        .get_all("Transfer-Encoding")
+        .any(|te| te.rsplit(',').next().is_some_and(|c| c.trim().eq_ignore_ascii_case("chunked")));
+    let body = match content_length {
+        _ if chunked => response::decode_chunked(body)?,
+        Some(len) => body.get(..len.parse::<usize>().ok()?)?.to_vec(),
+        None => body.to_vec(),
+    };
+    res.set_body(body);
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use async_std::net::TcpListener;
+    use async_std::task;
+
+    #[test]
+    fn upstream_urls() {
+        assert_eq!(
+            Upstream::parse("http://127.0.0.1:3000"),
+            Some(Upstream { authority: "127.0.0.1:3000", base_path: "" })
+        );
+        assert_eq!(
+            Upstream::parse("HTTP://backend/app/"),
+            Some(Upstream { authority: "backend", base_path: "/app" })
+        );
+        assert!(Upstream::parse("https://backend").is_none());
+        assert!(Upstream::parse("http://").is_none());
+        assert!(Upstream::parse("http://user@backend").is_none());
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let routes = HashMap::from([
+            ("/api".to_string(), "http://a".to_string()),
+            ("/api/v2".to_string(), "http://b".to_string()),
+            ("/static/".to_string(), "http://c".to_string()),
+        ]);
+        assert_eq!(upstream_for(&routes, "/api"), Some("http://a"));
+        assert_eq!(upstream_for(&routes, "/api/users"), Some("http://a"));
+        assert_eq!(upstream_for(&routes, "/api/v2/users"), Some("http://b"));
+        assert_eq!(upstream_for(&routes, "/static/app.js"), Some("http://c"));
+        assert_eq!(upstream_for(&routes, "/apis"), None);
+        assert_eq!(upstream_for(&routes, "/"), None);
+    }
+
+    #[test]
+    fn hop_by_hop_headers_stripped() {
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Post;
+        req.set_target("/api/items?page=2").unwrap();
+        req.headers.set_raw("Host", "example.com");
+        req.headers.set_raw("Connection", "close, X-Secret");
+        req.headers.set_raw("X-Secret", "1");
+        req.headers.set_raw("Keep-Alive", "timeout=5");
+        req.headers.set_raw("X-Forwarded-For", "203.0.113.7");
+        req.headers.set_raw("Expect", "100-continue");
+        req.headers.set_raw("X-Request-Id", "42");
+        req.peer_addr = Some("10.0.0.1".parse().unwrap());
+        req.body = b"ping".to_vec();
+
+        let upstream = Upstream::parse("http://backend:3000/app").unwrap();
+        let request = String::from_utf8(build_request(&req, &upstream)).unwrap();
+        assert_eq!(
+            request,
+            "POST /app/api/items?page=2 HTTP/1.1\r\nHost: example.com\r\nX-Request-Id: 42\r\n\
+             X-Forwarded-For: 203.0.113.7, 10.0.0.1\r\nContent-Length: 4\r\nConnection: close\r\n\r\nping"
+        );
+
+        let raw = b"HTTP/1.1 200 OK\r\nKeep-Alive: timeout=5\r\nServer: backend\r\nContent-Length: 2\r\n\r\nok";
+        let res = parse_response(raw, false).unwrap();
+        assert!(res.headers.get("Keep-Alive").is_none());
+        assert_eq!(res.headers.get_all("Server").collect::<Vec<_>>(), ["backend"]);
+        assert_eq!(res.body, b"ok");
+    }
+
+    #[test]
+    fn unknown_upstream_status() {
+        init_test_config();
+        let res = parse_response(b"HTTP/1.1 418 I'm a teapot\r\nContent-Length: 0\r\n\r\n", false).unwrap();
+        assert_eq!(res.status, HttpStatus::from_code(418).unwrap());
+        let res = parse_response(b"HTTP/1.1 507 Insufficient Storage\r\nContent-Length: 0\r\n\r\n", false).unwrap();
+        assert!(res.build_headers().starts_with("HTTP/1.1 507 Server Error\r\n"));
+    }
+
+    #[test]
+    fn invalid_responses() {
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort", false).is_none());
+        assert!(parse_response(b"HTTP/1.1 999 Custom\r\n\r\n", false).is_none());
+        assert!(parse_response(b"garbage", false).is_none());
+
+        // the body of a HEAD response is not sent
+        let res = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", true).unwrap();
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "10");
+    }
+
+    #[test]
+    fn response_size_limit() {
+        task::block_on(async {
+            let raw = b"HTTP/1.1 200 OK\r\n\r\nbody";
+            assert_eq!(read_limited(&raw[..], raw.len()).await.unwrap().as_deref(), Some(&raw[..]));
+            assert_eq!(read_limited(&raw[..], raw.len() - 1).await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn proxied_request() {
+        init_test_config();
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let stub = task::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\nping") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = "HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\n\
+                                Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+                stream.write_all(response.as_bytes()).await.unwrap();
+                String::from_utf8(request).unwrap()
+            });
+
+            let mut req = HttpRequest::new();
+            req.method = HttpMethod::Post;
+            req.set_target("/api/items").unwrap();
+            req.headers.set_raw("Host", "localhost");
+            req.peer_addr = Some("10.0.0.1".parse().unwrap());
+            req.body = b"ping".to_vec();
+
+            let res = forward(&req, &url).await;
+            assert_eq!(res.status, HttpStatus::Created);
+            assert_eq!(res.body, b"hello");
+            assert_eq!(res.headers.get("Content-Length").unwrap(), "5");
+            assert!(res.headers.get("Transfer-Encoding").is_none());
+
+            let request = stub.await;
+            assert!(request.starts_with("POST /api/items HTTP/1.1\r\n"));
+            assert!(request.contains("\r\nX-Forwarded-For: 10.0.0.1\r\n"));
+        });
+    }
+
+    #[test]
+    fn unreachable_upstream() {
+        init_test_config();
+        task::block_on(async {
+            // the port is released once the listener is dropped
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            drop(listener);
+
+            let mut req = HttpRequest::new();
+            req.method = HttpMethod::Get;
+            req.set_target("/api").unwrap();
+            assert_eq!(forward(&req, &url).await.status, HttpStatus::BadGateway);
+        });
+    }
+}
@@ -1,66 +1,287 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
 use crate::config::config;
+use crate::handler::static_files;
+use crate::http::HttpMethod;
+use crate::http::negotiation;
+use crate::http::request::HttpRequest;
 use crate::http::response::{HttpResponse, ResponseHeader};
 use crate::http::status::HttpStatus;
+use crate::net::metrics::METRICS;
+use crate::util::html_escape;
+
+/// Format of the built-in error responses.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    Html,
+    Json,
+}
+
+impl ErrorFormat {
+    fn media_type(self) -> &'static str {
+        match self {
+            ErrorFormat::Html => "text/html",
+            ErrorFormat::Json => "application/json",
+        }
+    }
+
+    /// Selects the format preferred by an `Accept` header value, `default` being used
+    /// when the header is absent, accepts neither format or weighs both equally.
+    pub fn negotiate(accept: Option<&str>, default: ErrorFormat) -> ErrorFormat {
+        let other = match default {
+            ErrorFormat::Html => ErrorFormat::Json,
+            ErrorFormat::Json => ErrorFormat::Html,
+        };
+        // equally weighted types resolve to the first offered one
+        match accept.and_then(|a| negotiation::select_media_type(a, &[default.media_type(), other.media_type()])) {
+            Some(media_type) if media_type == other.media_type() => other,
+            _ => default,
+        }
+    }
+
+    /// Renders the built-in body of an error status.
+    fn body(self, status: HttpStatus) -> String {
+        let reason = status.reason_phrase();
+        match self {
+            ErrorFormat::Html => format!("<h1>{} {}</h1>", status.code(), reason),
+            ErrorFormat::Json => format!("{{\"status\":{},\"error\":\"{}\"}}", status.code(), reason),
+        }
+    }
+}
+
+/// Format of the error responses to a request, negotiated from its `Accept` header
+/// with [`default_error_format`](crate::config::ServerConfig::default_error_format) as the fallback.
+pub fn error_format(req: &HttpRequest) -> ErrorFormat {
+    ErrorFormat::negotiate(req.headers.get("Accept").map(String::as_str), config().default_error_format)
+}
+
+/// Content of a custom error page.
+pub struct ErrorPage {
+    body: Vec<u8>,
+    content_type: &'static str,
+}
+
+/// Custom error pages given by [`error_pages`](crate::config::ServerConfig::error_pages),
+/// read once so that errors never hit the disk.
+static ERROR_PAGES: LazyLock<HashMap<u16, ErrorPage>> =
+    LazyLock::new(|| load_error_pages(&config().error_pages));
+
+/// Reads the custom error pages at startup rather than on the first error.
+pub fn preload_error_pages() {
+    LazyLock::force(&ERROR_PAGES);
+}
+
+/// Reads the custom error pages, skipping the unreadable ones.
+fn load_error_pages(paths: &HashMap<u16, String>) -> HashMap<u16, ErrorPage> {
+    paths
+        .iter()
+        .filter_map(|(&code, path)| match std::fs::read(path) {
+            Ok(body) => Some((code, ErrorPage { body, content_type: static_files::guess_mime(path) })),
+            Err(err) => {
+                eprintln!("Fail to read the {} error page {}: {}, using the built-in one", code, path, err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Builds an error response from the custom page of the status,
+/// or the built-in body in the given format.
+fn error_page(pages: &HashMap<u16, ErrorPage>, status: HttpStatus, format: ErrorFormat) -> HttpResponse {
+    let (body, content_type) = match pages.get(&status.code()) {
+        Some(page) => (page.body.clone(), page.content_type),
+        None => (format.body(status).into_bytes(), format.media_type()),
+    };
+
+    HttpResponse::builder()
+        .status(status)
+        .header(ResponseHeader::ContentType, content_type)
+        .body_bytes(body)
+        .build()
+}
 
 pub fn welcome() -> HttpResponse {
-    let mut res = HttpResponse::new();
-    res.status = HttpStatus::Ok;
-    let body = format!("<h1>Welcome to {}!</h1>", config().server_name)
-        .as_bytes()
-        .to_vec();
+    HttpResponse::builder()
+        .body_html(format!("<h1>Welcome to {}!</h1>", html_escape(&config().server_name)))
+        .build()
+}
 
-    res.set_header(ResponseHeader::ContentLength, &body.len().to_string());
-    res.set_header(ResponseHeader::ContentType, "text/html");
+/// Renders the request metrics for Prometheus.
+pub fn metrics() -> HttpResponse {
+    HttpResponse::builder()
+        .header(ResponseHeader::ContentType, "text/plain; version=0.0.4")
+        .body_text(METRICS.render())
+        .build()
+}
 
-    res.body = body;
-    res
+/// Formats a list of methods as an `Allow` header value.
+fn allow_header(methods: &[HttpMethod]) -> String {
+    methods
+        .iter()
+        .map(|m| m.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
-pub fn forbidden() -> HttpResponse {
-    let mut res = HttpResponse::new();
-    res.status = HttpStatus::Forbidden;
-    let body = b"<h1>403 Forbidden</h1>".to_vec();
+/// Redirects the client to `location`, with an empty body.
+pub fn redirect(status: HttpStatus, location: &str) -> HttpResponse {
+    HttpResponse::builder()
+        .status(status)
+        .header(ResponseHeader::Location, location)
+        .build()
+}
 
-    res.set_header(ResponseHeader::ContentLength, &body.len().to_string());
-    res.set_header(ResponseHeader::ContentType, "text/html");
+/// Answers an OPTIONS request with the methods allowed for the target.
+pub fn options(allowed: &[HttpMethod]) -> HttpResponse {
+    HttpResponse::builder()
+        .header(ResponseHeader::Allow, &allow_header(allowed))
+        .build()
+}
 
-    res.body = body;
+pub fn method_not_allowed(allowed: &[HttpMethod], format: ErrorFormat) -> HttpResponse {
+    let mut res = any_error(HttpStatus::MethodNotAllowed, format);
+    res.set_header(ResponseHeader::Allow, &allow_header(allowed));
     res
 }
 
-pub fn not_found() -> HttpResponse {
-    let mut res = HttpResponse::new();
-    res.status = HttpStatus::NotFound;
-    let body = b"<h1>404 Not Found</h1>".to_vec();
+/// Headers never echoed back by [`trace`] as they may carry credentials.
+const TRACE_HIDDEN_HEADERS: [&str; 3] = ["Authorization", "Cookie", "Proxy-Authorization"];
+
+/// Echoes the received request line and headers back to the client.
+pub fn trace(req: &HttpRequest) -> HttpResponse {
+    let (maj, min) = req.http_version;
+    let mut body = format!("{} {} HTTP/{}.{}\r\n", req.method.as_str(), req.uri, maj, min);
+    for (name, value) in req.headers.iter() {
+        if !TRACE_HIDDEN_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+            body.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    body.push_str("\r\n");
 
-    res.set_header(ResponseHeader::ContentLength, &body.len().to_string());
-    res.set_header(ResponseHeader::ContentType, "text/html");
+    HttpResponse::builder()
+        .header(ResponseHeader::ContentType, "message/http")
+        .body_bytes(body.into_bytes())
+        .build()
+}
 
-    res.body = body;
-    res
+pub fn forbidden(format: ErrorFormat) -> HttpResponse {
+    error_page(&ERROR_PAGES, HttpStatus::Forbidden, format)
 }
 
-pub fn internal_server_error() -> HttpResponse {
-    let mut res = HttpResponse::new();
-    res.status = HttpStatus::InternalServerError;
-    let body = b"<h1>500 Internal Server Error</h1>".to_vec();
+pub fn not_found(format: ErrorFormat) -> HttpResponse {
+    error_page(&ERROR_PAGES, HttpStatus::NotFound, format)
+}
 
-    res.set_header(ResponseHeader::ContentLength, &body.len().to_string());
-    res.set_header(ResponseHeader::ContentType, "text/html");
+pub fn internal_server_error(format: ErrorFormat) -> HttpResponse {
+    error_page(&ERROR_PAGES, HttpStatus::InternalServerError, format)
+}
 
-    res.body = body;
-    res
+pub fn not_implemented(format: ErrorFormat) -> HttpResponse {
+    error_page(&ERROR_PAGES, HttpStatus::NotImplemented, format)
 }
 
-pub fn any_error(err: HttpStatus) -> HttpResponse {
-    match err {
-        HttpStatus::BadRequest => return not_found(),
-        HttpStatus::Forbidden => return forbidden(),
-        HttpStatus::NotFound => return not_found(),
-        HttpStatus::InternalServerError => return internal_server_error(),
-        _ => { }
+/// Builds the response of a status, the error ones carrying their error page.
+pub fn any_error(err: HttpStatus, format: ErrorFormat) -> HttpResponse {
+    if err.code() >= 400 {
+        return error_page(&ERROR_PAGES, err, format);
+    }
+    HttpResponse::builder().status(err).build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use crate::http::HttpMethod;
+
+    #[test]
+    fn custom_error_pages() {
+        init_test_config();
+        let dir = std::env::temp_dir().join(format!("rustynet-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let page = dir.join("404.html");
+        std::fs::write(&page, "<h1>Lost?</h1>").unwrap();
+
+        let paths = HashMap::from([
+            (404, page.to_str().unwrap().to_string()),
+            (500, dir.join("missing.html").to_str().unwrap().to_string()),
+        ]);
+        let pages = load_error_pages(&paths);
+
+        let res = error_page(&pages, HttpStatus::NotFound, ErrorFormat::Html);
+        assert_eq!(res.body, b"<h1>Lost?</h1>");
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "text/html; charset=utf-8");
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "14");
+
+        // unreadable pages fall back to the built-in ones
+        let res = error_page(&pages, HttpStatus::InternalServerError, ErrorFormat::Html);
+        assert_eq!(res.status, HttpStatus::InternalServerError);
+        assert_eq!(res.body, b"<h1>500 Internal Server Error</h1>");
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "text/html");
+    }
+
+    #[test]
+    fn default_error_format() {
+        init_test_config();
+        let pages = HashMap::new();
+
+        // without Accept header, the default format is used
+        let res = error_page(&pages, HttpStatus::NotFound, ErrorFormat::negotiate(None, ErrorFormat::Json));
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "application/json");
+        assert_eq!(res.body, br#"{"status":404,"error":"Not Found"}"#);
+        assert_eq!(res.headers.get("Content-Length").unwrap(), &res.body.len().to_string());
+
+        // ambiguous or unrelated Accept headers fall back to the default
+        assert_eq!(ErrorFormat::negotiate(Some("*/*"), ErrorFormat::Json), ErrorFormat::Json);
+        assert_eq!(ErrorFormat::negotiate(Some("image/png"), ErrorFormat::Json), ErrorFormat::Json);
+        assert_eq!(ErrorFormat::negotiate(Some("text/html"), ErrorFormat::Json), ErrorFormat::Html);
+        assert_eq!(
+            ErrorFormat::negotiate(Some("text/html;q=0.5, application/json"), ErrorFormat::Html),
+            ErrorFormat::Json
+        );
+
+        let cfg: crate::config::ServerConfig = toml::from_str("default_error_format = \"json\"").unwrap();
+        assert_eq!(cfg.default_error_format, ErrorFormat::Json);
+    }
+
+    #[test]
+    fn error_pages_config() {
+        let cfg: crate::config::ServerConfig = toml::from_str("[error_pages]\n404 = \"./404.html\"").unwrap();
+        assert_eq!(cfg.error_pages.get(&404).unwrap(), "./404.html");
+    }
+
+    #[test]
+    fn every_error_has_a_page() {
+        init_test_config();
+        let res = any_error(HttpStatus::PayloadTooLarge, ErrorFormat::Json);
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "application/json");
+        assert_eq!(res.body, br#"{"status":413,"error":"Payload Too Large"}"#);
+
+        let res = method_not_allowed(&[HttpMethod::Get], ErrorFormat::Html);
+        assert_eq!(res.body, b"<h1>405 Method Not Allowed</h1>");
+        assert_eq!(res.headers.get("Allow").unwrap(), "GET");
+
+        // the other statuses have no body
+        assert!(any_error(HttpStatus::NoContent, ErrorFormat::Html).body.is_empty());
+    }
+
+    #[test]
+    fn trace_echoes_request() {
+        init_test_config();
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Trace;
+        req.set_target("/echo").unwrap();
+        req.http_version = (1, 1);
+        req.headers.set_raw("Host", "example.com");
+        req.headers.set_raw("Authorization", "Basic c2VjcmV0");
+        req.headers.set_raw("Cookie", "session=secret");
+
+        let res = trace(&req);
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "message/http");
+        assert_eq!(res.body, b"TRACE /echo HTTP/1.1\r\nHost: example.com\r\n\r\n");
     }
-    let mut res = HttpResponse::new();
-    res.status = err;
-    res
 }
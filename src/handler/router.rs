@@ -1,15 +1,511 @@
+use crate::config::config;
+use crate::handler::health;
+use crate::handler::proxy;
 use crate::handler::responses;
 use crate::handler::static_files;
 use crate::http::HttpMethod;
-use crate::http::request::HttpRequest;
+use crate::http::percent;
+use crate::http::request::{HttpRequest, origin_form};
 use crate::http::response::HttpResponse;
 use crate::http::status::HttpStatus;
+use serde::Deserialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, RwLock};
 
-pub fn route(req: &HttpRequest) -> HttpResponse {
-    match (&req.method, req.uri.as_str()) {
-        (HttpMethod::Get, "/") => responses::welcome(),
+/// Function producing the response of a registered route.
+pub type RouteHandlerFn = dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync;
 
-        (HttpMethod::Get, _) => static_files::serve(&req.uri),
-        _ => responses::any_error(HttpStatus::MethodNotAllowed),
+/// Future of the response produced by a [`Handler`].
+pub type HandlerFuture = Pin<Box<dyn Future<Output = HttpResponse> + Send>>;
+
+/// An asynchronous route handler, implemented for the closures returning a future
+/// such as `|req| async move { ... }`.
+///
+/// [`Handler::call`] is the desugared form of `async fn call(&self, req: HttpRequest) -> HttpResponse`
+/// whose future is boxed, so that handlers of different types can be stored by the router.
+pub trait Handler: Send + Sync {
+    fn call(&self, req: HttpRequest) -> HandlerFuture;
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(HttpRequest) -> Fut + Send + Sync,
+    Fut: Future<Output = HttpResponse> + Send + 'static,
+{
+    fn call(&self, req: HttpRequest) -> HandlerFuture {
+        Box::pin(self(req))
+    }
+}
+
+enum RouteHandler {
+    Sync(Box<RouteHandlerFn>),
+    Async(Box<dyn Handler>),
+}
+
+/// A route registered programmatically, matched on the method and the exact path,
+/// and optionally on the hostname of the request `Host` header.
+pub struct Route {
+    host: Option<String>,
+    method: HttpMethod,
+    path: String,
+    handler: RouteHandler,
+}
+
+impl Route {
+    #[allow(dead_code)]
+    pub fn new(
+        method: HttpMethod,
+        path: &str,
+        handler: impl Fn(&HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_handler(method, path, RouteHandler::Sync(Box::new(handler)))
+    }
+
+    /// Same as [`Route::new`] with an asynchronous handler, receiving a copy of the request.
+    #[allow(dead_code)]
+    pub fn new_async(method: HttpMethod, path: &str, handler: impl Handler + 'static) -> Self {
+        Self::with_handler(method, path, RouteHandler::Async(Box::new(handler)))
+    }
+
+    fn with_handler(method: HttpMethod, path: &str, handler: RouteHandler) -> Self {
+        Self {
+            host: None,
+            method,
+            path: path.to_string(),
+            handler,
+        }
+    }
+
+    /// Restricts the route to the requests for the given hostname (e.g. `api.example.com`).
+    #[allow(dead_code)]
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    /// GET routes also answer HEAD requests, whose body is discarded afterwards.
+    fn matches(&self, req: &HttpRequest) -> bool {
+        let method_matches = self.method == req.method
+            || (self.method == HttpMethod::Get && req.method == HttpMethod::Head);
+
+        method_matches && self.matches_host(req) && self.path == req.path
+    }
+
+    fn matches_host(&self, req: &HttpRequest) -> bool {
+        match &self.host {
+            None => true,
+            Some(host) => req.host().is_some_and(|(name, _)| name.eq_ignore_ascii_case(host)),
+        }
+    }
+
+    async fn call(&self, req: &HttpRequest) -> HttpResponse {
+        match &self.handler {
+            RouteHandler::Sync(handler) => handler(req),
+            RouteHandler::Async(handler) => handler.call(req.clone()).await,
+        }
+    }
+}
+
+/// Canonical form of the paths of the registered routes, the router matching
+/// `/about` and `/about/` alike as the request paths are normalized.
+///
+/// The policy only applies to the requests matching a route: the static files keep
+/// their own rules, directories being served with a trailing slash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    #[default]
+    Off,
+    Strip,
+    Append,
+}
+
+impl TrailingSlash {
+    /// Returns the target to which a request must be redirected, the query being kept,
+    /// or `None` when its path is already canonical.
+    ///
+    /// The target is rebuilt from the normalized path of the request, so that it never
+    /// starts with `//`, which would be a network-path reference to another host.
+    fn canonical_target(self, req: &HttpRequest) -> Option<String> {
+        let target = origin_form(&req.uri);
+        let (raw_path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+        // the root is canonical in any case
+        if raw_path == "/" || !raw_path.starts_with('/') {
+            return None;
+        }
+
+        let path: String = req
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| format!("/{}", percent::encode_segment(segment)))
+            .collect();
+        let path = match self {
+            TrailingSlash::Off => return None,
+            TrailingSlash::Strip if raw_path.ends_with('/') => match path.is_empty() {
+                true => "/".to_string(),
+                false => path,
+            },
+            TrailingSlash::Append if !raw_path.ends_with('/') => format!("{path}/"),
+            _ => return None,
+        };
+        Some(match query {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        })
+    }
+
+    /// Redirects a request matching a route to its canonical target, permanently.
+    /// 308 is used for the methods other than GET and HEAD, as clients may turn them
+    /// into a GET on a 301.
+    fn redirect(self, req: &HttpRequest) -> Option<HttpResponse> {
+        let location = self.canonical_target(req)?;
+        let status = match req.method {
+            HttpMethod::Get | HttpMethod::Head => HttpStatus::MovedPermanently,
+            _ => HttpStatus::PermanentRedirect,
+        };
+        Some(responses::redirect(status, &location))
+    }
+}
+
+/// Registered routes, shared so that a matched route outlives the lock
+/// while its asynchronous handler runs.
+static ROUTES: LazyLock<RwLock<Vec<Arc<Route>>>> = LazyLock::new(|| RwLock::new(Vec::new()));
+
+/// Registers a route, checked before the built-in ones in registration order.
+#[allow(dead_code)]
+pub fn register_route(route: Route) {
+    ROUTES.write().unwrap().push(Arc::new(route));
+}
+
+pub async fn route(req: &HttpRequest) -> HttpResponse {
+    let (matched, route_methods) = find_route(&ROUTES.read().unwrap(), req);
+    dispatch(matched, &route_methods, req).await
+}
+
+/// Returns the route matching the request, along with the methods of the routes registered
+/// for its host and path, or for its host only with `*`.
+fn find_route(routes: &[Arc<Route>], req: &HttpRequest) -> (Option<Arc<Route>>, Vec<HttpMethod>) {
+    let matched = routes.iter().find(|r| r.matches(req)).cloned();
+    let methods = routes
+        .iter()
+        .filter(|r| r.matches_host(req) && (req.path == "*" || r.path == req.path))
+        .map(|r| r.method)
+        .collect();
+    (matched, methods)
+}
+
+async fn dispatch(matched: Option<Arc<Route>>, route_methods: &[HttpMethod], req: &HttpRequest) -> HttpResponse {
+    // the probes are never shadowed by other routes
+    if health::is_health_check(req) {
+        return health::livez();
+    }
+    if matches!(req.method, HttpMethod::Get | HttpMethod::Head) {
+        if req.path == config().livez_path {
+            return health::livez();
+        }
+        if req.path == config().readyz_path {
+            return health::readyz();
+        }
+        // served on its own port instead when one is configured
+        if config().metrics && config().metrics_port.is_none() && req.path == config().metrics_path {
+            return responses::metrics();
+        }
+    }
+
+    if let Some(route) = matched {
+        if let Some(redirect) = config().trailing_slash.redirect(req) {
+            return redirect;
+        }
+        return route.call(req).await;
+    }
+
+    if let Some(upstream) = proxy::upstream_for(&config().reverse_proxy, &req.path) {
+        return proxy::forward(req, upstream).await;
+    }
+
+    let not_allowed = || {
+        responses::method_not_allowed(&allowed_methods(&req.path, route_methods), responses::error_format(req))
+    };
+    match (&req.method, req.path.as_str()) {
+        (HttpMethod::Options, _) => responses::options(&allowed_methods(&req.path, route_methods)),
+        (HttpMethod::Trace, _) if config().allow_trace => responses::trace(req),
+        // the path is served by its routes, none of which accepts the method
+        _ if !route_methods.is_empty() => not_allowed(),
+
+        (HttpMethod::Get | HttpMethod::Head, "/") => responses::welcome(),
+
+        (HttpMethod::Get | HttpMethod::Head, _) => static_files::serve(req).await,
+        (HttpMethod::Connect, _) => responses::not_implemented(responses::error_format(req)),
+        _ => not_allowed(),
+    }
+}
+
+/// Lists the methods accepted for a path, or for the whole server with `*`, given the
+/// methods of the routes registered for it (see [`find_route`]).
+/// Used to answer OPTIONS requests and build the `Allow` header of 405 responses.
+fn allowed_methods(path: &str, route_methods: &[HttpMethod]) -> Vec<HttpMethod> {
+    let mut methods = Vec::new();
+    // the paths without routes are served by the welcome page or the static files
+    if path == "*" || route_methods.is_empty() {
+        methods.extend([HttpMethod::Get, HttpMethod::Head]);
+    }
+    for &method in route_methods {
+        // GET routes also answer HEAD requests
+        let implied = (method == HttpMethod::Get).then_some(HttpMethod::Head);
+        for method in std::iter::once(method).chain(implied) {
+            if !methods.contains(&method) {
+                methods.push(method);
+            }
+        }
+    }
+
+    methods.push(HttpMethod::Options);
+    if config().allow_trace {
+        methods.push(HttpMethod::Trace);
+    }
+    methods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use crate::http::status::HttpStatus;
+    use async_std::task::block_on;
+
+    fn route_with(routes: &[Arc<Route>], req: &HttpRequest) -> HttpResponse {
+        let (matched, route_methods) = find_route(routes, req);
+        block_on(dispatch(matched, &route_methods, req))
+    }
+
+    #[test]
+    fn trace_disabled_by_default() {
+        init_test_config();
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Trace;
+        req.set_target("/").unwrap();
+        assert_eq!(block_on(route(&req)).status, HttpStatus::MethodNotAllowed);
+    }
+
+    fn options_request(target: &str) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Options;
+        req.set_target(target).unwrap();
+        req
+    }
+
+    #[test]
+    fn options_server() {
+        init_test_config();
+        let res = block_on(route(&options_request("*")));
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.headers.get("Allow").unwrap(), "GET, HEAD, OPTIONS");
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "0");
+        assert!(res.body.is_empty());
+    }
+
+    #[test]
+    fn options_path() {
+        init_test_config();
+        let res = block_on(route(&options_request("/")));
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.headers.get("Allow").unwrap(), "GET, HEAD, OPTIONS");
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "0");
+    }
+
+    #[test]
+    fn method_not_allowed_lists_methods() {
+        init_test_config();
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Delete;
+        req.set_target("/").unwrap();
+        let res = block_on(route(&req));
+        assert_eq!(res.status, HttpStatus::MethodNotAllowed);
+        assert_eq!(res.headers.get("Allow").unwrap(), "GET, HEAD, OPTIONS");
+    }
+
+    #[test]
+    fn methods_of_registered_routes() {
+        init_test_config();
+        let created = |_: &HttpRequest| responses::any_error(HttpStatus::Created, responses::ErrorFormat::Html);
+        let routes = [
+            Arc::new(Route::new(HttpMethod::Post, "/items", created)),
+            Arc::new(Route::new(HttpMethod::Put, "/items", created)),
+            Arc::new(Route::new(HttpMethod::Delete, "/other", created).host("other.example.com")),
+        ];
+
+        let res = route_with(&routes, &options_request("/items"));
+        assert_eq!(res.headers.get("Allow").unwrap(), "POST, PUT, OPTIONS");
+        assert_eq!(route_with(&routes, &request(HttpMethod::Post, "/items")).status, HttpStatus::Created);
+
+        // not served from the static files
+        let res = route_with(&routes, &request(HttpMethod::Get, "/items"));
+        assert_eq!(res.status, HttpStatus::MethodNotAllowed);
+        assert_eq!(res.headers.get("Allow").unwrap(), "POST, PUT, OPTIONS");
+
+        let res = route_with(&routes, &options_request("*"));
+        assert_eq!(res.headers.get("Allow").unwrap(), "GET, HEAD, POST, PUT, OPTIONS");
+
+        // the route of another host is ignored
+        let res = route_with(&routes, &options_request("/other"));
+        assert_eq!(res.headers.get("Allow").unwrap(), "GET, HEAD, OPTIONS");
+    }
+
+    #[test]
+    fn connect_not_implemented() {
+        init_test_config();
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Connect;
+        req.set_target("example.com:443").unwrap();
+        assert_eq!(block_on(route(&req)).status, HttpStatus::NotImplemented);
+    }
+
+    #[test]
+    fn health_probes() {
+        init_test_config();
+        let probe = |path: &str| {
+            let mut req = HttpRequest::new();
+            req.method = HttpMethod::Get;
+            req.set_target(path).unwrap();
+            block_on(route(&req))
+        };
+
+        let res = probe("/livez");
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.body, b"OK");
+
+        // not ready during warmup or maintenance, while still alive
+        health::mark_unready();
+        assert_eq!(probe("/readyz").status, HttpStatus::ServiceUnavailable);
+        assert_eq!(probe("/livez").status, HttpStatus::Ok);
+
+        health::mark_ready();
+        let res = probe("/readyz");
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "text/plain");
+
+        health::mark_unready();
+        assert_eq!(probe("/readyz").status, HttpStatus::ServiceUnavailable);
+        assert_eq!(probe("/livez").status, HttpStatus::Ok);
+    }
+
+    #[test]
+    fn health_check_before_static_files() {
+        init_test_config();
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Get;
+        req.set_target("/healthz").unwrap();
+
+        // neither a route nor a static file of the same name is served
+        let not_found = |_: &HttpRequest| responses::not_found(responses::ErrorFormat::Html);
+        let shadowing = [Arc::new(Route::new(HttpMethod::Get, "/healthz", not_found))];
+        let res = route_with(&shadowing, &req);
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.body, b"OK");
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "text/plain");
+
+        // nor compressed
+        req.headers.set_raw("Accept-Encoding", "gzip");
+        let res = block_on(crate::handler::handle_request(&req));
+        assert_eq!(res.body, b"OK");
+        assert!(res.headers.get("Content-Encoding").is_none());
+    }
+
+    fn request(method: HttpMethod, target: &str) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.method = method;
+        req.set_target(target).unwrap();
+        req
+    }
+
+    #[test]
+    fn trailing_slash_off() {
+        init_test_config();
+        let ok = |_: &HttpRequest| responses::any_error(HttpStatus::NoContent, responses::ErrorFormat::Html);
+        let routes = [Arc::new(Route::new(HttpMethod::Get, "/about", ok))];
+
+        // both forms are served by the route
+        for target in ["/about", "/about/"] {
+            assert_eq!(route_with(&routes, &request(HttpMethod::Get, target)).status, HttpStatus::NoContent);
+            assert!(TrailingSlash::Off.redirect(&request(HttpMethod::Get, target)).is_none());
+        }
+    }
+
+    #[test]
+    fn trailing_slash_strip() {
+        init_test_config();
+        let policy = TrailingSlash::Strip;
+        let res = policy.redirect(&request(HttpMethod::Get, "/about/")).unwrap();
+        assert_eq!(res.status, HttpStatus::MovedPermanently);
+        assert_eq!(res.headers.get("Location").unwrap(), "/about");
+
+        let canonical = |target| policy.canonical_target(&request(HttpMethod::Get, target));
+        assert_eq!(canonical("/a%20b//?q=1/").as_deref(), Some("/a%20b?q=1/"));
+        assert_eq!(canonical("http://example.com/about/").as_deref(), Some("/about"));
+        assert_eq!(canonical("/about"), None);
+        assert_eq!(canonical("/"), None);
+        assert_eq!(canonical("//").as_deref(), Some("/"));
+
+        let res = policy.redirect(&request(HttpMethod::Post, "/form/")).unwrap();
+        assert_eq!(res.status, HttpStatus::PermanentRedirect);
+    }
+
+    #[test]
+    fn trailing_slash_redirect_stays_on_host() {
+        init_test_config();
+        let location = |policy: TrailingSlash, target| {
+            let res = policy.redirect(&request(HttpMethod::Get, target)).unwrap();
+            res.headers.get("Location").unwrap().to_string()
+        };
+        assert_eq!(location(TrailingSlash::Strip, "//evil.example/"), "/evil.example");
+        assert_eq!(location(TrailingSlash::Strip, "//evil.example/../about//"), "/about");
+        assert_eq!(location(TrailingSlash::Append, "//evil.example"), "/evil.example/");
+        assert_eq!(location(TrailingSlash::Append, "///evil.example?q=1"), "/evil.example/?q=1");
+    }
+
+    #[test]
+    fn trailing_slash_append() {
+        init_test_config();
+        let policy = TrailingSlash::Append;
+        let res = policy.redirect(&request(HttpMethod::Head, "/about?lang=fr")).unwrap();
+        assert_eq!(res.status, HttpStatus::MovedPermanently);
+        assert_eq!(res.headers.get("Location").unwrap(), "/about/?lang=fr");
+
+        let canonical = |target| policy.canonical_target(&request(HttpMethod::Get, target));
+        assert_eq!(canonical("/about/"), None);
+        assert_eq!(canonical("/"), None);
+        assert_eq!(canonical("*"), None);
+
+        let cfg: crate::config::ServerConfig = toml::from_str("trailing_slash = \"append\"").unwrap();
+        assert_eq!(cfg.trailing_slash, TrailingSlash::Append);
+    }
+
+    #[test]
+    fn host_qualified_route() {
+        use crate::handler::responses;
+        use crate::http::request::RequestHeader;
+
+        init_test_config();
+        let no_content = |_: &HttpRequest| responses::any_error(HttpStatus::NoContent, responses::ErrorFormat::Html);
+        let routes = [Arc::new(Route::new(HttpMethod::Get, "/v1/users", no_content).host("api.example.com"))];
+
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Get;
+        req.set_target("/v1/users").unwrap();
+
+        req.set_header(RequestHeader::Host, "API.example.com:8080");
+        assert_eq!(route_with(&routes, &req).status, HttpStatus::NoContent);
+
+        req.set_header(RequestHeader::Host, "www.example.com");
+        assert_ne!(route_with(&routes, &req).status, HttpStatus::NoContent);
+
+        req.set_header(RequestHeader::Host, "api.example.com");
+        req.set_target("/v1/orders").unwrap();
+        assert_ne!(route_with(&routes, &req).status, HttpStatus::NoContent);
     }
 }
@@ -1,58 +1,858 @@
-use std::fs::File;
-use std::io::Read;
+use std::collections::HashMap;
 use std::io::ErrorKind::*;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::config::config;
+use async_std::task;
+
+use crate::config::{ServerConfig, config};
+use crate::handler::file_cache::{FileCache, StaticFile};
+use crate::handler::middleware;
 use crate::handler::responses;
+use crate::http::negotiation;
+use crate::http::percent;
+use crate::http::range::{self, Range};
+use crate::http::request::HttpRequest;
 use crate::http::response::{HttpResponse, ResponseHeader};
 use crate::http::status::HttpStatus;
+use crate::util::html_escape;
+
+/// Strong ETags already computed, keyed by file path along with the modification
+/// time they were computed for, so that files are only rehashed when they change.
+static STRONG_ETAGS: LazyLock<Mutex<HashMap<String, (SystemTime, String)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static FILE_CACHE: LazyLock<FileCache> = LazyLock::new(|| {
+    FileCache::new(
+        config().static_cache_size,
+        config().static_cache_max_file_size,
+        config().static_cache_max_size,
+    )
+});
+
+pub async fn serve(req: &HttpRequest) -> HttpResponse {
+    let cfg = config();
+    let root = document_root(req, &cfg.vhosts, &cfg.static_files_root);
+    serve_blocking(root.to_string(), req.clone()).await
+}
+
+/// Runs [`serve_from`] on a thread of the blocking pool, so that a slow disk does not
+/// stall the executor threads and the other connections they handle.
+async fn serve_blocking(root: String, req: HttpRequest) -> HttpResponse {
+    task::spawn_blocking(move || serve_from(&root, &req)).await
+}
+
+/// Selects the static files root for the request `Host` among the configured
+/// [`vhosts`](crate::config::ServerConfig::vhosts), falling back to `default_root`.
+fn document_root<'a>(
+    req: &HttpRequest,
+    vhosts: &'a HashMap<String, String>,
+    default_root: &'a str,
+) -> &'a str {
+    let Some((hostname, _)) = req.host() else {
+        return default_root;
+    };
+
+    vhosts
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(hostname))
+        .map(|(_, root)| root.as_str())
+        .unwrap_or(default_root)
+}
 
-pub fn serve(path: &str) -> HttpResponse {
+fn serve_from(root: &str, req: &HttpRequest) -> HttpResponse {
+    let cfg = config();
     let mut response = HttpResponse::new();
 
-    let safe_path = sanitize_path(path);
-    let full_path = format!("{}{}", config().static_files_root, safe_path);
+    // already decoded and resolved, so that it cannot leave the root
+    let safe_path = &req.path;
+    let mut full_path = format!("{}{}", root, safe_path);
+
+    // a directory is served through its index file, or listed when there is none
+    if Path::new(&full_path).is_dir() {
+        let index = format!("{}/index.html", full_path.trim_end_matches('/'));
+        if !Path::new(&index).is_file() {
+            if !cfg.autoindex {
+                return responses::not_found(responses::error_format(req));
+            }
+            return match directory_listing(root, &full_path, safe_path) {
+                Ok(listing) => listing,
+                Err(_) => responses::internal_server_error(responses::error_format(req)),
+            };
+        }
+        full_path = index;
+    }
     eprintln!("Serving static file: {}", full_path);
 
-    let mut file = match File::open(&full_path) {
+    let mime = match negotiation::negotiate(
+        req.headers.get("Accept").map(String::as_str),
+        &[guess_mime(&full_path)],
+        cfg.strict_accept,
+    ) {
+        Ok(mime) => mime,
+        Err(status) => return responses::any_error(status, responses::error_format(req)),
+    };
+
+    let accepts_gzip = req
+        .headers
+        .get("Accept-Encoding")
+        .is_some_and(|ae| negotiation::accepts_encoding(ae, "gzip"));
+
+    #[cfg(feature = "sendfile")]
+    if let Some(response) = unread_file(req, &full_path, mime, accepts_gzip) {
+        return response;
+    }
+
+    let (file, encoding) = match read_variant(&full_path, cfg.gzip_sidecars && accepts_gzip) {
         Ok(f) => f,
         Err(err) => match err.kind() {
-            NotFound => return responses::not_found(),
-            PermissionDenied => return responses::forbidden(),
-            _ => return responses::internal_server_error(), 
-        }
+            NotFound => return responses::not_found(responses::error_format(req)),
+            PermissionDenied => return responses::forbidden(responses::error_format(req)),
+            _ => return responses::internal_server_error(responses::error_format(req)),
+        },
+    };
+
+    caching_headers(&mut response, &full_path, &cfg);
+    if let Some(encoding) = encoding {
+        response.set_header(ResponseHeader::ContentEncoding, encoding);
+    }
+
+    // each variant has its own entity tag
+    let variant_path = match encoding {
+        Some(_) => format!("{full_path}.gz"),
+        None => full_path.clone(),
     };
+    let etag = etag(&variant_path, &file, cfg.strong_etags);
+    response.set_header(ResponseHeader::ETag, &etag);
 
-    let mut body = Vec::new();
-    if file.read_to_end(&mut body).is_err() {
-        response.status = HttpStatus::InternalServerError;
+    if let Some(if_none_match) = req.headers.get("If-None-Match")
+        && etag_matches(if_none_match, &etag)
+    {
+        response.status = HttpStatus::NotModified;
         return response;
     }
 
-    response.set_header(ResponseHeader::ContentLength, &body.len().to_string());
-    response.set_header(ResponseHeader::ContentType, guess_mime(&full_path));
+    response.set_header(ResponseHeader::AcceptRanges, "bytes");
+    response.set_header(ResponseHeader::ContentType, mime);
 
-    response.body = body;
+    let len = file.body.len();
+    let if_range = req.headers.get("If-Range").map(String::as_str);
+    let range = req
+        .headers
+        .get("Range")
+        .filter(|_| if_range_matches(if_range, &etag, file.modified));
+    let body = match range.map(|r| range::parse_range(r, len)) {
+        Some(Range::Partial { start, end }) => {
+            response.status = HttpStatus::PartialContent;
+            response.set_header(
+                ResponseHeader::ContentRange,
+                &format!("bytes {start}-{end}/{len}"),
+            );
+            file.body[start..=end].to_vec()
+        }
+        Some(Range::Unsatisfiable) => {
+            response.status = HttpStatus::RangeNotSatisfiable;
+            response.set_header(ResponseHeader::ContentRange, &format!("bytes */{len}"));
+            Vec::new()
+        }
+        Some(Range::Full) | None => file.body.to_vec(),
+    };
+    response.set_body(body);
     response
 }
 
-fn sanitize_path(path: &str) -> &str {
-    path // do nothing for now
+/// Sets the `Cache-Control` and `Vary` headers of a static file.
+fn caching_headers(response: &mut HttpResponse, full_path: &str, cfg: &ServerConfig) {
+    let default_cache_control = cfg.static_cache_control.as_deref();
+    if let Some(cache_control) = cache_control(full_path, &cfg.cache_control, default_cache_control)
+    {
+        response.set_header(ResponseHeader::CacheControl, cache_control);
+    }
+
+    if cfg.gzip_sidecars {
+        // the representation depends on the request Accept-Encoding
+        response.append_header(ResponseHeader::Vary, "Accept-Encoding");
+    }
 }
 
-fn guess_mime(path: &str) -> &str {
-    match path.rsplit('.').next() {
-        Some("htm") | Some("html") => "text/html",
-        Some("css") => "text/css",
-        Some("js") => "application/javascript",
-        Some("png") => "image/png",
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("gif") => "image/gif",
-        Some("svg") => "image/svg+xml",
-        Some("json") => "application/json",
-        Some("xml") => "application/xml",
-        Some("txt") => "text/plain",
-        Some("pdf") => "application/pdf",
+/// Builds the response of a file too large for the cache without reading it, the file
+/// being sent with `sendfile` (see [`HttpResponse::file`]). Returns `None` for the files
+/// read into memory: the small ones, those served as a byte range or from their `.gz`
+/// sidecar, or all of them with strong ETags, which hash the content.
+#[cfg(feature = "sendfile")]
+fn unread_file(
+    req: &HttpRequest,
+    full_path: &str,
+    mime: &'static str,
+    accepts_gzip: bool,
+) -> Option<HttpResponse> {
+    let cfg = config();
+    if cfg.strong_etags || (cfg.gzip_sidecars && accepts_gzip) || req.headers.get("Range").is_some()
+    {
+        return None;
+    }
+    // the errors are answered by the buffered path, and opening the special
+    // files, e.g. a FIFO, could block until they are written
+    if !std::fs::metadata(full_path).is_ok_and(|metadata| metadata.is_file()) {
+        return None;
+    }
+    let file = std::fs::File::open(full_path).ok()?;
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() <= cfg.static_cache_max_file_size as u64 {
+        return None;
+    }
+
+    let mut response = HttpResponse::new();
+    caching_headers(&mut response, full_path, &cfg);
+    let etag = weak_etag(metadata.len(), metadata.modified().unwrap_or(UNIX_EPOCH));
+    response.set_header(ResponseHeader::ETag, &etag);
+
+    if let Some(if_none_match) = req.headers.get("If-None-Match")
+        && etag_matches(if_none_match, &etag)
+    {
+        response.status = HttpStatus::NotModified;
+        return Some(response);
+    }
+
+    response.set_header(ResponseHeader::AcceptRanges, "bytes");
+    response.set_header(ResponseHeader::ContentType, mime);
+    response.set_file(file, metadata.len());
+    Some(response)
+}
+
+/// Reads the file to serve, preferring its `.gz` sidecar when `gzip` is set.
+/// Returns the content coding of the file read, if any.
+fn read_variant(path: &str, gzip: bool) -> std::io::Result<(StaticFile, Option<&'static str>)> {
+    if gzip && let Ok(file) = FILE_CACHE.read(&format!("{path}.gz")) {
+        return Ok((file, Some("gzip")));
+    }
+    FILE_CACHE.read(path).map(|file| (file, None))
+}
+
+/// Generates the HTML listing of a directory, directories first then files, sorted by name.
+/// Entries resolving outside of `root`, such as symbolic links to other parts of the
+/// filesystem, are not listed.
+fn directory_listing(root: &str, dir: &str, uri_path: &str) -> std::io::Result<HttpResponse> {
+    let root = std::fs::canonicalize(root)?;
+
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let Ok(target) = std::fs::canonicalize(entry.path()) else {
+            continue; // dangling link
+        };
+        if !target.starts_with(&root) {
+            continue;
+        }
+        let metadata = std::fs::metadata(&target)?;
+        entries.push((
+            !metadata.is_dir(),
+            entry.file_name().to_string_lossy().into_owned(),
+            metadata.len(),
+        ));
+    }
+    entries.sort();
+
+    let base = uri_path.trim_end_matches('/');
+    let title = html_escape(&format!("Index of {}/", base));
+    let mut body = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+         <body>\n<h1>{title}</h1>\n<table>\n"
+    );
+    if !base.is_empty() {
+        body.push_str("<tr><td><a href=\"../\">../</a></td><td>-</td></tr>\n");
+    }
+    for (is_file, name, size) in entries {
+        let (slash, size) = match is_file {
+            true => ("", size.to_string()),
+            false => ("/", "-".to_string()),
+        };
+        body.push_str(&format!(
+            "<tr><td><a href=\"{}/{}{slash}\">{}{slash}</a></td><td>{size}</td></tr>\n",
+            html_escape(base),
+            percent::encode_segment(&name),
+            html_escape(&name),
+        ));
+    }
+    body.push_str("</table>\n</body>\n</html>\n");
+
+    Ok(HttpResponse::builder().body_html(body).build())
+}
+
+/// Selects the `Cache-Control` value configured for the extension of the file,
+/// falling back to the default one.
+fn cache_control<'a>(
+    path: &str,
+    by_extension: &'a HashMap<String, String>,
+    default: Option<&'a str>,
+) -> Option<&'a str> {
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str());
+    extension
+        .and_then(|extension| {
+            by_extension
+                .iter()
+                .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+                .map(|(_, value)| value.as_str())
+        })
+        .or(default)
+}
+
+/// Computes the entity tag of a file.
+///
+/// The weak form is derived from the file size and modification time, which is cheap
+/// but differs between servers holding the same content. The strong form is a hash
+/// of the content itself, cached by path and modification time.
+fn etag(path: &str, file: &StaticFile, strong: bool) -> String {
+    let mtime = file.modified;
+
+    if !strong {
+        return weak_etag(file.body.len() as u64, mtime);
+    }
+
+    let mut cache = STRONG_ETAGS.lock().unwrap();
+    if let Some((cached_mtime, etag)) = cache.get(path)
+        && *cached_mtime == mtime
+    {
+        return etag.clone();
+    }
+
+    let etag = format!("\"{:016x}\"", fnv1a(&file.body));
+    cache.insert(path.to_string(), (mtime, etag.clone()));
+    etag
+}
+
+/// Weak ETag computed from the size and modification time of a file.
+fn weak_etag(len: u64, modified: SystemTime) -> String {
+    let secs = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("W/\"{:x}-{:x}\"", len, secs)
+}
+
+/// 64-bit FNV-1a hash, stable across platforms and builds unlike the std hashers.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Checks an `If-None-Match` header value against the current entity tag, or the one
+/// of its gzip variant, using the weak comparison required by RFC 7232 section 3.2.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let (identity, gzip) = (opaque(etag), opaque(&middleware::gzip_etag(etag)));
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(opaque)
+            .any(|tag| tag == identity || tag == gzip)
+}
+
+/// Checks an `If-Range` header value, whose absence lets the `Range` apply, against the
+/// current representation (RFC 9110 section 13.1.5). A different one is sent in full.
+///
+/// Entity tags use the strong comparison, a weak tag never matching, and a date must be
+/// the modification time of the file, to the second as HTTP dates are.
+fn if_range_matches(if_range: Option<&str>, etag: &str, modified: SystemTime) -> bool {
+    let Some(if_range) = if_range.map(str::trim) else {
+        return true;
+    };
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return !etag.starts_with("W/") && if_range == etag;
+    }
+
+    let secs = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    httpdate::parse_http_date(if_range)
+        .is_ok_and(|date| date == UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Guesses the media type of a file from its extension, case-insensitively.
+/// Textual types are declared as UTF-8.
+pub fn guess_mime(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        // text
+        "htm" | "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml; charset=utf-8",
+        "json" | "map" => "application/json; charset=utf-8",
+        "webmanifest" => "application/manifest+json; charset=utf-8",
+        "svg" => "image/svg+xml; charset=utf-8",
+
+        // images
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+
+        // fonts
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+
+        // audio and video
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+
+        // others
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
         _ => "application/octet-stream",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use crate::http::request::RequestHeader;
+    use std::path::PathBuf;
+
+    fn write_temp(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("rustynet-{}", std::process::id()))
+            .join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn file_etag(path: &Path, strong: bool) -> String {
+        let file = FileCache::new(0, 0, 0)
+            .read(path.to_str().unwrap())
+            .unwrap();
+        etag(path.to_str().unwrap(), &file, strong)
+    }
+
+    #[test]
+    fn virtual_hosts() {
+        init_test_config();
+        let a = write_temp("vhost-a/index.txt", b"site a");
+        let b = write_temp("vhost-b/index.txt", b"site b");
+        let root = |p: &PathBuf| p.parent().unwrap().to_str().unwrap().to_string();
+
+        let vhosts = HashMap::from([
+            ("a.example.com".to_string(), root(&a)),
+            ("b.example.com".to_string(), root(&b)),
+        ]);
+
+        let mut req = HttpRequest::new();
+        req.set_target("/index.txt").unwrap();
+
+        req.set_header(RequestHeader::Host, "a.example.com:8080");
+        let res = serve_from(document_root(&req, &vhosts, "./static"), &req);
+        assert_eq!(res.body, b"site a");
+
+        req.set_header(RequestHeader::Host, "b.example.com");
+        let res = serve_from(document_root(&req, &vhosts, "./static"), &req);
+        assert_eq!(res.body, b"site b");
+
+        req.set_header(RequestHeader::Host, "c.example.com");
+        assert_eq!(document_root(&req, &vhosts, "./static"), "./static");
+    }
+
+    #[test]
+    fn range_requests() {
+        init_test_config();
+        let path = write_temp("range/digits.txt", b"0123456789");
+        let root = path.parent().unwrap().to_str().unwrap();
+
+        let mut req = HttpRequest::new();
+        req.set_target("/digits.txt").unwrap();
+
+        req.headers.set_raw("Range", "bytes=2-4");
+        let res = serve_from(root, &req);
+        assert_eq!(res.status, HttpStatus::PartialContent);
+        assert_eq!(res.headers.get("Content-Range").unwrap(), "bytes 2-4/10");
+        assert_eq!(res.body, b"234");
+
+        req.headers.set_raw("Range", "bytes=20-");
+        let res = serve_from(root, &req);
+        assert_eq!(res.status, HttpStatus::RangeNotSatisfiable);
+        assert_eq!(res.headers.get("Content-Range").unwrap(), "bytes */10");
+        assert!(res.body.is_empty());
+
+        req.headers.set_raw("Range", "items=0-9");
+        let res = serve_from(root, &req);
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert!(res.headers.get("Content-Range").is_none());
+        assert_eq!(res.body, b"0123456789");
+    }
+
+    #[cfg(feature = "sendfile")]
+    #[test]
+    fn large_file_is_not_read() {
+        init_test_config();
+        let content = vec![b'x'; config().static_cache_max_file_size + 1];
+        let path = write_temp("sendfile/large.txt", &content);
+        let root = path.parent().unwrap().to_str().unwrap();
+        write_temp("sendfile/small.txt", b"small");
+
+        let mut req = HttpRequest::new();
+        req.set_target("/large.txt").unwrap();
+        let mut res = serve_from(root, &req);
+        assert!(res.body.is_empty());
+        assert_eq!(
+            res.headers.get("Content-Length").unwrap(),
+            &content.len().to_string()
+        );
+        task::block_on(res.load_file()).unwrap();
+        assert_eq!(res.body, content);
+
+        // a byte range is sliced from memory
+        req.headers.set_raw("Range", "bytes=0-1");
+        let res = serve_from(root, &req);
+        assert!(res.file.is_none());
+        assert_eq!(res.body, b"xx");
+
+        let mut req = HttpRequest::new();
+        req.set_target("/small.txt").unwrap();
+        let res = serve_from(root, &req);
+        assert!(res.file.is_none());
+        assert_eq!(res.body, b"small");
+    }
+
+    #[test]
+    fn if_range() {
+        init_test_config();
+        let path = write_temp("if-range/digits.txt", b"0123456789");
+        let root = path.parent().unwrap().to_str().unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let mut req = HttpRequest::new();
+        req.set_target("/digits.txt").unwrap();
+        req.headers.set_raw("Range", "bytes=2-4");
+
+        req.headers
+            .set_raw("If-Range", &httpdate::fmt_http_date(modified));
+        let res = serve_from(root, &req);
+        assert_eq!(res.status, HttpStatus::PartialContent);
+        assert_eq!(res.body, b"234");
+
+        // the file changed since the client received its part
+        let stale = modified - Duration::from_secs(60);
+        req.headers
+            .set_raw("If-Range", &httpdate::fmt_http_date(stale));
+        let res = serve_from(root, &req);
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert!(res.headers.get("Content-Range").is_none());
+        assert_eq!(res.body, b"0123456789");
+
+        // the weak entity tags of the default config are not usable
+        let etag = res.headers.get("ETag").unwrap().clone();
+        req.headers.set_raw("If-Range", &etag);
+        assert_eq!(serve_from(root, &req).status, HttpStatus::Ok);
+    }
+
+    #[test]
+    fn if_range_validators() {
+        let modified = UNIX_EPOCH + Duration::from_millis(784_111_777_250);
+        let strong = "\"0123456789abcdef\"";
+
+        assert!(if_range_matches(None, strong, modified));
+        assert!(if_range_matches(Some(strong), strong, modified));
+        assert!(!if_range_matches(
+            Some("\"fedcba9876543210\""),
+            strong,
+            modified
+        ));
+        assert!(!if_range_matches(Some("W/\"a-1\""), "W/\"a-1\"", modified));
+        assert!(!if_range_matches(
+            Some("W/\"0123456789abcdef\""),
+            strong,
+            modified
+        ));
+
+        assert!(if_range_matches(
+            Some("Sun, 06 Nov 1994 08:49:37 GMT"),
+            strong,
+            modified
+        ));
+        assert!(!if_range_matches(
+            Some("Sun, 06 Nov 1994 08:49:36 GMT"),
+            strong,
+            modified
+        ));
+        assert!(!if_range_matches(Some("not a date"), strong, modified));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn large_file_does_not_block_the_executor() {
+        use std::future::Future;
+        use std::io::Write;
+        use std::task::Poll;
+
+        init_test_config();
+        // reading a FIFO blocks until it is written, like a large file on a slow disk
+        let path = write_temp("large/video.bin", b"");
+        std::fs::remove_file(&path).unwrap();
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        // SAFETY: the path is a valid C string
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let root = path.parent().unwrap().to_str().unwrap().to_string();
+        let mut req = HttpRequest::new();
+        req.set_target("/video.bin").unwrap();
+
+        write_temp("large/small.txt", b"small");
+        let mut small = HttpRequest::new();
+        small.set_target("/small.txt").unwrap();
+
+        // the FIFO is written once another file was served, or after a while when the
+        // read blocks the executor thread, its first poll completing the response then
+        let content = vec![7; 16 * 1024 * 1024];
+        let (served, wait_served) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn({
+            let (path, content) = (path.clone(), content.clone());
+            move || {
+                let waited = wait_served.recv_timeout(Duration::from_secs(5)).is_ok();
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .unwrap()
+                    .write_all(&content)
+                    .unwrap();
+                waited
+            }
+        });
+
+        let mut serving = std::pin::pin!(serve_blocking(root.clone(), req));
+        let pending = task::block_on(std::future::poll_fn(|cx| {
+            Poll::Ready(serving.as_mut().poll(cx).is_pending())
+        }));
+        assert!(pending);
+        // served while the large file is still being read
+        assert_eq!(task::block_on(serve_blocking(root, small)).body, b"small");
+        let _ = served.send(());
+        assert_eq!(task::block_on(serving).body, content);
+        assert!(
+            writer.join().unwrap(),
+            "the small file was served after the large one"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn gzip_sidecar() {
+        init_test_config();
+        let plain = write_temp("sidecar/app.js", b"console.log('plain')");
+        write_temp("sidecar/app.js.gz", b"gzipped bytes");
+        let plain = plain.to_str().unwrap();
+
+        let (file, encoding) = read_variant(plain, true).unwrap();
+        assert_eq!(encoding, Some("gzip"));
+        assert_eq!(file.body.as_slice(), b"gzipped bytes");
+
+        let (file, encoding) = read_variant(plain, false).unwrap();
+        assert_eq!(encoding, None);
+        assert_eq!(file.body.as_slice(), b"console.log('plain')");
+
+        // without a sidecar the plain file is served
+        let lone = write_temp("sidecar/lone.css", b"body {}");
+        let (file, encoding) = read_variant(lone.to_str().unwrap(), true).unwrap();
+        assert_eq!(encoding, None);
+        assert_eq!(file.body.as_slice(), b"body {}");
+    }
+
+    #[test]
+    fn directory_listings() {
+        init_test_config();
+        let file = write_temp("listing/docs/b.txt", b"12345");
+        write_temp("listing/docs/a <b>&\"c\".txt", b"");
+        write_temp("listing/docs/z/nested.txt", b"");
+        let docs = file.parent().unwrap().to_str().unwrap();
+        let root = file.parent().unwrap().parent().unwrap().to_str().unwrap();
+
+        let res = directory_listing(root, docs, "/docs/").unwrap();
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(
+            res.headers.get("Content-Type").unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            res.headers.get("Content-Length").unwrap(),
+            &res.body.len().to_string()
+        );
+
+        let body = String::from_utf8(res.body).unwrap();
+        assert!(body.contains("<title>Index of /docs/</title>"));
+        assert!(body.contains("<a href=\"../\">../</a>"));
+        assert!(body.contains("<a href=\"/docs/b.txt\">b.txt</a></td><td>5</td>"));
+        assert!(body.contains("<a href=\"/docs/z/\">z/</a></td><td>-</td>"));
+
+        // special characters never reach the markup unescaped
+        assert!(body.contains(
+            "<a href=\"/docs/a%20%3Cb%3E%26%22c%22.txt\">a &lt;b&gt;&amp;&quot;c&quot;.txt</a>"
+        ));
+        assert!(!body.contains("<b>"));
+
+        // sorted by name, directories first
+        let pos = |name: &str| body.find(name).unwrap();
+        assert!(pos("z/") < pos("a &lt;b") && pos("a &lt;b") < pos("b.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listing_stays_in_root() {
+        init_test_config();
+        let file = write_temp("listing-root/public/shown.txt", b"");
+        let secret = write_temp("listing-root/secret.txt", b"secret");
+        let public = file.parent().unwrap();
+        let link = public.join("escape.txt");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&secret, &link).unwrap();
+
+        let root = public.to_str().unwrap();
+        let res = directory_listing(root, root, "/").unwrap();
+        let body = String::from_utf8(res.body).unwrap();
+        assert!(body.contains("shown.txt"));
+        assert!(!body.contains("escape.txt"));
+        assert!(!body.contains("../"));
+    }
+
+    #[test]
+    fn encoded_file_names() {
+        init_test_config();
+        let path = write_temp("encoded/my file.txt", b"spaced");
+        write_temp("encoded/日本.txt", b"unicode");
+        let root = path.parent().unwrap().to_str().unwrap();
+
+        let mut req = HttpRequest::new();
+        req.set_target("/my%20file.txt").unwrap();
+        assert_eq!(serve_from(root, &req).body, b"spaced");
+
+        req.set_target("/%E6%97%A5%E6%9C%AC.txt").unwrap();
+        assert_eq!(serve_from(root, &req).body, b"unicode");
+
+        assert_eq!(
+            req.set_target("/my%zzfile.txt"),
+            Err(HttpStatus::BadRequest)
+        );
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!(
+            guess_mime("./static/index.html"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(guess_mime("./static/INDEX.HTM"), "text/html; charset=utf-8");
+        assert_eq!(guess_mime("./static/data.csv"), "text/csv; charset=utf-8");
+        assert_eq!(
+            guess_mime("./static/README.md"),
+            "text/markdown; charset=utf-8"
+        );
+        assert_eq!(guess_mime("./static/font.woff2"), "font/woff2");
+        assert_eq!(guess_mime("./static/clip.MP4"), "video/mp4");
+        assert_eq!(guess_mime("./static/clip.webm"), "video/webm");
+        assert_eq!(guess_mime("./static/photo.webp"), "image/webp");
+        assert_eq!(guess_mime("./static/app.wasm"), "application/wasm");
+        assert_eq!(guess_mime("./static/favicon.ico"), "image/x-icon");
+        assert_eq!(guess_mime("./static/Makefile"), "application/octet-stream");
+        assert_eq!(guess_mime("./static.d/archive"), "application/octet-stream");
+    }
+
+    #[test]
+    fn cache_control_by_extension() {
+        let by_extension = HashMap::from([
+            ("js".to_string(), "max-age=31536000, immutable".to_string()),
+            ("html".to_string(), "no-cache".to_string()),
+        ]);
+
+        assert_eq!(
+            cache_control("./static/app.3f2a.js", &by_extension, None),
+            Some("max-age=31536000, immutable")
+        );
+        assert_eq!(
+            cache_control("./static/index.HTML", &by_extension, None),
+            Some("no-cache")
+        );
+        assert_eq!(
+            cache_control("./static/logo.png", &by_extension, None),
+            None
+        );
+        assert_eq!(cache_control("./static/README", &by_extension, None), None);
+    }
+
+    #[test]
+    fn default_cache_control() {
+        let by_extension = HashMap::from([("html".to_string(), "no-cache".to_string())]);
+        let default = Some("public, max-age=3600");
+
+        assert_eq!(
+            cache_control("./static/logo.png", &by_extension, default),
+            default
+        );
+        assert_eq!(
+            cache_control("./static/README", &by_extension, default),
+            default
+        );
+        assert_eq!(
+            cache_control("./static/index.html", &by_extension, default),
+            Some("no-cache")
+        );
+        assert_eq!(
+            cache_control("./static/logo.png", &HashMap::new(), None),
+            None
+        );
+
+        // no Cache-Control is configured by default
+        init_test_config();
+        let path = write_temp("cache/logo.png", b"png");
+        let mut req = HttpRequest::new();
+        req.set_target("/logo.png").unwrap();
+        let res = serve_from(path.parent().unwrap().to_str().unwrap(), &req);
+        assert!(res.headers.get("Cache-Control").is_none());
+    }
+
+    #[test]
+    fn strong_etag_identical_content() {
+        let a = write_temp("etag-a.txt", b"same content");
+        let b = write_temp("etag-b.txt", b"same content");
+        let c = write_temp("etag-c.txt", b"other content");
+
+        assert_eq!(file_etag(&a, true), file_etag(&b, true));
+        assert_ne!(file_etag(&a, true), file_etag(&c, true));
+        assert!(!file_etag(&a, true).starts_with("W/"));
+    }
+
+    #[test]
+    fn weak_etag() {
+        let a = write_temp("etag-weak.txt", b"content");
+        assert!(file_etag(&a, false).starts_with("W/\""));
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert!(etag_matches("\"abc\"", "\"abc\""));
+        assert!(etag_matches("W/\"abc\"", "\"abc\""));
+        assert!(etag_matches("\"xyz\", \"abc\"", "\"abc\""));
+        assert!(etag_matches("*", "\"abc\""));
+        assert!(!etag_matches("\"xyz\"", "\"abc\""));
+        // the tag of the compressed response
+        assert!(etag_matches("W/\"abc-gzip\"", "W/\"abc\""));
+    }
+}
@@ -0,0 +1,191 @@
+//! Cookies set by responses through the `Set-Cookie` header (RFC 6265 section 4.1).
+//! The cookies sent by clients are read with [`HttpRequest::cookies`](crate::http::request::HttpRequest::cookies).
+
+use std::time::SystemTime;
+
+/// Value of the `SameSite` attribute of a cookie.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to set on the client, added to a response with
+/// [`HttpResponse::add_cookie`](crate::http::response::HttpResponse::add_cookie).
+///
+/// ```ignore
+/// let cookie = Cookie::new("session", "abc123")?.path("/").http_only(true);
+/// ```
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    /// Lifetime of the cookie in seconds, `0` removing it right away.
+    pub max_age: Option<u64>,
+    pub expires: Option<SystemTime>,
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+
+#[allow(dead_code)]
+impl Cookie {
+    /// Creates a session cookie without attributes.
+    ///
+    /// Returns `None` when the name is not a token or when the value contains
+    /// control characters, whitespace or one of `"`, `,`, `;` and `\`, which would
+    /// otherwise end the value early or inject attributes.
+    pub fn new(name: &str, value: &str) -> Option<Self> {
+        if !is_valid_name(name) || !is_valid_value(value) {
+            return None;
+        }
+        Some(Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        })
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn expires(mut self, time: SystemTime) -> Self {
+        self.expires = Some(time);
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Serializes the cookie as the value of a `Set-Cookie` header.
+    /// Path and domain attributes containing `;` or control characters are left out.
+    pub fn to_header_value(&self) -> String {
+        let mut value = format!("{}={}", self.name, self.value);
+        let attribute = |s: &str| !s.chars().any(|c| c == ';' || c.is_control());
+        if let Some(path) = self.path.as_deref().filter(|p| attribute(p)) {
+            value.push_str(&format!("; Path={path}"));
+        }
+        if let Some(domain) = self.domain.as_deref().filter(|d| attribute(d)) {
+            value.push_str(&format!("; Domain={domain}"));
+        }
+        if let Some(max_age) = self.max_age {
+            value.push_str(&format!("; Max-Age={max_age}"));
+        }
+        if let Some(expires) = self.expires {
+            value.push_str(&format!("; Expires={}", httpdate::fmt_http_date(expires)));
+        }
+        if self.http_only {
+            value.push_str("; HttpOnly");
+        }
+        if self.secure {
+            value.push_str("; Secure");
+        }
+        if let Some(same_site) = self.same_site {
+            value.push_str(&format!("; SameSite={}", same_site.as_str()));
+        }
+        value
+    }
+}
+
+/// Whether the name is a token (RFC 9110 section 5.6.2).
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Whether the value only holds cookie-octets (RFC 6265 section 4.1.1).
+fn is_valid_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b.is_ascii_graphic() && !matches!(b, b'"' | b',' | b';' | b'\\'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn minimal_cookie() {
+        let cookie = Cookie::new("session", "abc123").unwrap();
+        assert_eq!(cookie.to_header_value(), "session=abc123");
+        assert_eq!(Cookie::new("empty", "").unwrap().to_header_value(), "empty=");
+    }
+
+    #[test]
+    fn full_cookie() {
+        let cookie = Cookie::new("id", "a3fWa")
+            .unwrap()
+            .path("/docs")
+            .domain("example.com")
+            .max_age(3600)
+            .expires(UNIX_EPOCH + Duration::from_secs(1_445_412_480))
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_header_value(),
+            "id=a3fWa; Path=/docs; Domain=example.com; Max-Age=3600; \
+             Expires=Wed, 21 Oct 2015 07:28:00 GMT; HttpOnly; Secure; SameSite=Strict"
+        );
+    }
+
+    #[test]
+    fn invalid_cookies() {
+        assert!(Cookie::new("id", "a\r\nSet-Cookie: x=1").is_none());
+        assert!(Cookie::new("id", "a; Domain=evil.com").is_none());
+        assert!(Cookie::new("id", "a b").is_none());
+        assert!(Cookie::new("", "a").is_none());
+        assert!(Cookie::new("i=d", "a").is_none());
+
+        let cookie = Cookie::new("id", "a").unwrap().path("/; Secure").domain("example.com");
+        assert_eq!(cookie.to_header_value(), "id=a; Domain=example.com");
+    }
+}
@@ -8,7 +8,20 @@
 //! Both header names and values are stored as raw strings, without validation
 //! or restrictions on which headers are allowed.
 //!
-//! This abstraction does not enforce any HTTP semantics or constraints.
+//! Header names are case-insensitive (RFC 9110 section 5.1): `content-type` designates
+//! the same header as `Content-Type`, which is serialized with the case it was first
+//! stored with.
+//!
+//! A header name may hold several values (see [`HttpHeaders::append_raw`]).
+//! On serialization, headers that legitimately repeat such as `Set-Cookie`
+//! are emitted once per value, while the others are combined into a single
+//! comma-separated line as allowed by RFC 7230 section 3.2.2.
+//!
+//! CR and LF characters are stripped from names and values when they are stored,
+//! so a value echoed from a request can never inject extra header lines into a
+//! response (response splitting).
+//!
+//! Apart from that, this abstraction does not enforce any HTTP semantics or constraints.
 //! Higher-level types such as [`HttpRequest`](crate::http::request::HttpRequest)
 //! and [`HttpResponse`](crate::http::response::HttpResponse) are responsible for
 //! applying their own rules by wrapping or constraining access to this structure.
@@ -16,10 +29,58 @@
 //! When required, header values can be validated by the
 //! [`validator`](crate::http::validator) module.
 
-use indexmap::IndexMap;
+use indexmap::{Equivalent, IndexMap};
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+
+/// Headers whose values cannot be combined into a single line and must be
+/// serialized once per value.
+const REPEATABLE_HEADERS: [&str; 1] = ["Set-Cookie"];
 
+#[derive(Clone)]
 pub struct HttpHeaders {
-    headers: IndexMap<String, String>,
+    headers: IndexMap<HeaderName, Vec<String>>,
+}
+
+/// Header name compared and hashed regardless of its ASCII case, keeping its original case.
+#[derive(Clone, Debug)]
+struct HeaderName(String);
+
+/// Borrowed form of [`HeaderName`] used for lookups.
+struct HeaderNameRef<'a>(&'a str);
+
+fn hash_ignore_case<H: Hasher>(name: &str, state: &mut H) {
+    for b in name.bytes() {
+        state.write_u8(b.to_ascii_lowercase());
+    }
+    // same as str, so that the names are prefix-free
+    state.write_u8(0xff);
+}
+
+impl Hash for HeaderName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_ignore_case(&self.0, state);
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for HeaderName {}
+
+impl Hash for HeaderNameRef<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_ignore_case(self.0, state);
+    }
+}
+
+impl Equivalent<HeaderName> for HeaderNameRef<'_> {
+    fn equivalent(&self, key: &HeaderName) -> bool {
+        self.0.eq_ignore_ascii_case(&key.0)
+    }
 }
 
 impl HttpHeaders {
@@ -29,19 +90,154 @@ impl HttpHeaders {
         }
     }
 
+    /// Sets a header, replacing any value previously stored under the same name.
     pub fn set_raw(&mut self, name: &str, value: &str) {
-        self.headers.insert(name.to_string(), value.to_string());
+        self.headers
+            .insert(HeaderName(sanitize(name).into_owned()), vec![sanitize(value).into_owned()]);
+    }
+
+    /// Adds a value to a header, keeping the values previously stored under the same name.
+    pub fn append_raw(&mut self, name: &str, value: &str) {
+        self.headers
+            .entry(HeaderName(sanitize(name).into_owned()))
+            .or_default()
+            .push(sanitize(value).into_owned());
     }
 
+    /// Removes all the values stored for the header.
+    pub fn remove(&mut self, name: &str) {
+        self.headers.shift_remove(&HeaderNameRef(name));
+    }
+
+    /// Returns the first value stored for the header.
     pub fn get(&self, name: &str) -> Option<&String> {
-        self.headers.get(name)
+        self.headers.get(&HeaderNameRef(name)).and_then(|values| values.first())
+    }
+
+    /// Returns all the values stored for the header, in insertion order.
+    #[allow(dead_code)]
+    pub fn get_all(&self, name: &str) -> impl Iterator<Item = &String> {
+        self.headers.get(&HeaderNameRef(name)).into_iter().flatten()
+    }
+
+    /// Iterates over every `(name, value)` pair, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.headers
+            .iter()
+            .flat_map(|(name, values)| values.iter().map(move |value| (&name.0, value)))
     }
 
+    #[allow(dead_code)]
     pub fn stringify(&self) -> String {
+        self.stringify_limited(usize::MAX)
+    }
+
+    /// Same as [`HttpHeaders::stringify`] but emits at most `max_repeats` lines for each
+    /// of the headers serialized once per value, the values beyond being dropped.
+    pub fn stringify_limited(&self, max_repeats: usize) -> String {
         let mut result = String::new();
-        for (name, value) in &self.headers {
-            result.push_str(&format!("{}: {}\r\n", name, value));
+        for (HeaderName(name), values) in &self.headers {
+            if REPEATABLE_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+                for value in values.iter().take(max_repeats) {
+                    result.push_str(&format!("{}: {}\r\n", name, value));
+                }
+            } else {
+                result.push_str(&format!("{}: {}\r\n", name, values.join(", ")));
+            }
         }
         result
     }
 }
+
+/// Strips CR and LF characters which would otherwise end the header line.
+fn sanitize(s: &str) -> Cow<'_, str> {
+    if s.contains(['\r', '\n']) {
+        Cow::Owned(s.replace(['\r', '\n'], ""))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_raw_replaces_value() {
+        let mut headers = HttpHeaders::new();
+        headers.set_raw("Content-Type", "text/plain");
+        headers.set_raw("Content-Type", "text/html");
+        assert_eq!(headers.get("Content-Type").unwrap(), "text/html");
+        assert_eq!(headers.get_all("Content-Type").count(), 1);
+    }
+
+    #[test]
+    fn append_raw_keeps_all_values() {
+        let mut headers = HttpHeaders::new();
+        headers.append_raw("Set-Cookie", "a=1");
+        headers.append_raw("Set-Cookie", "b=2");
+        let values: Vec<&String> = headers.get_all("Set-Cookie").collect();
+        assert_eq!(values, ["a=1", "b=2"]);
+        assert_eq!(headers.get("Set-Cookie").unwrap(), "a=1");
+    }
+
+    #[test]
+    fn stringify_repeats_set_cookie() {
+        let mut headers = HttpHeaders::new();
+        headers.append_raw("Set-Cookie", "a=1");
+        headers.append_raw("Set-Cookie", "b=2");
+        assert_eq!(headers.stringify(), "Set-Cookie: a=1\r\nSet-Cookie: b=2\r\n");
+    }
+
+    #[test]
+    fn stringify_limits_repeats() {
+        let mut headers = HttpHeaders::new();
+        headers.append_raw("Vary", "Accept");
+        headers.append_raw("Vary", "Accept-Encoding");
+        for i in 0..5 {
+            headers.append_raw("Set-Cookie", &format!("c{i}=1"));
+        }
+        assert_eq!(
+            headers.stringify_limited(2),
+            "Vary: Accept, Accept-Encoding\r\nSet-Cookie: c0=1\r\nSet-Cookie: c1=1\r\n"
+        );
+    }
+
+    #[test]
+    fn stringify_combines_other_headers() {
+        let mut headers = HttpHeaders::new();
+        headers.append_raw("Vary", "Accept");
+        headers.append_raw("Vary", "Accept-Encoding");
+        assert_eq!(headers.stringify(), "Vary: Accept, Accept-Encoding\r\n");
+    }
+
+    #[test]
+    fn case_insensitive_names() {
+        let mut headers = HttpHeaders::new();
+        headers.set_raw("Content-Type", "text/plain");
+        assert_eq!(headers.get("content-type").unwrap(), "text/plain");
+        assert_eq!(headers.get("CONTENT-TYPE").unwrap(), "text/plain");
+
+        // the same header whatever the case, serialized with the case it was first set with
+        headers.set_raw("content-type", "text/html");
+        headers.append_raw("VARY", "Accept");
+        headers.append_raw("vary", "Accept-Encoding");
+        assert_eq!(headers.get_all("Content-Type").collect::<Vec<_>>(), ["text/html"]);
+        assert_eq!(headers.stringify(), "Content-Type: text/html\r\nVARY: Accept, Accept-Encoding\r\n");
+
+        headers.remove("CONTENT-type");
+        assert!(headers.get("Content-Type").is_none());
+        assert!(headers.get("Content-Typ").is_none());
+    }
+
+    #[test]
+    fn crlf_is_stripped() {
+        let mut headers = HttpHeaders::new();
+        headers.set_raw("Location", "/home\r\nSet-Cookie: evil=1");
+        headers.append_raw("X-Injected\r\n", "a\nb");
+        assert_eq!(
+            headers.stringify(),
+            "Location: /homeSet-Cookie: evil=1\r\nX-Injected: ab\r\n"
+        );
+    }
+}
@@ -1,7 +1,12 @@
 use serde::Deserialize;
 
+pub mod cookie;
 pub mod headers;
+pub mod multipart;
+pub mod negotiation;
 pub mod parser;
+pub mod percent;
+pub mod range;
 pub mod request;
 pub mod response;
 pub mod status;
@@ -35,7 +40,7 @@ impl HttpVersion {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum HttpMethod {
     Get,
     Head,
@@ -48,6 +53,22 @@ pub enum HttpMethod {
     Unknown,
 }
 
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
+            HttpMethod::Post => "POST",
+            HttpMethod::Put => "PUT",
+            HttpMethod::Delete => "DELETE",
+            HttpMethod::Connect => "CONNECT",
+            HttpMethod::Options => "OPTIONS",
+            HttpMethod::Trace => "TRACE",
+            HttpMethod::Unknown => "-",
+        }
+    }
+}
+
 pub fn http_method_from_str(method: &str) -> HttpMethod {
     match method {
         "GET" => HttpMethod::Get,
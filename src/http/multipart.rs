@@ -0,0 +1,448 @@
+/*!
+An incremental `multipart/form-data` parser (RFC 7578).
+
+The body can be fed in chunks of any size, each part being reported through
+[`MultipartEvent`]s: its headers once they are complete, then its content as soon
+as it is known not to contain the closing delimiter. Only the data which may be
+the start of a delimiter is held back between chunks, along with the headers of
+the current part, bounded by the `max_header_size` given to [`MultipartParser::new`].
+
+The server reads the whole body of a request, within
+[`max_body_size`](crate::config::ServerConfig::max_body_size), before handling it:
+uploads are not streamed to the handlers, which parse the parts from memory with
+[`collect`], as [`HttpRequest::multipart`](crate::http::request::HttpRequest::multipart) does.
+*/
+
+use crate::http::status::HttpStatus;
+
+/// Headers of a part, from its `Content-Disposition` and `Content-Type`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Part {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    /// All the headers of the part, in order
+    pub headers: Vec<(String, String)>,
+}
+
+/// A part of a body parsed by [`collect`], with its whole content.
+#[derive(Debug, PartialEq)]
+pub struct MultipartField {
+    pub part: Part,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MultipartEvent<'a> {
+    /// Headers of a new part, followed by its data.
+    PartStart(Part),
+    /// A piece of the content of the current part.
+    Data(&'a [u8]),
+    /// The current part is complete.
+    PartEnd,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MultipartError {
+    /// The body does not follow the multipart syntax.
+    Malformed,
+    /// The headers of a part exceed the configured limit.
+    HeadersTooLarge,
+    /// The body ended before the closing delimiter.
+    Incomplete,
+    /// The request is not `multipart/form-data`.
+    NotMultipart,
+    /// The `Content-Type` has no `boundary` parameter.
+    MissingBoundary,
+    /// The content of a part exceeds the configured limit.
+    PartTooLarge,
+    /// The content of all the parts exceeds the configured limit.
+    TooLarge,
+}
+
+#[allow(dead_code)]
+impl MultipartError {
+    pub fn into_http_status(self) -> HttpStatus {
+        match self {
+            MultipartError::NotMultipart => HttpStatus::UnsupportedMediaType,
+            MultipartError::PartTooLarge | MultipartError::TooLarge => HttpStatus::PayloadTooLarge,
+            _ => HttpStatus::BadRequest,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum MultipartState {
+    Preamble,
+    Headers,
+    Data,
+    Done,
+}
+
+pub struct MultipartParser {
+    /// `\r\n--<boundary>`, the leading CRLF being absent before the first part
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: MultipartState,
+    max_header_size: usize,
+}
+
+#[allow(dead_code)]
+impl MultipartParser {
+    pub fn new(boundary: &str, max_header_size: usize) -> Self {
+        Self {
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            // the preamble is considered to start with a CRLF so that a delimiter
+            // at the very beginning of the body is found like any other
+            buf: b"\r\n".to_vec(),
+            state: MultipartState::Preamble,
+            max_header_size,
+        }
+    }
+
+    /// Feeds a chunk of the body, reporting the parsed parts to `on_event`.
+    pub fn feed(
+        &mut self,
+        chunk: &[u8],
+        mut on_event: impl FnMut(MultipartEvent),
+    ) -> Result<(), MultipartError> {
+        if self.state == MultipartState::Done {
+            return Ok(()); // the epilogue is ignored
+        }
+        self.buf.extend_from_slice(chunk);
+
+        loop {
+            let progressed = match self.state {
+                MultipartState::Preamble => self.parse_preamble()?,
+                MultipartState::Headers => self.parse_headers(&mut on_event)?,
+                MultipartState::Data => self.parse_data(&mut on_event)?,
+                MultipartState::Done => return Ok(()),
+            };
+            if !progressed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Checks that the body was complete once it has been entirely fed.
+    pub fn finish(&self) -> Result<(), MultipartError> {
+        match self.state {
+            MultipartState::Done => Ok(()),
+            _ => Err(MultipartError::Incomplete),
+        }
+    }
+
+    fn find(&self, pattern: &[u8]) -> Option<usize> {
+        self.buf.windows(pattern.len()).position(|w| w == pattern)
+    }
+
+    /// Consumes a delimiter found at `idx` along with the CRLF or `--` following it.
+    /// Returns `false` when these two bytes are not received yet.
+    fn consume_delimiter(&mut self, idx: usize) -> Result<bool, MultipartError> {
+        let end = idx + self.delimiter.len();
+        match self.buf.get(end..end + 2) {
+            None => Ok(false),
+            Some(b"\r\n") => {
+                self.buf.drain(..end + 2);
+                self.state = MultipartState::Headers;
+                Ok(true)
+            }
+            Some(b"--") => {
+                self.buf.clear();
+                self.state = MultipartState::Done;
+                Ok(true)
+            }
+            Some(_) => Err(MultipartError::Malformed),
+        }
+    }
+
+    fn parse_preamble(&mut self) -> Result<bool, MultipartError> {
+        match self.find(&self.delimiter) {
+            Some(idx) => self.consume_delimiter(idx),
+            None => {
+                // keep what may be the start of the delimiter
+                let keep = self.delimiter.len() - 1;
+                if self.buf.len() > keep {
+                    self.buf.drain(..self.buf.len() - keep);
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    fn parse_headers(
+        &mut self,
+        on_event: &mut impl FnMut(MultipartEvent),
+    ) -> Result<bool, MultipartError> {
+        let Some(end) = self.find(b"\r\n\r\n") else {
+            if self.buf.len() > self.max_header_size {
+                return Err(MultipartError::HeadersTooLarge);
+            }
+            return Ok(false);
+        };
+        if end > self.max_header_size {
+            return Err(MultipartError::HeadersTooLarge);
+        }
+
+        let headers = std::str::from_utf8(&self.buf[..end]).map_err(|_| MultipartError::Malformed)?;
+        let mut part = Part::default();
+        for line in headers.split("\r\n").filter(|l| !l.is_empty()) {
+            let (name, value) = line.split_once(':').ok_or(MultipartError::Malformed)?;
+            let (name, value) = (name.trim(), value.trim());
+            part.headers.push((name.to_string(), value.to_string()));
+            if name.eq_ignore_ascii_case("content-disposition") {
+                part.name = disposition_param(value, "name");
+                part.filename = disposition_param(value, "filename");
+            } else if name.eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.to_string());
+            }
+        }
+
+        self.buf.drain(..end + 4);
+        self.state = MultipartState::Data;
+        on_event(MultipartEvent::PartStart(part));
+        Ok(true)
+    }
+
+    fn parse_data(
+        &mut self,
+        on_event: &mut impl FnMut(MultipartEvent),
+    ) -> Result<bool, MultipartError> {
+        match self.find(&self.delimiter) {
+            Some(idx) => {
+                // the data is reported once, before the delimiter is consumed
+                if idx > 0 {
+                    on_event(MultipartEvent::Data(&self.buf[..idx]));
+                    self.buf.drain(..idx);
+                }
+                let consumed = self.consume_delimiter(0)?;
+                if consumed {
+                    on_event(MultipartEvent::PartEnd);
+                }
+                Ok(consumed)
+            }
+            None => {
+                // the end of the buffer may be the start of the delimiter
+                let safe = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+                if safe > 0 {
+                    on_event(MultipartEvent::Data(&self.buf[..safe]));
+                    self.buf.drain(..safe);
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Parses a whole body into its parts, the content of each being bounded by `max_part_size`
+/// and the content of all of them by `max_size`.
+#[allow(dead_code)]
+pub fn collect(
+    body: &[u8],
+    boundary: &str,
+    max_header_size: usize,
+    max_part_size: usize,
+    max_size: usize,
+) -> Result<Vec<MultipartField>, MultipartError> {
+    let mut parser = MultipartParser::new(boundary, max_header_size);
+    let mut fields: Vec<MultipartField> = Vec::new();
+    let mut size = 0;
+    let mut limit_error = None;
+
+    parser.feed(body, |event| match event {
+        MultipartEvent::PartStart(part) => fields.push(MultipartField { part, data: Vec::new() }),
+        MultipartEvent::Data(data) if limit_error.is_none() => {
+            // a part being reported before its data, the last field is the current one
+            let field = fields.last_mut().unwrap();
+            size += data.len();
+            if field.data.len() + data.len() > max_part_size {
+                limit_error = Some(MultipartError::PartTooLarge);
+            } else if size > max_size {
+                limit_error = Some(MultipartError::TooLarge);
+            } else {
+                field.data.extend_from_slice(data);
+            }
+        }
+        _ => (),
+    })?;
+
+    if let Some(err) = limit_error {
+        return Err(err);
+    }
+    parser.finish()?;
+    Ok(fields)
+}
+
+/// Extracts the `boundary` parameter of a `multipart/form-data` content type.
+#[allow(dead_code)]
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    let essence = params.next()?.trim();
+    if !essence.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params
+        .filter_map(|p| p.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .filter(|b| !b.is_empty())
+}
+
+/// Extracts a parameter of a `Content-Disposition` value, such as `name="field"`.
+fn disposition_param(value: &str, param: &str) -> Option<String> {
+    value
+        .split(';')
+        .skip(1)
+        .filter_map(|p| p.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(param))
+        .map(|(_, v)| v.trim().trim_matches('"').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Multipart events with owned data to be compared after parsing.
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Start(Part),
+        Data(Vec<u8>),
+        End,
+    }
+
+    fn parse_in_chunks(body: &[u8], chunk_size: usize) -> Result<Vec<Event>, MultipartError> {
+        let mut parser = MultipartParser::new("XyZ", 1024);
+        let mut events = Vec::new();
+        for chunk in body.chunks(chunk_size) {
+            parser.feed(chunk, |e| {
+                events.push(match e {
+                    MultipartEvent::PartStart(part) => Event::Start(part),
+                    MultipartEvent::Data(data) => Event::Data(data.to_vec()),
+                    MultipartEvent::PartEnd => Event::End,
+                })
+            })?;
+        }
+        parser.finish()?;
+        Ok(events)
+    }
+
+    const TWO_FILES: &[u8] = b"preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"first\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        first file content\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"second\"; filename=\"b.bin\"\r\n\
+        \r\n\
+        second\r\n-- not a delimiter\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    #[test]
+    fn files_in_chunks() {
+        let events = parse_in_chunks(TWO_FILES, 7).unwrap();
+
+        // each file is received incrementally, in several pieces
+        let mut files: Vec<(Part, Vec<Vec<u8>>)> = Vec::new();
+        for event in events {
+            match event {
+                Event::Start(part) => files.push((part, Vec::new())),
+                Event::Data(data) => files.last_mut().unwrap().1.push(data),
+                Event::End => (),
+            }
+        }
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0.name.as_deref(), Some("first"));
+        assert_eq!(files[0].0.filename.as_deref(), Some("a.txt"));
+        assert_eq!(files[0].0.content_type.as_deref(), Some("text/plain"));
+        assert!(files[0].1.len() > 1);
+        assert_eq!(files[0].1.concat(), b"first file content");
+
+        assert_eq!(files[1].0.filename.as_deref(), Some("b.bin"));
+        assert_eq!(files[1].0.content_type, None);
+        assert!(files[1].1.len() > 1);
+        assert_eq!(files[1].1.concat(), b"second\r\n-- not a delimiter");
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        let whole = parse_in_chunks(TWO_FILES, TWO_FILES.len()).unwrap();
+        assert_eq!(whole.len(), 6);
+        assert_eq!(whole[1], Event::Data(b"first file content".to_vec()));
+        assert_eq!(whole[2], Event::End);
+
+        let bytewise = parse_in_chunks(TWO_FILES, 1).unwrap();
+        assert_eq!(bytewise.iter().filter(|e| **e == Event::End).count(), 2);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_in_chunks(b"--XyZ\r\nContent-Disposition: form-data\r\n\r\ntruncated", 4),
+            Err(MultipartError::Incomplete)
+        );
+        assert_eq!(parse_in_chunks(b"--XyZ\r\nno colon\r\n\r\n", 4), Err(MultipartError::Malformed));
+
+        let long_header = format!("--XyZ\r\nX-Long: {}\r\n\r\n--XyZ--", "a".repeat(2048));
+        assert_eq!(
+            parse_in_chunks(long_header.as_bytes(), 64),
+            Err(MultipartError::HeadersTooLarge)
+        );
+    }
+
+    const TEXT_AND_FILE: &[u8] = b"--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Holiday pictures\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"photo\"; filename=\"beach.png\"\r\n\
+        Content-Type: image/png\r\n\
+        \r\n\
+        \x89PNG\r\n\x1a\n\r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn collected_fields() {
+        let fields = collect(TEXT_AND_FILE, "XyZ", 1024, 1024, 1024).unwrap();
+        assert_eq!(fields.len(), 2);
+
+        assert_eq!(fields[0].part.name.as_deref(), Some("title"));
+        assert_eq!(fields[0].part.filename, None);
+        assert_eq!(fields[0].data, b"Holiday pictures");
+
+        assert_eq!(fields[1].part.name.as_deref(), Some("photo"));
+        assert_eq!(fields[1].part.filename.as_deref(), Some("beach.png"));
+        assert_eq!(
+            fields[1].part.headers,
+            [
+                ("Content-Disposition", "form-data; name=\"photo\"; filename=\"beach.png\""),
+                ("Content-Type", "image/png"),
+            ]
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+        );
+        assert_eq!(fields[1].data, b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[test]
+    fn collected_size_limits() {
+        // "Holiday pictures" is the largest part, with 16 bytes
+        assert!(collect(TEXT_AND_FILE, "XyZ", 1024, 16, 1024).is_ok());
+        assert_eq!(collect(TEXT_AND_FILE, "XyZ", 1024, 15, 1024), Err(MultipartError::PartTooLarge));
+        assert_eq!(collect(TEXT_AND_FILE, "XyZ", 1024, 16, 20), Err(MultipartError::TooLarge));
+        assert_eq!(MultipartError::TooLarge.into_http_status(), HttpStatus::PayloadTooLarge);
+
+        let truncated = &TEXT_AND_FILE[..TEXT_AND_FILE.len() - 10];
+        assert_eq!(collect(truncated, "XyZ", 1024, 1024, 1024), Err(MultipartError::Incomplete));
+    }
+
+    #[test]
+    fn boundary_parameter() {
+        assert_eq!(boundary("multipart/form-data; boundary=XyZ"), Some("XyZ"));
+        assert_eq!(boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\""), Some("a b"));
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("text/plain; boundary=XyZ"), None);
+    }
+}
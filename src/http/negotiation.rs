@@ -0,0 +1,159 @@
+//! Content negotiation helpers.
+//!
+//! Parses the quality lists sent by clients in headers such as `Accept`
+//! and selects the preferred representation among the ones offered by a handler,
+//! following RFC 7231 section 5.3.
+
+use crate::http::status::HttpStatus;
+
+/// Parses a comma-separated list of `value;q=weight` items.
+/// Items without a `q` parameter have a weight of 1, and malformed weights are treated as 0.
+pub fn parse_quality_list(header: &str) -> Vec<(&str, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let value = params.next()?.trim();
+            if value.is_empty() {
+                return None;
+            }
+
+            let q = params
+                .filter_map(|p| p.trim().split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map(|(_, q)| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            Some((value, q))
+        })
+        .collect()
+}
+
+/// Returns the weight given by the `Accept` media ranges to a media type.
+/// The most specific matching range wins (`type/subtype` over `type/*` over `*/*`).
+fn media_type_quality(ranges: &[(&str, f32)], media_type: &str) -> f32 {
+    // parameters such as charset do not take part in the matching
+    let essence = media_type.split(';').next().unwrap_or("").trim();
+    let (ty, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+
+    ranges
+        .iter()
+        .filter_map(|&(range, q)| {
+            let (range_ty, range_subtype) = range.split_once('/')?;
+            let specificity = match (range_ty, range_subtype) {
+                ("*", "*") => 0,
+                (t, "*") if t.eq_ignore_ascii_case(ty) => 1,
+                (t, s) if t.eq_ignore_ascii_case(ty) && s.eq_ignore_ascii_case(subtype) => 2,
+                _ => return None,
+            };
+            Some((specificity, q))
+        })
+        .max_by_key(|&(specificity, _)| specificity)
+        .map(|(_, q)| q)
+        .unwrap_or(0.0)
+}
+
+/// Selects the offered media type preferred by the `Accept` header value,
+/// or `None` if none of them is acceptable.
+pub fn select_media_type<'a>(accept: &str, offered: &[&'a str]) -> Option<&'a str> {
+    let ranges = parse_quality_list(accept);
+    offered
+        .iter()
+        .map(|&media_type| (media_type, media_type_quality(&ranges, media_type)))
+        .filter(|&(_, q)| q > 0.0)
+        // keep the first offered type among equally weighted ones
+        .fold(None, |best: Option<(&str, f32)>, (media_type, q)| match best {
+            Some((_, best_q)) if best_q >= q => best,
+            _ => Some((media_type, q)),
+        })
+        .map(|(media_type, _)| media_type)
+}
+
+/// Checks whether a content coding (e.g. `gzip`) is acceptable according to the
+/// `Accept-Encoding` header value. An explicit weight for the coding takes precedence
+/// over the `*` wildcard, and codings which are not listed are not acceptable.
+pub fn accepts_encoding(accept_encoding: &str, coding: &str) -> bool {
+    let codings = parse_quality_list(accept_encoding);
+    let quality = |name: &str| {
+        codings
+            .iter()
+            .find(|(c, _)| c.eq_ignore_ascii_case(name))
+            .map(|&(_, q)| q)
+    };
+
+    quality(coding).or_else(|| quality("*")).is_some_and(|q| q > 0.0)
+}
+
+/// Negotiates the media type of a response among the `offered` ones.
+///
+/// Without an `Accept` header, the first offered type is used. When no offered
+/// type is acceptable, `strict` mode fails with `406 Not Acceptable` whereas the
+/// lenient mode ignores the header and falls back to the first offered type.
+/// (see [`strict_accept`](crate::config::ServerConfig::strict_accept))
+pub fn negotiate<'a>(
+    accept: Option<&str>,
+    offered: &[&'a str],
+    strict: bool,
+) -> Result<&'a str, HttpStatus> {
+    let fallback = offered.first().copied().ok_or(HttpStatus::NotAcceptable)?;
+    let Some(accept) = accept else {
+        return Ok(fallback);
+    };
+
+    match select_media_type(accept, offered) {
+        Some(media_type) => Ok(media_type),
+        None if strict => Err(HttpStatus::NotAcceptable),
+        None => Ok(fallback),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quality_list() {
+        let list = parse_quality_list("text/html, application/json;q=0.5, */*; q=0");
+        assert_eq!(list, [("text/html", 1.0), ("application/json", 0.5), ("*/*", 0.0)]);
+    }
+
+    #[test]
+    fn select_preferred_type() {
+        let offered = ["text/html", "application/json"];
+        assert_eq!(select_media_type("application/json", &offered), Some("application/json"));
+        assert_eq!(
+            select_media_type("text/html;q=0.5, application/json", &offered),
+            Some("application/json")
+        );
+        assert_eq!(select_media_type("text/*", &offered), Some("text/html"));
+        assert_eq!(select_media_type("*/*", &offered), Some("text/html"));
+        assert_eq!(select_media_type("application/xml", &offered), None);
+    }
+
+    #[test]
+    fn specific_range_overrides_wildcard() {
+        let offered = ["text/html"];
+        assert_eq!(select_media_type("text/html;q=0, */*", &offered), None);
+    }
+
+    #[test]
+    fn accepted_encodings() {
+        assert!(accepts_encoding("gzip, deflate", "gzip"));
+        assert!(accepts_encoding("GZIP;q=0.5", "gzip"));
+        assert!(accepts_encoding("*", "gzip"));
+        assert!(!accepts_encoding("identity;q=1, gzip;q=0", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0, *", "gzip"));
+        assert!(!accepts_encoding("br", "gzip"));
+    }
+
+    #[test]
+    fn unsatisfiable_accept() {
+        let offered = ["text/html", "application/json"];
+        assert_eq!(
+            negotiate(Some("application/xml"), &offered, true),
+            Err(HttpStatus::NotAcceptable)
+        );
+        assert_eq!(negotiate(Some("application/xml"), &offered, false), Ok("text/html"));
+        assert_eq!(negotiate(None, &offered, true), Ok("text/html"));
+    }
+}
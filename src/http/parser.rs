@@ -12,6 +12,11 @@ field can be parsed, except for the request line, which must fit entirely
 within the parsing buffer. By contrast, body data is appended directly
 to the request body as it is received.
 
+Parsed bytes are consumed by moving a cursor rather than by shifting the
+rest of the buffer, which is only compacted when new data does not fit
+after the unconsumed bytes, so that each byte is moved at most once per
+buffer capacity received.
+
 
 The [`ParserOk::Incomplete`] state is used to signal the server to
 continue reading packets in order to complete the current field.
@@ -22,14 +27,15 @@ caller: the [`ParserOk::HeadersDone`] state allows the server to pause
 parsing and validate the headers before body parsing begins.
 */
 
-use crate::config::config;
+use std::sync::Arc;
+
+use crate::config::{ServerConfig, config_or_default};
 use crate::http::request::*;
 use crate::http::status::HttpStatus;
 use crate::http::*;
 
-/// Capacity of the internal parser buffer.
-/// Its value should be the same as the server read [`buffer capacity`](crate::config::ServerConfig::buffer_size)
-const PARSER_BUF_CAP: usize = 4096;
+/// Maximum number of empty lines ignored before the request line (RFC 9112 section 2.2)
+const MAX_LEADING_EMPTY_LINES: usize = 8;
 
 /// The finite states of the parser.
 /// They are given sequentially as a pipeline
@@ -42,14 +48,48 @@ enum ParserState {
     Done,
 }
 
+/// Progress within a body framed with the chunked transfer coding (RFC 9112 section 7.1).
+#[derive(PartialEq, Clone, Copy)]
+enum ChunkState {
+    /// Expecting the line giving the size of the next chunk
+    Size,
+    /// Copying the bytes left in the current chunk
+    Data(usize),
+    /// Expecting the CRLF ending the current chunk
+    DataEnd,
+    /// Skipping the trailer section following the last chunk
+    Trailers,
+}
+
 /// The HTTP request parser.
 pub struct Parser {
-    buf: [u8; PARSER_BUF_CAP],
+    /// Internal buffer, whose capacity is the server read [`buffer capacity`](crate::config::ServerConfig::buffer_size)
+    buf: Vec<u8>,
+    /// Start of the bytes not consumed yet
+    pos: usize,
+    /// End of the bytes received
     buf_len: usize,
     state: ParserState,
+    chunk: ChunkState,
+
+    /// Snapshot of the configuration giving the limits of the request
+    cfg: Arc<ServerConfig>,
+
+    /// Number of bytes moved by the compactions of the buffer
+    #[cfg(test)]
+    moved: usize,
 
     /// helper to track the global headers size and apply the [`server limit`](crate::config::ServerConfig::max_header_size)
     headers_bytes_parsed: usize,
+
+    /// helper to track the number of header lines and apply the [`server limit`](crate::config::ServerConfig::max_header_count)
+    headers_count: usize,
+
+    /// helper to bound the empty lines skipped before the request line to [`MAX_LEADING_EMPTY_LINES`]
+    leading_empty_lines: usize,
+
+    /// set when the `Host` is given by an absolute-form target, the `Host` header being then ignored
+    host_from_target: bool,
 }
 
 /// Informative parsing outcomes.
@@ -68,124 +108,338 @@ pub enum ParserOk {
     Done,
 }
 
+/// Form of the request target, see [`Parser::parse_target`].
+enum RequestTarget<'a> {
+    /// Target without authority
+    Origin,
+    /// Target of a proxy request, whose authority gives the host
+    Absolute { authority: &'a str },
+}
+
 /// Syntaxic parsing errors.
+/// The variants carry the offending field, when there is one, to be logged.
 #[derive(PartialEq, Debug)]
 pub enum ParserError {
-    Error,
+    /// Request line which is not made of a method, a target and a version,
+    /// preceded by too many empty lines or exceeding the
+    /// [`server limit`](crate::config::ServerConfig::max_request_line_size)
+    MalformedRequestLine,
+
+    /// Method which is not a token
+    InvalidMethod(String),
+
+    /// Well-formed method token which is not a method known by the server
+    UnknownMethod(String),
+
+    /// Target whose form is not allowed for the method, or whose path is invalid
+    InvalidTarget(String),
 
     /// Limit can be found in the server [`config`](crate::config::ServerConfig::max_uri_size)
     TooLongUri,
+
+    /// Version which is not `HTTP/<major>.<minor>`
+    InvalidVersion(String),
+
+    /// Request line whose tokens are not separated by exactly one space,
+    /// e.g. with tabs, consecutive spaces or leading/trailing spaces
+    RequestLineWhitespace,
+
+    /// Header line starting with whitespace, continuing the previous one
+    /// with the obsolete line folding (RFC 9112 section 5.2)
+    ObsoleteLineFolding,
+
+    /// Header line without a colon separating the name from the value
+    MalformedHeaderLine(String),
+
+    /// Header name which is not a token
+    InvalidHeaderName(String),
+
+    /// Value with control characters, or empty for a framing header, given with the header name
+    InvalidHeaderValue(String),
+
+    /// `Content-Length` which is not a number of bytes
+    InvalidContentLength(String),
+
+    /// Chunk size line which is not a hexadecimal size, or chunk not followed by a CRLF
+    MalformedChunk(String),
+
+    /// Headers exceeding the [`size`](crate::config::ServerConfig::max_header_size)
+    /// or [`count`](crate::config::ServerConfig::max_header_count) limits of the server
+    HeadersTooLarge,
+
+    /// Body exceeding the [`server limit`](crate::config::ServerConfig::max_body_size)
+    BodyTooLarge,
+
+    /// Data exceeding the free [`capacity`](crate::config::ServerConfig::buffer_size) of the buffer
+    BufferFull,
 }
 
 impl ParserError {
     pub fn into_http_status(self) -> HttpStatus {
         match self {
-            ParserError::Error => HttpStatus::BadRequest,
+            ParserError::MalformedRequestLine => HttpStatus::BadRequest,
+            ParserError::InvalidMethod(_) => HttpStatus::BadRequest,
+            ParserError::UnknownMethod(_) => HttpStatus::NotImplemented,
+            ParserError::InvalidTarget(_) => HttpStatus::BadRequest,
             ParserError::TooLongUri => HttpStatus::UriTooLong,
+            ParserError::InvalidVersion(_) => HttpStatus::BadRequest,
+            ParserError::RequestLineWhitespace => HttpStatus::BadRequest,
+            ParserError::ObsoleteLineFolding => HttpStatus::BadRequest,
+            ParserError::MalformedHeaderLine(_) => HttpStatus::BadRequest,
+            ParserError::InvalidHeaderName(_) => HttpStatus::BadRequest,
+            ParserError::InvalidHeaderValue(_) => HttpStatus::BadRequest,
+            ParserError::InvalidContentLength(_) => HttpStatus::BadRequest,
+            ParserError::MalformedChunk(_) => HttpStatus::BadRequest,
+            ParserError::HeadersTooLarge => HttpStatus::RequestHeaderFieldsTooLarge,
+            ParserError::BodyTooLarge => HttpStatus::PayloadTooLarge,
+            ParserError::BufferFull => HttpStatus::BadRequest,
         }
     }
 }
 
+impl std::fmt::Display for ParserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParserError::MalformedRequestLine => write!(f, "malformed request line"),
+            ParserError::InvalidMethod(method) => write!(f, "invalid method {method:?}"),
+            ParserError::UnknownMethod(method) => write!(f, "unknown method {method:?}"),
+            ParserError::InvalidTarget(target) => write!(f, "invalid request target {target:?}"),
+            ParserError::TooLongUri => write!(f, "request target too long"),
+            ParserError::InvalidVersion(version) => write!(f, "invalid HTTP version {version:?}"),
+            ParserError::RequestLineWhitespace => write!(f, "invalid whitespace in the request line"),
+            ParserError::ObsoleteLineFolding => write!(f, "obsolete header line folding"),
+            ParserError::MalformedHeaderLine(line) => write!(f, "malformed header line {line:?}"),
+            ParserError::InvalidHeaderName(name) => write!(f, "invalid header name {name:?}"),
+            ParserError::InvalidHeaderValue(name) => write!(f, "invalid value of the header {name}"),
+            ParserError::InvalidContentLength(value) => write!(f, "invalid Content-Length {value:?}"),
+            ParserError::MalformedChunk(line) => write!(f, "malformed chunk {line:?}"),
+            ParserError::HeadersTooLarge => write!(f, "headers too large"),
+            ParserError::BodyTooLarge => write!(f, "body too large"),
+            ParserError::BufferFull => write!(f, "parser buffer full"),
+        }
+    }
+}
+
+/// Lossy conversion of a field for the error reporting it.
+fn field(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
 impl Parser {
+    /// Creates a parser applying the limits of the current configuration.
+    #[allow(dead_code)]
     pub fn new() -> Self {
+        Self::with_config(config_or_default())
+    }
+
+    /// Creates a parser applying the limits of the given configuration,
+    /// its buffer holding [`buffer_size`](ServerConfig::buffer_size) bytes.
+    pub fn with_config(cfg: Arc<ServerConfig>) -> Self {
         Self {
-            buf: [0; PARSER_BUF_CAP],
+            buf: vec![0; cfg.buffer_size],
+            pos: 0,
             buf_len: 0,
             state: ParserState::RequestLine,
+            chunk: ChunkState::Size,
+            cfg,
+            #[cfg(test)]
+            moved: 0,
             headers_bytes_parsed: 0,
+            headers_count: 0,
+            leading_empty_lines: 0,
+            host_from_target: false,
         }
     }
 
-    // After headers are parsed, the parser stop feeding new data until the server
-    // validates the request and resumes parsing.
-    // Once resumed, the internal buffer should be completely consumed before reading more data.
-    pub fn is_buffer_empty(&self) -> bool {
-        self.buf_len == 0
+    /// Number of bytes received but not yet consumed by the parser.
+    pub fn buffered(&self) -> usize {
+        self.buf_len - self.pos
     }
 
-    /// helper to find a byte pattern in the internal buffer like crlf delimiters
+    /// Number of bytes which can still be fed to the parser, see [`ParserError::BufferFull`].
+    pub fn free_capacity(&self) -> usize {
+        self.buf.len() - self.buffered()
+    }
+
+    /// helper to find a byte pattern in the unconsumed bytes like crlf delimiters,
+    /// returning its position relative to the first of them
     fn find_delimiter(&self, pattern: &[u8]) -> Option<usize> {
-        self.buf[..self.buf_len]
+        self.buf[self.pos..self.buf_len]
             .windows(pattern.len())
             .position(|window| window == pattern)
     }
 
+    /// Marks the next `n` unconsumed bytes as parsed.
+    fn consume(&mut self, n: usize) {
+        self.pos += n;
+        if self.pos == self.buf_len {
+            self.pos = 0;
+            self.buf_len = 0;
+        }
+    }
+
+    /// Error of a request line exceeding its limit, possibly received only in part:
+    /// [`ParserError::TooLongUri`] when it starts with a method token followed by
+    /// a target longer than [`max_uri_size`](crate::config::ServerConfig::max_uri_size),
+    /// [`ParserError::MalformedRequestLine`] otherwise.
+    fn too_long_line_error(&self, line: &[u8]) -> ParserError {
+        let Some(method_end) = line.iter().position(|&b| b == b' ') else {
+            return ParserError::MalformedRequestLine;
+        };
+        let rest = &line[method_end + 1..];
+        let uri_len = rest.iter().position(|&b| b == b' ').unwrap_or(rest.len());
+        if is_token(&line[..method_end]) && uri_len > self.cfg.max_uri_size {
+            ParserError::TooLongUri
+        } else {
+            ParserError::MalformedRequestLine
+        }
+    }
+
+    /// Parses the request line, whose length without the terminating CRLF is limited to
+    /// [`max_request_line_size`](crate::config::ServerConfig::max_request_line_size).
+    /// The limit is enforced as soon as the received part of the line exceeds it,
+    /// a trailing CR possibly starting the CRLF not being counted.
     fn parse_request_line(&mut self, req: &mut HttpRequest) -> Result<ParserOk, ParserError> {
+        self.skip_leading_empty_lines()?;
+
+        let max_request_line_size = self.cfg.max_request_line_size;
         let end = self.find_delimiter(b"\r\n");
 
         let request_line_end = match end {
             Some(idx) => idx,
             None => {
-                if self.buf_len > config().max_request_line_size {
-                    return Err(ParserError::Error);
+                let received = &self.buf[self.pos..self.buf_len];
+                let line = received.strip_suffix(b"\r").unwrap_or(received);
+                if line.len() > max_request_line_size {
+                    return Err(self.too_long_line_error(line));
                 }
 
                 return Ok(ParserOk::Incomplete);
             }
         };
 
-        if request_line_end > config().max_request_line_size {
-            return Err(ParserError::Error);
+        if request_line_end > max_request_line_size {
+            let line = &self.buf[self.pos..self.pos + request_line_end];
+            return Err(self.too_long_line_error(line));
         }
 
-        // Request line: METHOD uri HTTP/VERSION
-        let request_line = &self.buf[..request_line_end];
+        // Request line: METHOD uri HTTP/VERSION, with exactly one SP between the tokens
+        // as the lenient parsing of other whitespace allowed by RFC 9112 section 3
+        // can be interpreted differently by intermediaries
+        let request_line = &self.buf[self.pos..self.pos + request_line_end];
+        if request_line.contains(&b'\t')
+            || request_line.starts_with(b" ")
+            || request_line.ends_with(b" ")
+            || request_line.windows(2).any(|w| w == b"  ")
+        {
+            return Err(ParserError::RequestLineWhitespace);
+        }
         let parts: Vec<&[u8]> = request_line.split(|&b| b == b' ').collect();
         if parts.len() != 3 {
-            return Err(ParserError::Error);
+            return Err(ParserError::MalformedRequestLine);
         }
 
-        let method = std::str::from_utf8(parts[0]).map_err(|_| ParserError::Error)?;
-
-        let method_enum = match http_method_from_str(method) {
-            HttpMethod::Unknown => return Err(ParserError::Error),
-            m => m,
-        };
+        // a token is ASCII and thus valid UTF-8
+        if !is_token(parts[0]) {
+            return Err(ParserError::InvalidMethod(field(parts[0])));
+        }
+        let method = std::str::from_utf8(parts[0]).unwrap();
+        let method_enum = http_method_from_str(method);
 
-        let uri = std::str::from_utf8(parts[1]).map_err(|_| ParserError::Error)?;
-        if uri.len() > config().max_uri_size {
+        let uri = std::str::from_utf8(parts[1]).map_err(|_| ParserError::InvalidTarget(field(parts[1])))?;
+        if uri.len() > self.cfg.max_uri_size {
             return Err(ParserError::TooLongUri);
         }
+        let target = Self::parse_target(uri, method_enum)?;
 
-        let version = std::str::from_utf8(parts[2]).map_err(|_| ParserError::Error)?;
-        let http_version = version
-            .strip_prefix("HTTP/")
+        let invalid_version = || ParserError::InvalidVersion(field(parts[2]));
+        let (maj, min) = std::str::from_utf8(parts[2])
+            .ok()
+            .and_then(|v| v.strip_prefix("HTTP/"))
             .and_then(|v| v.split_once('.'))
-            .ok_or(ParserError::Error)?;
+            .ok_or_else(invalid_version)?;
+        let maj: u8 = maj.parse().map_err(|_| invalid_version())?;
+        let min: u8 = min.parse().map_err(|_| invalid_version())?;
 
-        let (maj, min) = http_version;
-        let maj: u8 = maj.parse().map_err(|_| ParserError::Error)?;
-        let min: u8 = min.parse().map_err(|_| ParserError::Error)?;
+        // reported only once the whole line is known to be well-formed
+        if method_enum == HttpMethod::Unknown {
+            return Err(ParserError::UnknownMethod(method.to_string()));
+        }
 
         req.method = method_enum;
-        req.uri = uri.to_string();
         req.http_version = (maj, min);
-
-        let consume = request_line_end + 2;
-        let remaining = self.buf_len - consume;
+        req.set_target(uri).map_err(|_| ParserError::InvalidTarget(uri.to_string()))?;
+        if let RequestTarget::Absolute { authority } = target {
+            req.set_header(RequestHeader::Host, authority);
+            self.host_from_target = true;
+        }
 
         // Successfully parsed request line
-        // Update parser state and remove parsed line from bufs
+        // Update parser state and consume the parsed line
         self.state = ParserState::Headers;
-        self.buf.copy_within(consume..self.buf_len, 0);
-        self.buf_len = remaining;
+        self.consume(request_line_end + 2);
 
         Ok(ParserOk::Ok)
     }
 
+    /// Determines the form of the request target (RFC 9112 section 3.2).
+    ///
+    /// The origin-form (`/path?query`), the asterisk-form `*` of server-wide OPTIONS
+    /// requests and the authority-form (`host:port`) of CONNECT requests are kept as is.
+    /// The authority of an absolute-form target (`http://host/path?query`), sent to proxies,
+    /// replaces the `Host` header. Schemes other than `http` and `https` are rejected.
+    fn parse_target(uri: &str, method: HttpMethod) -> Result<RequestTarget<'_>, ParserError> {
+        if uri.starts_with('/') || method == HttpMethod::Connect {
+            return Ok(RequestTarget::Origin);
+        }
+        let invalid = || ParserError::InvalidTarget(uri.to_string());
+        if uri == "*" {
+            return match method {
+                HttpMethod::Options => Ok(RequestTarget::Origin),
+                _ => Err(invalid()),
+            };
+        }
+
+        let (scheme, rest) = uri.split_once("://").ok_or_else(invalid)?;
+        if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+            return Err(invalid());
+        }
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let authority = &rest[..authority_end];
+        // the userinfo subcomponent is deprecated (RFC 9110 section 4.2.4)
+        if authority.is_empty() || authority.contains('@') {
+            return Err(invalid());
+        }
+        Ok(RequestTarget::Absolute { authority })
+    }
+
+    /// Consumes the empty lines some clients send before the request line,
+    /// failing beyond [`MAX_LEADING_EMPTY_LINES`].
+    fn skip_leading_empty_lines(&mut self) -> Result<(), ParserError> {
+        while self.buf[self.pos..self.buf_len].starts_with(b"\r\n") {
+            self.leading_empty_lines += 1;
+            if self.leading_empty_lines > MAX_LEADING_EMPTY_LINES {
+                return Err(ParserError::MalformedRequestLine);
+            }
+            self.consume(2);
+        }
+        Ok(())
+    }
+
     /// Validate header name according to RFC 7230 :
     /// <https://datatracker.ietf.org/doc/html/rfc7230#section-3.2.6>
     fn get_header_name(name: &[u8]) -> Result<&str, ParserError> {
-        let s = std::str::from_utf8(name).map_err(|_| ParserError::Error)?;
+        let invalid = || ParserError::InvalidHeaderName(field(name));
+        let s = std::str::from_utf8(name).map_err(|_| invalid())?;
         if s.is_empty() {
-            return Err(ParserError::Error);
+            return Err(invalid());
         }
 
         if !s
             .bytes()
             .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
         {
-            return Err(ParserError::Error);
+            return Err(invalid());
         }
 
         Ok(s)
@@ -193,15 +447,37 @@ impl Parser {
 
     /// Validate header value according to RFC 7230 :
     /// <https://datatracker.ietf.org/doc/html/rfc7230#section-3.2.6>
-    fn get_header_value(value: &[u8]) -> Result<&str, ParserError> {
-        let s = std::str::from_utf8(value).map_err(|_| ParserError::Error)?;
-
-        // No control characters except HTAB (0x09)
+    ///
+    /// Only the optional whitespace (SP and HTAB) around the value is trimmed,
+    /// the whitespace inside it being kept as is.
+    fn get_header_value<'a>(name: &str, value: &'a [u8]) -> Result<&'a str, ParserError> {
+        let invalid = || ParserError::InvalidHeaderValue(name.to_string());
+        let s = std::str::from_utf8(value).map_err(|_| invalid())?;
+
+        // No control characters except HTAB (0x09), which rejects stray CRs as well
         if s.bytes().any(|b| (b < 0x20 && b != 0x09) || b == 0x7F) {
-            return Err(ParserError::Error);
+            return Err(invalid());
         }
 
-        Ok(s.trim())
+        Ok(s.trim_matches([' ', '\t']))
+    }
+
+    /// Sets the `Content-Length` of the request, a number of bytes made of digits only.
+    /// A repeated header must give the same length (RFC 9112 section 6.3), which is stored
+    /// without its leading zeros.
+    fn set_content_length(req: &mut HttpRequest, value: &str) -> Result<(), ParserError> {
+        let invalid = || ParserError::InvalidContentLength(value.to_string());
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let length: usize = value.parse().map_err(|_| invalid())?;
+        if let Some(previous) = req.headers.get("Content-Length")
+            && previous.parse::<usize>() != Ok(length)
+        {
+            return Err(invalid());
+        }
+        req.set_header(RequestHeader::ContentLength, &length.to_string());
+        Ok(())
     }
 
     fn parse_headers(&mut self, req: &mut HttpRequest) -> Result<ParserOk, ParserError> {
@@ -209,24 +485,28 @@ impl Parser {
         let next_line_end = self.find_delimiter(b"\r\n");
 
         if next_line_end.is_none() {
-            if self.buf_len == PARSER_BUF_CAP {
-                return Err(ParserError::Error);
+            // a single header line filling the whole buffer
+            if self.buffered() == self.buf.len() {
+                return Err(ParserError::HeadersTooLarge);
             }
             return Ok(ParserOk::Incomplete);
         }
 
+        // all the complete lines received are parsed at once,
+        // leaving room in the buffer for the next ones
         let bytes_to_consume = if let Some(end) = headers_end {
             end + 4
         } else {
-            next_line_end.unwrap() + 2
+            let last_line_end = self.buf[self.pos..self.buf_len].windows(2).rposition(|w| w == b"\r\n");
+            last_line_end.unwrap() + 2
         };
 
         self.headers_bytes_parsed += bytes_to_consume;
-        if self.headers_bytes_parsed > config().max_header_size {
-            return Err(ParserError::Error);
+        if self.headers_bytes_parsed > self.cfg.max_header_size {
+            return Err(ParserError::HeadersTooLarge);
         }
         // Parse headers line by line
-        let headers_chunk = &self.buf[..bytes_to_consume];
+        let headers_chunk = &self.buf[self.pos..self.pos + bytes_to_consume];
         let mut is_header_end = false;
         for raw_line in headers_chunk.split(|&b| b == b'\n') {
             if raw_line.is_empty() {
@@ -239,28 +519,62 @@ impl Parser {
                 break;
             }
 
+            // a folded value could be joined differently by intermediaries, enabling
+            // request smuggling, so it is rejected rather than unfolded
+            if line.starts_with(b" ") || line.starts_with(b"\t") {
+                return Err(ParserError::ObsoleteLineFolding);
+            }
+
+            self.headers_count += 1;
+            if self.headers_count > self.cfg.max_header_count {
+                return Err(ParserError::HeadersTooLarge);
+            }
+
             let mut parts = line.splitn(2, |&b| b == b':');
             let name = parts.next().unwrap();
-            let value = parts.next().ok_or(ParserError::Error)?;
+            let value = parts.next().ok_or_else(|| ParserError::MalformedHeaderLine(field(line)))?;
 
             let name = Self::get_header_name(name)?;
-            let value = Self::get_header_value(value)?;
+            let value = Self::get_header_value(name, value)?;
 
-            // no validation on value is performed here - it is left to the validator
+            // no validation on value is performed here - it is left to the validator,
+            // except for the framing headers
             match name.to_lowercase().as_str() {
-                "host" => req.set_header(RequestHeader::Host, value),
-                "content-length" => req.set_header(RequestHeader::ContentLength, value),
+                "transfer-encoding" if value.is_empty() => return Err(ParserError::InvalidHeaderValue(name.to_string())),
+                // kept as a multi-value header so the validator can detect duplicates
+                "host" if self.host_from_target => {}
+                "host" => req.append_header(RequestHeader::Host, value),
+                "content-length" => Self::set_content_length(req, value)?,
                 "content-type" => req.set_header(RequestHeader::ContentType, value),
+                "transfer-encoding" => req.append_header(RequestHeader::TransferEncoding, value),
+                "connection" => req.append_header(RequestHeader::Connection, value),
+                "accept" => req.headers.set_raw("Accept", value),
                 "accept-encoding" => req.headers.set_raw("Accept-Encoding", value),
-                _ => {}
+                "if-none-match" => req.headers.set_raw("If-None-Match", value),
+                "if-modified-since" => req.headers.set_raw("If-Modified-Since", value),
+                "range" => req.headers.set_raw("Range", value),
+                "if-range" => req.headers.set_raw("If-Range", value),
+                "expect" => req.headers.set_raw("Expect", value),
+                "origin" => req.headers.set_raw("Origin", value),
+                "cookie" => req.headers.append_raw("Cookie", value),
+                // forwarded by the reverse proxy
+                "authorization" => req.headers.set_raw("Authorization", value),
+                "user-agent" => req.headers.set_raw("User-Agent", value),
+                "x-forwarded-for" => req.headers.append_raw("X-Forwarded-For", value),
+                "access-control-request-method" => {
+                    req.headers.set_raw("Access-Control-Request-Method", value)
+                }
+                "access-control-request-headers" => {
+                    req.headers.set_raw("Access-Control-Request-Headers", value)
+                }
+                // kept as received, e.g. to be forwarded by the reverse proxy
+                _ => req.headers.append_raw(name, value),
             }
         }
 
-        let remaining = self.buf_len - bytes_to_consume;
         // Successfully parsed headers
-        // Update parser state and remove parsed headers from bufs
-        self.buf.copy_within(bytes_to_consume..self.buf_len, 0);
-        self.buf_len = remaining;
+        // Update parser state and consume the parsed headers
+        self.consume(bytes_to_consume);
 
         if headers_end.is_none() && !is_header_end {
             return Ok(ParserOk::Incomplete);
@@ -271,8 +585,21 @@ impl Parser {
     }
 
     fn parse_body(&mut self, req: &mut HttpRequest) -> Result<ParserOk, ParserError> {
+        if req.is_chunked() {
+            return self.parse_chunked_body(req);
+        }
+
         let content_length = match req.headers.get("Content-Length") {
-            Some(v) => v.parse::<usize>().map_err(|_| ParserError::Error)?,
+            Some(v) => v.parse::<usize>().map_err(|_| ParserError::InvalidContentLength(v.clone()))?,
+            // the body lasts until the connection is closed, see [`Parser::finish`]
+            None if req.is_close_delimited() => {
+                if req.body.len() + self.buffered() > self.cfg.max_body_size {
+                    return Err(ParserError::BodyTooLarge);
+                }
+                req.body.extend_from_slice(&self.buf[self.pos..self.buf_len]);
+                self.consume(self.buffered());
+                return Ok(ParserOk::Incomplete);
+            }
             None => {
                 self.state = ParserState::Done;
                 return Ok(ParserOk::Done);
@@ -280,14 +607,13 @@ impl Parser {
         };
 
         let remaining = content_length.saturating_sub(req.body.len());
-        let to_copy = std::cmp::min(self.buf_len, remaining);
-        if req.body.len() + to_copy > config().max_body_size {
-            return Err(ParserError::Error);
+        let to_copy = std::cmp::min(self.buffered(), remaining);
+        if req.body.len() + to_copy > self.cfg.max_body_size {
+            return Err(ParserError::BodyTooLarge);
         }
 
-        req.body.extend_from_slice(&self.buf[..to_copy]);
-        self.buf.copy_within(to_copy..self.buf_len, 0);
-        self.buf_len -= to_copy;
+        req.body.extend_from_slice(&self.buf[self.pos..self.pos + to_copy]);
+        self.consume(to_copy);
 
         if req.body.len() == content_length {
             self.state = ParserState::Done;
@@ -297,10 +623,84 @@ impl Parser {
         Ok(ParserOk::Incomplete)
     }
 
+    /// Decodes a chunked body as it is received, the chunk extensions being ignored.
+    /// The trailer fields count against [`max_header_size`](crate::config::ServerConfig::max_header_size)
+    /// and are discarded.
+    fn parse_chunked_body(&mut self, req: &mut HttpRequest) -> Result<ParserOk, ParserError> {
+        loop {
+            match self.chunk {
+                ChunkState::Size => {
+                    let Some(end) = self.find_delimiter(b"\r\n") else {
+                        return Ok(ParserOk::Incomplete);
+                    };
+                    let line = &self.buf[self.pos..self.pos + end];
+                    let size = chunk_size(line).ok_or_else(|| ParserError::MalformedChunk(field(line)))?;
+                    self.consume(end + 2);
+                    self.chunk = match size {
+                        0 => ChunkState::Trailers,
+                        size => ChunkState::Data(size),
+                    };
+                }
+                ChunkState::Data(remaining) => {
+                    if self.buffered() == 0 {
+                        return Ok(ParserOk::Incomplete);
+                    }
+                    let to_copy = std::cmp::min(self.buffered(), remaining);
+                    if req.body.len() + to_copy > self.cfg.max_body_size {
+                        return Err(ParserError::BodyTooLarge);
+                    }
+                    req.body.extend_from_slice(&self.buf[self.pos..self.pos + to_copy]);
+                    self.consume(to_copy);
+                    self.chunk = match remaining - to_copy {
+                        0 => ChunkState::DataEnd,
+                        remaining => ChunkState::Data(remaining),
+                    };
+                }
+                ChunkState::DataEnd => {
+                    if self.buffered() < 2 {
+                        return Ok(ParserOk::Incomplete);
+                    }
+                    let end = &self.buf[self.pos..self.pos + 2];
+                    if end != b"\r\n" {
+                        return Err(ParserError::MalformedChunk(field(end)));
+                    }
+                    self.consume(2);
+                    self.chunk = ChunkState::Size;
+                }
+                ChunkState::Trailers => {
+                    let Some(end) = self.find_delimiter(b"\r\n") else {
+                        return Ok(ParserOk::Incomplete);
+                    };
+                    self.headers_bytes_parsed += end + 2;
+                    if self.headers_bytes_parsed > self.cfg.max_header_size {
+                        return Err(ParserError::HeadersTooLarge);
+                    }
+                    self.consume(end + 2);
+                    // the empty line ending the trailer section
+                    if end == 0 {
+                        self.state = ParserState::Done;
+                        return Ok(ParserOk::Done);
+                    }
+                }
+            }
+        }
+    }
+
     // Helper for the tests to work without server context.
     fn fill_buffer(&mut self, buf: &[u8]) -> Result<(), ParserError> {
-        if self.buf_len + buf.len() > PARSER_BUF_CAP {
-            return Err(ParserError::Error);
+        if self.buffered() + buf.len() > self.buf.len() {
+            return Err(ParserError::BufferFull);
+        }
+
+        // compact the buffer only when the data does not fit after the unconsumed bytes
+        if self.buf_len + buf.len() > self.buf.len() {
+            self.buf.copy_within(self.pos..self.buf_len, 0);
+            #[cfg(test)]
+            {
+                self.moved += self.buffered();
+            }
+            self.buf_len -= self.pos;
+            self.pos = 0;
         }
 
         self.buf[self.buf_len..self.buf_len + buf.len()].copy_from_slice(buf);
@@ -308,6 +708,16 @@ impl Parser {
         Ok(())
     }
 
+    /// Signals that the client closed the connection, which completes
+    /// a [`close-delimited`](HttpRequest::is_close_delimited) body.
+    /// Returns whether the request is complete.
+    pub fn finish(&mut self, req: &HttpRequest) -> bool {
+        if self.state == ParserState::Body && req.is_close_delimited() {
+            self.state = ParserState::Done;
+        }
+        self.state == ParserState::Done
+    }
+
     /// Incremental parsing of the HTTP request
     pub fn feed(&mut self, buf: &[u8], req: &mut HttpRequest) -> Result<ParserOk, ParserError> {
         self.fill_buffer(buf)?;
@@ -330,29 +740,33 @@ impl Parser {
     }
 }
 
+/// Parses the hexadecimal size of a chunk size line, ignoring its extensions.
+pub(crate) fn chunk_size(line: &[u8]) -> Option<usize> {
+    let size = line.split(|&b| b == b';').next()?;
+    let size = std::str::from_utf8(size).ok()?.trim_matches([' ', '\t']);
+    // from_str_radix would also accept a sign
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    usize::from_str_radix(size, 16).ok()
+}
+
+/// Checks that a method or header name is a `token` as defined by RFC 7230 section 3.2.6.
+fn is_token(bytes: &[u8]) -> bool {
+    !bytes.is_empty()
+        && bytes.iter().all(|&b| {
+            b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::*;
     use crate::http::request::HttpRequest;
-    use std::sync::Once;
-
-    static INIT: Once = Once::new();
-
-    fn setup() {
-        INIT.call_once(|| {
-            let cfg = ServerConfig {
-                max_uri_size: 1024,
-                max_header_size: 8192,
-                max_body_size: 1024 * 1024,
-                ..Default::default()
-            };
-            set_config(cfg);
-        });
-    }
 
     fn run_test<F: FnOnce(&mut Parser, &mut HttpRequest)>(f: F) {
-        setup();
+        init_test_config();
         let mut parser = Parser::new();
         let mut req = HttpRequest::new();
         f(&mut parser, &mut req);
@@ -385,6 +799,53 @@ mod tests {
         Ok(ParserOk::Incomplete)
     }
 
+    /// The parser reads its limits from the default configuration when none is set.
+    /// As other tests may have set it already, the limits are those of the default one either way.
+    #[test]
+    fn without_config() {
+        let mut parser = Parser::new();
+        let mut req = HttpRequest::new();
+        let res = parser.feed(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n", &mut req);
+        assert_eq!(res, Ok(ParserOk::HeadersDone));
+        assert_eq!(req.uri, "/index.html");
+
+        let long_uri = "a".repeat(ServerConfig::default().max_uri_size + 1);
+        let line = format!("GET /{long_uri} HTTP/1.1\r\n\r\n");
+        assert!(Parser::new().feed(line.as_bytes(), &mut HttpRequest::new()).is_err());
+    }
+
+    /// Parsed bytes are consumed in place: feeding many small headers in small chunks
+    /// only moves the partial lines left at the end of the buffer when it is compacted.
+    #[test]
+    fn bounded_copies() {
+        init_test_config();
+        let count = config_or_default().max_header_count;
+        let mut request = b"POST /upload HTTP/1.1\r\nContent-Length: 10000\r\n".to_vec();
+        for i in 0..count - 1 {
+            request.extend_from_slice(format!("X-Header-{i}: {}\r\n", "v".repeat(40)).as_bytes());
+        }
+        request.extend_from_slice(b"\r\n");
+        let headers_len = request.len();
+        // large enough for the buffer to be compacted
+        assert!(headers_len > config_or_default().buffer_size && headers_len < config_or_default().max_header_size);
+        request.extend_from_slice(&[b'a'; 10000]);
+
+        for chunk_size in [3, 7, 64, 1000] {
+            let mut parser = Parser::new();
+            let mut req = HttpRequest::new();
+            let mut outcome = ParserOk::Incomplete;
+            for chunk in request.chunks(chunk_size) {
+                outcome = parser.feed(chunk, &mut req).unwrap();
+                if outcome == ParserOk::HeadersDone {
+                    outcome = parser.feed(&[], &mut req).unwrap();
+                }
+            }
+            assert_eq!(outcome, ParserOk::Done);
+            assert_eq!(req.body.len(), 10000);
+            assert!(parser.moved < headers_len, "{} bytes moved with chunks of {chunk_size}", parser.moved);
+        }
+    }
+
     // --------------------------
     // Request Line Tests
     // --------------------------
@@ -405,19 +866,68 @@ mod tests {
         }
 
         #[test]
-        fn bad_method() {
+        fn unknown_method() {
             run_test(|parser, req| {
-                let line = b"BADMETHOD /index.html HTTP/1.1\r\n";
+                let line = b"FOOBAR / HTTP/1.1\r\n";
                 parser.fill_buffer(line).unwrap();
                 let r = parser.parse_request_line(req);
-                assert_eq!(r, Err(ParserError::Error));
+                assert_eq!(r, Err(ParserError::UnknownMethod("FOOBAR".to_string())));
+                assert_eq!(ParserError::UnknownMethod(String::new()).into_http_status(), HttpStatus::NotImplemented);
+            });
+        }
+
+        #[test]
+        fn bad_method() {
+            run_test(|parser, req| {
+                let line = b"GE T / HTTP/1.1\r\n";
+                parser.fill_buffer(line).unwrap();
+                assert_eq!(parser.parse_request_line(req), Err(ParserError::MalformedRequestLine));
+            });
+
+            run_test(|parser, req| {
+                let line = b"GE\"T / HTTP/1.1\r\n";
+                parser.fill_buffer(line).unwrap();
+                assert_eq!(parser.parse_request_line(req), Err(ParserError::InvalidMethod("GE\"T".to_string())));
+            });
+        }
+
+        #[test]
+        fn request_line_whitespace() {
+            for line in [
+                "GET\t/ HTTP/1.1\r\n",
+                "GET /\tHTTP/1.1\r\n",
+                "GET  / HTTP/1.1\r\n",
+                "GET /  HTTP/1.1\r\n",
+                " GET / HTTP/1.1\r\n",
+                "GET / HTTP/1.1 \r\n",
+            ] {
+                run_test(|parser, req| {
+                    parser.fill_buffer(line.as_bytes()).unwrap();
+                    assert_eq!(parser.parse_request_line(req), Err(ParserError::RequestLineWhitespace), "{line:?}");
+                });
+            }
+            assert_eq!(ParserError::RequestLineWhitespace.into_http_status(), HttpStatus::BadRequest);
+
+            // missing or extra tokens are malformed lines
+            run_test(|parser, req| {
+                parser.fill_buffer(b"GET / HTTP/1.1 extra\r\n").unwrap();
+                assert_eq!(parser.parse_request_line(req), Err(ParserError::MalformedRequestLine));
+            });
+        }
+
+        #[test]
+        fn unknown_method_malformed_line() {
+            run_test(|parser, req| {
+                let line = b"FOOBAR / HTTP/XYZ\r\n";
+                parser.fill_buffer(line).unwrap();
+                assert_eq!(parser.parse_request_line(req), Err(ParserError::InvalidVersion("HTTP/XYZ".to_string())));
             });
         }
 
         #[test]
         fn too_long_uri() {
             run_test(|parser, req| {
-                let long_uri = "a".repeat(config().max_uri_size + 1);
+                let long_uri = "a".repeat(config_or_default().max_uri_size + 1);
                 let line = format!("GET /{} HTTP/1.1\r\n", long_uri);
                 let r =
                     parse_iteratively(parser, req, line.as_bytes(), |p, r| p.parse_request_line(r));
@@ -425,12 +935,167 @@ mod tests {
             });
         }
 
+        /// Request line of `len` bytes without its CRLF, holding the longest allowed URI
+        /// and a known method, the minor version being padded with zeros
+        fn request_line_of(len: usize) -> String {
+            let uri = "/".to_string() + &"a".repeat(config_or_default().max_uri_size - 1);
+            let line = format!("OPTIONS {uri} HTTP/1.1");
+            line.clone() + &"0".repeat(len - line.len())
+        }
+
+        #[test]
+        fn request_line_size_limit() {
+            run_test(|_, _| {
+                let max = config_or_default().max_request_line_size;
+                let parse = |line: String| {
+                    let mut parser = Parser::new();
+                    parser.fill_buffer(line.as_bytes()).unwrap();
+                    parser.parse_request_line(&mut HttpRequest::new())
+                };
+
+                // the default limit fits the longest URI with any known method
+                let uri = "/".repeat(config_or_default().max_uri_size);
+                assert!(format!("CONNECT {uri} HTTP/1.1").len() < max);
+
+                assert_eq!(parse(request_line_of(max) + "\r\n"), Ok(ParserOk::Ok));
+                assert_eq!(parse(request_line_of(max + 1) + "\r\n"), Err(ParserError::MalformedRequestLine));
+
+                // the same bound applies before the CRLF is received
+                assert_eq!(parse(request_line_of(max)), Ok(ParserOk::Incomplete));
+                assert_eq!(parse(request_line_of(max) + "\r"), Ok(ParserOk::Incomplete));
+                assert_eq!(parse(request_line_of(max + 1)), Err(ParserError::MalformedRequestLine));
+            });
+        }
+
+        #[test]
+        fn too_long_line() {
+            run_test(|_, _| {
+                let max = config_or_default().max_request_line_size;
+                let parse = |line: String| {
+                    let mut parser = Parser::new();
+                    parser.fill_buffer(line.as_bytes()).unwrap();
+                    parser.parse_request_line(&mut HttpRequest::new())
+                };
+
+                // the URI makes the line too long, whether its end is received or not
+                let uri = "/".to_string() + &"a".repeat(max);
+                assert_eq!(parse(format!("GET {uri} HTTP/1.1\r\n")), Err(ParserError::TooLongUri));
+                assert_eq!(parse(format!("GET {uri}")), Err(ParserError::TooLongUri));
+
+                // malformed lines stay bad requests
+                assert_eq!(parse("a".repeat(max + 1)), Err(ParserError::MalformedRequestLine));
+                assert_eq!(parse(format!("G(T {uri} HTTP/1.1\r\n")), Err(ParserError::MalformedRequestLine));
+                let version = "HTTP/1.1".to_string() + &"1".repeat(max);
+                assert_eq!(parse(format!("GET / {version}\r\n")), Err(ParserError::MalformedRequestLine));
+            });
+        }
+
+        #[test]
+        fn larger_buffer() {
+            let uri = "/".to_string() + &"a".repeat(8000);
+            let cfg = ServerConfig {
+                buffer_size: 16 * 1024,
+                max_uri_size: 8192,
+                max_request_line_size: 8192 + 32,
+                ..ServerConfig::default()
+            };
+            let line = format!("GET {uri} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            assert!(line.len() > ServerConfig::default().buffer_size);
+
+            let mut req = HttpRequest::new();
+            let res = Parser::with_config(Arc::new(cfg)).feed(line.as_bytes(), &mut req);
+            assert_eq!(res, Ok(ParserOk::HeadersDone));
+            assert_eq!(req.path, uri);
+
+            // the default buffer cannot hold it
+            let res = Parser::with_config(Arc::new(ServerConfig::default())).feed(line.as_bytes(), &mut req);
+            assert_eq!(res, Err(ParserError::BufferFull));
+        }
+
+        #[test]
+        fn leading_empty_lines() {
+            run_test(|parser, req| {
+                parser.fill_buffer(b"\r\nGET / HTTP/1.1\r\n").unwrap();
+                assert_eq!(parser.parse_request_line(req), Ok(ParserOk::Ok));
+                assert_eq!(req.uri, "/");
+            });
+
+            run_test(|parser, req| {
+                let line = "\r\n".repeat(MAX_LEADING_EMPTY_LINES) + "GET /multiple HTTP/1.1\r\n";
+                let r = parse_iteratively(parser, req, line.as_bytes(), |p, r| p.parse_request_line(r));
+                assert_eq!(r, Ok(ParserOk::Ok));
+                assert_eq!(req.uri, "/multiple");
+            });
+        }
+
+        #[test]
+        fn too_many_leading_empty_lines() {
+            run_test(|parser, req| {
+                let line = "\r\n".repeat(MAX_LEADING_EMPTY_LINES + 1) + "GET / HTTP/1.1\r\n";
+                let r = parse_iteratively(parser, req, line.as_bytes(), |p, r| p.parse_request_line(r));
+                assert_eq!(r, Err(ParserError::MalformedRequestLine));
+            });
+        }
+
+        #[test]
+        fn absolute_form() {
+            run_test(|parser, req| {
+                parser.fill_buffer(b"GET http://example.com:8080/path?q=1 HTTP/1.1\r\n").unwrap();
+                assert_eq!(parser.parse_request_line(req), Ok(ParserOk::Ok));
+                assert_eq!(req.uri, "http://example.com:8080/path?q=1");
+                assert_eq!(req.path, "/path");
+                assert_eq!(req.host(), Some(("example.com", Some(8080))));
+            });
+
+            for target in ["HTTPS://example.com", "http://example.com?q=1"] {
+                run_test(|parser, req| {
+                    parser.fill_buffer(format!("GET {target} HTTP/1.1\r\n").as_bytes()).unwrap();
+                    assert_eq!(parser.parse_request_line(req), Ok(ParserOk::Ok));
+                    assert_eq!(req.path, "/");
+                    assert_eq!(req.host(), Some(("example.com", None)));
+                });
+            }
+        }
+
+        #[test]
+        fn absolute_form_overrides_host_header() {
+            let mut parser = Parser::new();
+            let mut req = HttpRequest::new();
+            let request = b"GET http://example.com/ HTTP/1.1\r\nHost: other.com\r\n\r\n";
+            assert_eq!(parser.feed(request, &mut req), Ok(ParserOk::HeadersDone));
+            assert_eq!(req.headers.get_all("Host").collect::<Vec<_>>(), ["example.com"]);
+        }
+
+        #[test]
+        fn invalid_absolute_form() {
+            for target in ["ftp://example.com/", "http:///path", "http://user@example.com/", "index.html"] {
+                run_test(|parser, req| {
+                    parser.fill_buffer(format!("GET {target} HTTP/1.1\r\n").as_bytes()).unwrap();
+                    assert_eq!(parser.parse_request_line(req), Err(ParserError::InvalidTarget(target.to_string())));
+                });
+            }
+        }
+
+        #[test]
+        fn asterisk_form() {
+            run_test(|parser, req| {
+                parser.fill_buffer(b"OPTIONS * HTTP/1.1\r\n").unwrap();
+                assert_eq!(parser.parse_request_line(req), Ok(ParserOk::Ok));
+                assert_eq!(req.path, "*");
+            });
+
+            run_test(|parser, req| {
+                parser.fill_buffer(b"GET * HTTP/1.1\r\n").unwrap();
+                assert_eq!(parser.parse_request_line(req), Err(ParserError::InvalidTarget("*".to_string())));
+            });
+        }
+
         #[test]
         fn bad_http_version() {
             run_test(|parser, req| {
                 let line = b"GET /index.html HTTP/XYZ\r\n";
                 parser.fill_buffer(line).unwrap();
-                assert_eq!(parser.parse_request_line(req), Err(ParserError::Error));
+                assert_eq!(parser.parse_request_line(req), Err(ParserError::InvalidVersion("HTTP/XYZ".to_string())));
             });
         }
 
@@ -474,17 +1139,155 @@ mod tests {
             });
         }
 
+        #[test]
+        fn other_headers() {
+            run_test(|parser, req| {
+                let headers = b"X-Request-Id: 42\r\nx-tag: a\r\nX-Tag: b\r\n\r\n";
+                parser.fill_buffer(headers).unwrap();
+                assert_eq!(parser.parse_headers(req).unwrap(), ParserOk::Ok);
+                assert_eq!(req.headers.get("X-Request-Id").unwrap(), "42");
+                assert_eq!(req.headers.get_all("X-Tag").collect::<Vec<_>>(), ["a", "b"]);
+            });
+        }
+
+        #[test]
+        fn optional_whitespace() {
+            run_test(|parser, req| {
+                let headers = b"Accept:\t text/html \t\r\nOrigin:   https://a.example  b  \r\n\r\n";
+                parser.fill_buffer(headers).unwrap();
+                assert_eq!(parser.parse_headers(req), Ok(ParserOk::Ok));
+                assert_eq!(req.headers.get("Accept").unwrap(), "text/html");
+                // internal whitespace is preserved
+                assert_eq!(req.headers.get("Origin").unwrap(), "https://a.example  b");
+            });
+
+            // other whitespace, like a non-breaking space, is part of the value
+            run_test(|parser, req| {
+                parser.fill_buffer("Accept: \u{a0}text/html\r\n\r\n".as_bytes()).unwrap();
+                assert_eq!(parser.parse_headers(req), Ok(ParserOk::Ok));
+                assert_eq!(req.headers.get("Accept").unwrap(), "\u{a0}text/html");
+            });
+        }
+
+        #[test]
+        fn stray_carriage_return() {
+            run_test(|parser, req| {
+                parser.fill_buffer(b"Accept: text/html\rX-Injected: 1\r\n\r\n").unwrap();
+                assert_eq!(parser.parse_headers(req), Err(ParserError::InvalidHeaderValue("Accept".to_string())));
+            });
+        }
+
+        #[test]
+        fn empty_framing_headers() {
+            for (headers, err) in [
+                ("Content-Length: \t \r\n\r\n", ParserError::InvalidContentLength(String::new())),
+                ("Transfer-Encoding:\r\n\r\n", ParserError::InvalidHeaderValue("Transfer-Encoding".to_string())),
+            ] {
+                run_test(|parser, req| {
+                    parser.fill_buffer(headers.as_bytes()).unwrap();
+                    assert_eq!(parser.parse_headers(req), Err(err), "{headers:?}");
+                });
+            }
+        }
+
+        #[test]
+        fn strict_content_length() {
+            for value in ["+5", "-5", "0x5", "5 5", "5,5", "1e3", "99999999999999999999999"] {
+                run_test(|parser, req| {
+                    parser.fill_buffer(format!("Content-Length: {value}\r\n\r\n").as_bytes()).unwrap();
+                    assert_eq!(parser.parse_headers(req), Err(ParserError::InvalidContentLength(value.to_string())));
+                });
+            }
+
+            // the whitespace around the value is not part of it, unlike the one inside
+            let mut req = HttpRequest::new();
+            let err = Parser::set_content_length(&mut req, "5 ");
+            assert_eq!(err, Err(ParserError::InvalidContentLength("5 ".to_string())));
+            run_test(|parser, req| {
+                parser.fill_buffer(b"Content-Length: 5 \r\n\r\n").unwrap();
+                assert_eq!(parser.parse_headers(req), Ok(ParserOk::Ok));
+                assert_eq!(req.headers.get("Content-Length").unwrap(), "5");
+            });
+
+            run_test(|parser, req| {
+                parser.fill_buffer(b"Content-Length: 007\r\n\r\n").unwrap();
+                assert_eq!(parser.parse_headers(req), Ok(ParserOk::Ok));
+                assert_eq!(req.headers.get("Content-Length").unwrap(), "7");
+            });
+        }
+
+        #[test]
+        fn duplicate_content_length() {
+            run_test(|parser, req| {
+                parser.fill_buffer(b"Content-Length: 5\r\nContent-Length: 05\r\n\r\n").unwrap();
+                assert_eq!(parser.parse_headers(req), Ok(ParserOk::Ok));
+                assert_eq!(req.headers.get("Content-Length").unwrap(), "5");
+            });
+
+            run_test(|parser, req| {
+                parser.fill_buffer(b"Content-Length: 5\r\nContent-Length: 6\r\n\r\n").unwrap();
+                assert_eq!(parser.parse_headers(req), Err(ParserError::InvalidContentLength("6".to_string())));
+            });
+        }
+
+        #[test]
+        fn folded_header() {
+            for headers in [
+                "X-Folded: first\r\n second\r\n\r\n",
+                "X-Folded: first\r\n\tsecond\r\n\r\n",
+                "Host: example.com\r\n X-Injected: 1\r\n\r\n",
+            ] {
+                run_test(|parser, req| {
+                    let r = parse_iteratively(parser, req, headers.as_bytes(), |p, r| p.parse_headers(r));
+                    assert_eq!(r, Err(ParserError::ObsoleteLineFolding), "{headers:?}");
+                });
+            }
+            assert_eq!(ParserError::ObsoleteLineFolding.into_http_status(), HttpStatus::BadRequest);
+        }
+
         #[test]
         fn header_too_long() {
             run_test(|parser, req| {
                 let long_header = format!(
                     "X-Header: {}\r\n\r\n",
-                    "a".repeat(config().max_header_size + 1)
+                    "a".repeat(config_or_default().max_header_size + 1)
                 );
                 let r = parse_iteratively(parser, req, long_header.as_bytes(), |p, r| {
                     p.parse_headers(r)
                 });
-                assert_eq!(r, Err(ParserError::Error));
+                assert_eq!(r, Err(ParserError::HeadersTooLarge));
+            });
+        }
+
+        #[test]
+        fn headers_too_large() {
+            // many lines each fitting in the buffer but exceeding the total size
+            let count = config_or_default().max_header_size / 64 + 1;
+            let headers = format!("X-A: {}\r\n", "a".repeat(57)).repeat(count) + "\r\n";
+            let cfg = ServerConfig { max_header_count: count, ..ServerConfig::default() };
+            let mut parser = Parser::with_config(Arc::new(cfg));
+            let r = parse_iteratively(&mut parser, &mut HttpRequest::new(), headers.as_bytes(), |p, r| {
+                p.parse_headers(r)
+            });
+            assert_eq!(r, Err(ParserError::HeadersTooLarge));
+            assert_eq!(r.unwrap_err().into_http_status(), HttpStatus::RequestHeaderFieldsTooLarge);
+        }
+
+        #[test]
+        fn too_many_headers() {
+            run_test(|parser, req| {
+                let headers = "X-A: b\r\n".repeat(config_or_default().max_header_count + 1) + "\r\n";
+                let r = parse_iteratively(parser, req, headers.as_bytes(), |p, r| p.parse_headers(r));
+                assert_eq!(r, Err(ParserError::HeadersTooLarge));
+            });
+        }
+
+        #[test]
+        fn max_headers() {
+            run_test(|parser, req| {
+                let headers = "X-A: b\r\n".repeat(config_or_default().max_header_count) + "\r\n";
+                let r = parse_iteratively(parser, req, headers.as_bytes(), |p, r| p.parse_headers(r));
+                assert_eq!(r, Ok(ParserOk::Ok));
             });
         }
 
@@ -493,10 +1296,30 @@ mod tests {
             run_test(|parser, req| {
                 let header = b"BadHeaderWithoutColon\r\n\r\n";
                 parser.fill_buffer(header).unwrap();
-                assert_eq!(parser.parse_headers(req), Err(ParserError::Error));
+                let err = ParserError::MalformedHeaderLine("BadHeaderWithoutColon".to_string());
+                assert_eq!(parser.parse_headers(req), Err(err));
             });
         }
 
+        #[test]
+        fn invalid_header_name() {
+            for (headers, name) in [("X(A): b\r\n\r\n", "X(A)"), (": b\r\n\r\n", "")] {
+                run_test(|parser, req| {
+                    parser.fill_buffer(headers.as_bytes()).unwrap();
+                    assert_eq!(parser.parse_headers(req), Err(ParserError::InvalidHeaderName(name.to_string())));
+                });
+            }
+        }
+
+        #[test]
+        fn error_statuses() {
+            let err = ParserError::InvalidContentLength("abc".to_string());
+            assert_eq!(err.to_string(), "invalid Content-Length \"abc\"");
+            assert_eq!(err.into_http_status(), HttpStatus::BadRequest);
+            assert_eq!(ParserError::BodyTooLarge.into_http_status(), HttpStatus::PayloadTooLarge);
+            assert_eq!(ParserError::MalformedRequestLine.to_string(), "malformed request line");
+        }
+
         #[test]
         fn fragmented_header() {
             run_test(|parser, req| {
@@ -540,6 +1363,27 @@ mod tests {
             });
         }
 
+        #[test]
+        fn close_delimited_body() {
+            run_test(|parser, req| {
+                let head = b"POST /upload HTTP/1.0\r\nConnection: close\r\n\r\n";
+                assert_eq!(parser.feed(head, req), Ok(ParserOk::HeadersDone));
+                assert_eq!(parser.feed(b"hello", req), Ok(ParserOk::Incomplete));
+                assert_eq!(parser.feed(b" world", req), Ok(ParserOk::Incomplete));
+                assert!(parser.finish(req));
+                assert_eq!(req.body, b"hello world");
+                assert_eq!(parser.feed(&[], req), Ok(ParserOk::Done));
+            });
+
+            // without Connection: close, the body is empty
+            run_test(|parser, req| {
+                let head = b"POST /upload HTTP/1.0\r\n\r\nhello";
+                assert_eq!(parser.feed(head, req), Ok(ParserOk::HeadersDone));
+                assert_eq!(parser.feed(&[], req), Ok(ParserOk::Done));
+                assert!(req.body.is_empty());
+            });
+        }
+
         #[test]
         fn incomplete_body() {
             run_test(|parser, req| {
@@ -557,11 +1401,46 @@ mod tests {
             run_test(|parser, req| {
                 req.set_header(
                     RequestHeader::ContentLength,
-                    &(config().max_body_size + 1).to_string(),
+                    &(config_or_default().max_body_size + 1).to_string(),
                 );
-                let body = vec![b'a'; config().max_body_size + 1];
+                let body = vec![b'a'; config_or_default().max_body_size + 1];
                 let r = parse_iteratively(parser, req, body.as_slice(), |p, r| p.parse_body(r));
-                assert_eq!(r, Err(ParserError::Error));
+                assert_eq!(r, Err(ParserError::BodyTooLarge));
+            });
+        }
+
+        #[test]
+        fn chunked_body() {
+            run_test(|parser, req| {
+                let request = b"POST /upload HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5;name=value\r\nHello\r\nB\r\n, chunked!!\r\n0\r\nX-Checksum: 1\r\n\r\n";
+                assert_eq!(parser.feed(&request[..80], req), Ok(ParserOk::HeadersDone));
+                let r = parse_iteratively(parser, req, &request[80..], |p, r| p.parse_body(r)).unwrap();
+                assert_eq!(r, ParserOk::Done);
+                assert_eq!(req.body, b"Hello, chunked!!");
+            });
+        }
+
+        #[test]
+        fn malformed_chunks() {
+            for body in [&b"x\r\n"[..], b"-5\r\nHello\r\n", b"5\r\nHello!\r\n"] {
+                run_test(|parser, req| {
+                    req.set_header(RequestHeader::TransferEncoding, "chunked");
+                    parser.fill_buffer(body).unwrap();
+                    assert!(matches!(parser.parse_body(req), Err(ParserError::MalformedChunk(_))));
+                });
+            }
+        }
+
+        #[test]
+        fn too_large_chunked_body() {
+            run_test(|parser, req| {
+                req.set_header(RequestHeader::TransferEncoding, "chunked");
+                let size = config_or_default().max_body_size + 1;
+                parser.fill_buffer(format!("{size:x}\r\n").as_bytes()).unwrap();
+                let body = vec![b'a'; size];
+                let r = parse_iteratively(parser, req, &body, |p, r| p.parse_body(r));
+                assert_eq!(r, Err(ParserError::BodyTooLarge));
             });
         }
 
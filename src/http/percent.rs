@@ -0,0 +1,130 @@
+//! Percent-encoding of URI path segments (RFC 3986 section 2.1),
+//! normalization of request paths and decoding of form-urlencoded bodies.
+
+/// Percent-encodes a path segment, keeping only the unreserved characters of RFC 3986.
+pub fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Decodes the `%XX` sequences of a URI component.
+/// Returns `None` when a sequence is truncated, is not hexadecimal
+/// or when the decoded bytes are not valid UTF-8.
+pub fn decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            // from_str_radix would also accept a sign
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Decodes a name or value of an `application/x-www-form-urlencoded` body,
+/// in which `+` stands for a space and `%2B` for a plus sign, see [`decode`].
+pub fn decode_form_component(s: &str) -> Option<String> {
+    decode(&s.replace('+', " "))
+}
+
+/// Turns the origin-form target of a request into the path it designates.
+///
+/// The query is dropped and each segment is percent-decoded before `.` and `..` are
+/// resolved (RFC 3986 section 5.2.4), so that encoded dots (`%2e%2e`) cannot climb
+/// above the root either. Returns `None` for invalid encodings, NUL bytes and paths
+/// leaving the root, as well as for encoded slashes (`%2F`) unless `allow_encoded_slash`
+/// is set, in which case they separate segments like plain ones.
+pub fn normalize_path(target: &str, allow_encoded_slash: bool) -> Option<String> {
+    let path = target.split('?').next().unwrap_or("");
+
+    let mut segments: Vec<String> = Vec::new();
+    for raw in path.split('/') {
+        let decoded = decode(raw)?;
+        if decoded.contains('\0') || (decoded.contains('/') && !allow_encoded_slash) {
+            return None;
+        }
+
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => (),
+                ".." => {
+                    segments.pop()?;
+                }
+                _ => segments.push(segment.to_string()),
+            }
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode("my%20file.txt").as_deref(), Some("my file.txt"));
+        assert_eq!(decode("caf%C3%A9").as_deref(), Some("café"));
+        assert_eq!(decode("100%25").as_deref(), Some("100%"));
+        assert_eq!(decode("plain").as_deref(), Some("plain"));
+
+        assert_eq!(decode("%zz"), None);
+        assert_eq!(decode("%+1"), None);
+        assert_eq!(decode("trailing%2"), None);
+        assert_eq!(decode("%C3"), None); // truncated UTF-8 sequence
+    }
+
+    #[test]
+    fn form_components() {
+        assert_eq!(decode_form_component("John+Doe").as_deref(), Some("John Doe"));
+        assert_eq!(decode_form_component("1%2B1%3D2").as_deref(), Some("1+1=2"));
+        assert_eq!(decode_form_component("").as_deref(), Some(""));
+        assert_eq!(decode_form_component("100%"), None);
+    }
+
+    #[test]
+    fn normalized_paths() {
+        assert_eq!(normalize_path("/my%20file.txt", false).as_deref(), Some("/my file.txt"));
+        assert_eq!(normalize_path("/docs/caf%C3%A9.txt", false).as_deref(), Some("/docs/café.txt"));
+        assert_eq!(normalize_path("/a/./b/../c.txt?v=1", false).as_deref(), Some("/a/c.txt"));
+        assert_eq!(normalize_path("/", false).as_deref(), Some("/"));
+
+        assert_eq!(normalize_path("/%zz.txt", false), None);
+        assert_eq!(normalize_path("/../etc/passwd", false), None);
+        assert_eq!(normalize_path("/a/%2e%2e/%2E%2E/etc/passwd", false), None);
+        assert_eq!(normalize_path("/a%00.txt", false), None);
+    }
+
+    #[test]
+    fn encoded_slashes() {
+        assert_eq!(normalize_path("/docs%2Fa.txt", false), None);
+        assert_eq!(normalize_path("/a%2F..%2F..%2Fetc", false), None);
+
+        assert_eq!(normalize_path("/docs%2Fa.txt", true).as_deref(), Some("/docs/a.txt"));
+        assert_eq!(normalize_path("/docs%2f..%2Fa.txt", true).as_deref(), Some("/a.txt"));
+        // decoded slashes do not allow to leave the root either
+        assert_eq!(normalize_path("/a%2F..%2F..%2Fetc", true), None);
+    }
+
+    #[test]
+    fn encoding_roundtrip() {
+        let name = "a <b>&\"c\"/é.txt";
+        assert_eq!(encode_segment(name), "a%20%3Cb%3E%26%22c%22%2F%C3%A9.txt");
+        assert_eq!(decode(&encode_segment(name)).as_deref(), Some(name));
+    }
+}
@@ -0,0 +1,77 @@
+//! `Range` request header parsing (RFC 7233).
+//!
+//! Only single byte ranges are served. Other range units, multiple ranges and
+//! malformed headers are ignored so that the full representation is served instead,
+//! as allowed by RFC 7233 section 3.1.
+
+/// Outcome of evaluating a `Range` header against a representation of a given length.
+#[derive(Debug, PartialEq)]
+pub enum Range {
+    /// The header is ignored and the full representation is served.
+    Full,
+    /// Inclusive byte positions of the requested part of the representation.
+    Partial { start: usize, end: usize },
+    /// The range lies beyond the end of the representation.
+    Unsatisfiable,
+}
+
+/// Evaluates a `Range` header value against a representation of `len` bytes.
+pub fn parse_range(header: &str, len: usize) -> Range {
+    let Some((unit, set)) = header.split_once('=') else {
+        return Range::Full;
+    };
+
+    // only the bytes unit is supported, the others are ignored
+    if !unit.trim().eq_ignore_ascii_case("bytes") || set.contains(',') {
+        return Range::Full;
+    }
+
+    let Some((first, last)) = set.trim().split_once('-') else {
+        return Range::Full;
+    };
+
+    match (first.parse::<usize>(), last.parse::<usize>()) {
+        // suffix range: the last `suffix` bytes
+        (Err(_), Ok(suffix)) if first.is_empty() => {
+            if suffix == 0 || len == 0 {
+                Range::Unsatisfiable
+            } else {
+                Range::Partial { start: len.saturating_sub(suffix), end: len - 1 }
+            }
+        }
+        (Ok(start), _) if start >= len => Range::Unsatisfiable,
+        (Ok(start), Err(_)) if last.is_empty() => Range::Partial { start, end: len - 1 },
+        (Ok(start), Ok(end)) if start <= end => Range::Partial { start, end: end.min(len - 1) },
+        _ => Range::Full,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), Range::Partial { start: 0, end: 4 });
+        assert_eq!(parse_range("bytes=5-", 10), Range::Partial { start: 5, end: 9 });
+        assert_eq!(parse_range("bytes=-3", 10), Range::Partial { start: 7, end: 9 });
+        assert_eq!(parse_range("bytes=8-100", 10), Range::Partial { start: 8, end: 9 });
+        assert_eq!(parse_range("bytes=-100", 10), Range::Partial { start: 0, end: 9 });
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert_eq!(parse_range("bytes=10-", 10), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 10), Range::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignored() {
+        assert_eq!(parse_range("items=0-9", 10), Range::Full);
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), Range::Full);
+        assert_eq!(parse_range("bytes=5-2", 10), Range::Full);
+        assert_eq!(parse_range("bytes=a-b", 10), Range::Full);
+        assert_eq!(parse_range("0-9", 10), Range::Full);
+    }
+}
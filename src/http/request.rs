@@ -1,5 +1,12 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::config::config_or_default;
 use crate::http::HttpMethod;
 use crate::http::headers::HttpHeaders;
+use crate::http::multipart::{self, MultipartError, MultipartField};
+use crate::http::percent;
+use crate::http::status::HttpStatus;
 
 /// Common HTTP request headers
 /// This enum defines the set of headers that can be explicitly set on an
@@ -10,16 +17,38 @@ pub enum RequestHeader {
     Host,
     ContentLength,
     ContentType,
+    TransferEncoding,
+    Connection,
 }
 
+impl RequestHeader {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestHeader::ContentLength => "Content-Length",
+            RequestHeader::ContentType => "Content-Type",
+            RequestHeader::Host => "Host",
+            RequestHeader::TransferEncoding => "Transfer-Encoding",
+            RequestHeader::Connection => "Connection",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct HttpRequest {
     pub method: HttpMethod,
+    /// Request target exactly as sent, including the query
     pub uri: String,
+    /// Path designated by the target, percent-decoded and without dot segments,
+    /// on which the request is routed. See [`HttpRequest::set_target`].
+    pub path: String,
     pub http_version: (u8, u8),
 
     // headers
     pub headers: HttpHeaders,
     pub body: Vec<u8>,
+
+    /// Address of the client the request was received from, unknown for the Unix socket
+    pub peer_addr: Option<IpAddr>,
 }
 
 impl HttpRequest {
@@ -27,9 +56,11 @@ impl HttpRequest {
         Self {
             method: HttpMethod::Unknown,
             uri: String::new(),
+            path: String::new(),
             http_version: (0, 0),
             headers: HttpHeaders::new(),
             body: Vec::new(),
+            peer_addr: None,
         }
     }
 
@@ -41,12 +72,507 @@ impl HttpRequest {
     ///
     /// No validation is performed on the header value itself.
     pub fn set_header(&mut self, h: RequestHeader, value: &str) {
-        let name = match h {
-            RequestHeader::ContentLength => "Content-Length",
-            RequestHeader::ContentType => "Content-Type",
-            RequestHeader::Host => "Host",
+        self.headers.set_raw(h.as_str(), value);
+    }
+
+    /// Sets the request target and the path derived from it.
+    ///
+    /// The path of an origin-form (`/path?query`) or absolute-form (`http://host/path`)
+    /// target is normalized with [`percent::normalize_path`], an invalid one being a
+    /// `400 Bad Request`. The other forms, `*` and the authority of CONNECT, are kept as is.
+    pub fn set_target(&mut self, target: &str) -> Result<(), HttpStatus> {
+        let origin = origin_form(target);
+        self.path = match origin.starts_with('/') {
+            true => percent::normalize_path(origin, config_or_default().allow_encoded_slash)
+                .ok_or(HttpStatus::BadRequest)?,
+            false => origin.to_string(),
         };
+        self.uri = target.to_string();
+        Ok(())
+    }
+
+    /// Returns the request target exactly as sent, e.g. to be logged or forwarded.
+    pub fn raw_target(&self) -> &str {
+        &self.uri
+    }
+
+    /// Returns the hostname and optional port given by the `Host` header.
+    /// See [`parse_host`] for the accepted syntax.
+    pub fn host(&self) -> Option<(&str, Option<u16>)> {
+        self.headers.get("Host").and_then(|h| parse_host(h))
+    }
+
+    /// Same as [`HttpRequest::set_header`] but keeps the values already set for this header.
+    pub fn append_header(&mut self, h: RequestHeader, value: &str) {
+        self.headers.append_raw(h.as_str(), value);
+    }
+
+    /// Whether the body of the request is delimited by the client closing the connection,
+    /// as HTTP/1.0 clients may do: a POST or PUT without `Content-Length` nor
+    /// `Transfer-Encoding` but with `Connection: close`.
+    ///
+    /// Other methods are not read until the connection is closed, as their body is
+    /// optional and a client keeping the connection open would stall the response.
+    pub fn is_close_delimited(&self) -> bool {
+        matches!(self.method, HttpMethod::Post | HttpMethod::Put)
+            && self.headers.get("Content-Length").is_none()
+            && self.headers.get("Transfer-Encoding").is_none()
+            && self
+                .headers
+                .get_all("Connection")
+                .flat_map(|v| v.split(','))
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+    }
+
+    /// Whether the body of the request is framed with the chunked transfer coding,
+    /// the last one applied (RFC 9112 section 6.3).
+    pub fn is_chunked(&self) -> bool {
+        self.headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|coding| !coding.eq_ignore_ascii_case("identity"))
+            .last()
+            .is_some_and(|coding| coding.eq_ignore_ascii_case("chunked"))
+    }
+
+    /// Whether the `Content-Type` of the request is `media_type`, whatever its parameters.
+    fn has_media_type(&self, media_type: &str) -> bool {
+        self.headers.get("Content-Type").is_some_and(|value| {
+            let essence = value.split(';').next().unwrap_or("").trim();
+            essence.eq_ignore_ascii_case(media_type)
+        })
+    }
+
+    /// Returns the body decoded as UTF-8, whatever its `Content-Type`.
+    #[allow(dead_code)]
+    pub fn body_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+
+    /// Deserializes the body of a request sent with `Content-Type: application/json`,
+    /// the media type parameters such as `charset` being ignored.
+    #[cfg(feature = "json")]
+    #[allow(dead_code)]
+    pub fn body_json<T: serde::de::DeserializeOwned>(&self) -> Result<T, JsonBodyError> {
+        if !self.has_media_type("application/json") {
+            return Err(JsonBodyError::NotJson);
+        }
+        if self.body.is_empty() {
+            return Err(JsonBodyError::EmptyBody);
+        }
+        serde_json::from_slice(&self.body).map_err(JsonBodyError::Invalid)
+    }
+
+    /// Returns the fields of a body sent with `Content-Type: application/x-www-form-urlencoded`,
+    /// see [`parse_form`]. When a name is repeated, as for the options of a multiple select,
+    /// the first value is kept.
+    #[allow(dead_code)]
+    pub fn form_params(&self) -> Result<HashMap<String, String>, FormError> {
+        if !self.has_media_type("application/x-www-form-urlencoded") {
+            return Err(FormError::NotForm);
+        }
+
+        let body = std::str::from_utf8(&self.body).map_err(|_| FormError::InvalidEncoding(String::new()))?;
+        let mut params = HashMap::new();
+        for (name, value) in parse_form(body)? {
+            params.entry(name).or_insert(value);
+        }
+        Ok(params)
+    }
+
+    /// Returns the parts of a `multipart/form-data` body, within the `max_multipart_part_size`
+    /// and `max_multipart_size` limits of the server config, see [`multipart::collect`].
+    #[allow(dead_code)]
+    pub fn multipart(&self) -> Result<Vec<MultipartField>, MultipartError> {
+        let content_type = self.headers.get("Content-Type").ok_or(MultipartError::NotMultipart)?;
+        if !self.has_media_type("multipart/form-data") {
+            return Err(MultipartError::NotMultipart);
+        }
+        let boundary = multipart::boundary(content_type).ok_or(MultipartError::MissingBoundary)?;
+
+        let cfg = config_or_default();
+        multipart::collect(
+            &self.body,
+            boundary,
+            cfg.max_header_size,
+            cfg.max_multipart_part_size,
+            cfg.max_multipart_size,
+        )
+    }
+
+    /// Returns the cookies sent in the `Cookie` header(s), see [`parse_cookies`].
+    #[allow(dead_code)]
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let mut cookies = HashMap::new();
+        for header in self.headers.get_all("Cookie") {
+            for (name, value) in parse_cookies(header) {
+                cookies.entry(name.to_string()).or_insert_with(|| value.to_string());
+            }
+        }
+        cookies
+    }
+
+    /// Returns the value of a single cookie, see [`HttpRequest::cookies`].
+    #[allow(dead_code)]
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(|header| parse_cookies(header))
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.to_string())
+    }
+}
+
+/// Errors of [`HttpRequest::body_json`].
+#[cfg(feature = "json")]
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum JsonBodyError {
+    /// Missing `Content-Type`, or other than `application/json`
+    NotJson,
+    EmptyBody,
+    /// Body which is not valid JSON or does not match the expected type
+    Invalid(serde_json::Error),
+}
+
+#[cfg(feature = "json")]
+#[allow(dead_code)]
+impl JsonBodyError {
+    pub fn into_http_status(self) -> HttpStatus {
+        match self {
+            JsonBodyError::NotJson => HttpStatus::UnsupportedMediaType,
+            JsonBodyError::EmptyBody => HttpStatus::BadRequest,
+            JsonBodyError::Invalid(_) => HttpStatus::UnprocessableContent,
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl std::fmt::Display for JsonBodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonBodyError::NotJson => write!(f, "the body is not sent as application/json"),
+            JsonBodyError::EmptyBody => write!(f, "the JSON body is empty"),
+            JsonBodyError::Invalid(err) => write!(f, "invalid JSON body: {err}"),
+        }
+    }
+}
+
+/// Errors of [`HttpRequest::form_params`].
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+pub enum FormError {
+    /// Missing `Content-Type`, or other than `application/x-www-form-urlencoded`
+    NotForm,
+    /// Field with an invalid percent-encoding, or body which is not UTF-8 (empty field then)
+    InvalidEncoding(String),
+}
+
+#[allow(dead_code)]
+impl FormError {
+    pub fn into_http_status(self) -> HttpStatus {
+        match self {
+            FormError::NotForm => HttpStatus::UnsupportedMediaType,
+            FormError::InvalidEncoding(_) => HttpStatus::BadRequest,
+        }
+    }
+}
+
+/// Splits a form-urlencoded body into its decoded `(name, value)` fields, in order.
+///
+/// Fields are separated by `&` and split on their first `=`, a field without `=` having
+/// an empty value. Empty fields, as in `a=1&&b=2`, are skipped.
+pub fn parse_form(body: &str) -> Result<Vec<(String, String)>, FormError> {
+    body.split('&')
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (name, value) = field.split_once('=').unwrap_or((field, ""));
+            let decode = |s| percent::decode_form_component(s).ok_or_else(|| FormError::InvalidEncoding(field.to_string()));
+            Ok((decode(name)?, decode(value)?))
+        })
+        .collect()
+}
+
+/// Returns the origin-form part (`/path?query`) of an absolute-form target,
+/// the other targets being returned unchanged.
+pub fn origin_form(target: &str) -> &str {
+    if target.starts_with('/') {
+        return target;
+    }
+    match target.split_once("://") {
+        Some((_, rest)) => match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('/') => &rest[i..],
+            _ => "/",
+        },
+        None => target,
+    }
+}
+
+/// Splits a `Cookie` header value into `(name, value)` pairs, in order.
+///
+/// Pairs are separated by `;` and split on their first `=`, so values may contain `=`
+/// and be empty. Segments without `=` or with an empty name are skipped. When a name
+/// is repeated, the first pair is the one kept by [`HttpRequest::cookies`], as browsers
+/// send the cookies with the most specific path first (RFC 6265 section 5.4).
+pub fn parse_cookies(value: &str) -> impl Iterator<Item = (&str, &str)> {
+    value.split(';').filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        (!name.is_empty()).then_some((name, value.trim()))
+    })
+}
+
+/// Splits a `Host` header value into a hostname and an optional port.
+///
+/// IPv6 literals must be enclosed in brackets (`[::1]:8080`), which are not part
+/// of the returned hostname. Returns `None` for an empty hostname or an invalid port.
+pub fn parse_host(value: &str) -> Option<(&str, Option<u16>)> {
+    let (hostname, port) = if let Some(rest) = value.strip_prefix('[') {
+        let (literal, after) = rest.split_once(']')?;
+        match after {
+            "" => (literal, None),
+            _ => (literal, Some(after.strip_prefix(':')?)),
+        }
+    } else {
+        match value.split_once(':') {
+            Some((name, port)) => (name, Some(port)),
+            None => (value, None),
+        }
+    };
+
+    if hostname.is_empty() {
+        return None;
+    }
+
+    let port = match port {
+        Some(p) if p.bytes().all(|b| b.is_ascii_digit()) => Some(p.parse().ok()?),
+        Some(_) => return None,
+        None => None,
+    };
+
+    Some((hostname, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostname_only() {
+        assert_eq!(parse_host("example.com"), Some(("example.com", None)));
+    }
+
+    #[test]
+    fn hostname_and_port() {
+        assert_eq!(parse_host("example.com:8080"), Some(("example.com", Some(8080))));
+    }
+
+    #[test]
+    fn ipv6_literal() {
+        assert_eq!(parse_host("[::1]"), Some(("::1", None)));
+        assert_eq!(parse_host("[::1]:8080"), Some(("::1", Some(8080))));
+    }
+
+    fn with_cookies(headers: &[&str]) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        for header in headers {
+            req.headers.append_raw("Cookie", header);
+        }
+        req
+    }
+
+    #[test]
+    fn single_cookie() {
+        let req = with_cookies(&["session=abc123"]);
+        assert_eq!(req.cookies(), HashMap::from([("session".to_string(), "abc123".to_string())]));
+        assert_eq!(req.cookie("session").as_deref(), Some("abc123"));
+        assert_eq!(req.cookie("missing"), None);
+        assert!(HttpRequest::new().cookies().is_empty());
+    }
+
+    #[test]
+    fn multiple_cookies() {
+        let req = with_cookies(&["a=1; b=x=y==;  c= ; a=2", "d=4"]);
+        let cookies = req.cookies();
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies["a"], "1"); // the first of duplicates
+        assert_eq!(cookies["b"], "x=y==");
+        assert_eq!(cookies["c"], "");
+        assert_eq!(cookies["d"], "4");
+        assert_eq!(req.cookie("a").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn malformed_cookies() {
+        let req = with_cookies(&["novalue; =anonymous; ; ok=1;"]);
+        assert_eq!(req.cookies(), HashMap::from([("ok".to_string(), "1".to_string())]));
+    }
+
+    #[test]
+    fn raw_target_and_path() {
+        let mut req = HttpRequest::new();
+        req.set_target("/docs/./caf%C3%A9%20au%20lait.txt?v=%2F1").unwrap();
+        assert_eq!(req.raw_target(), "/docs/./caf%C3%A9%20au%20lait.txt?v=%2F1");
+        assert_eq!(req.uri, req.raw_target());
+        assert_eq!(req.path, "/docs/café au lait.txt");
+
+        req.set_target("http://example.com/a/../b?q").unwrap();
+        assert_eq!(req.raw_target(), "http://example.com/a/../b?q");
+        assert_eq!(req.path, "/b");
+
+        req.set_target("*").unwrap();
+        assert_eq!(req.path, "*");
+        req.set_target("example.com:443").unwrap();
+        assert_eq!(req.path, "example.com:443");
+
+        assert_eq!(req.set_target("/../etc/passwd"), Err(HttpStatus::BadRequest));
+    }
+
+    #[test]
+    fn malformed_hosts() {
+        assert_eq!(parse_host("foo:bar"), None);
+        assert_eq!(parse_host("foo:"), None);
+        assert_eq!(parse_host(":8080"), None);
+        assert_eq!(parse_host("example.com:99999"), None);
+        assert_eq!(parse_host("[::1"), None);
+        assert_eq!(parse_host("[::1]8080"), None);
+        assert_eq!(parse_host(""), None);
+    }
+
+    fn form_request(body: &str) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.set_header(RequestHeader::ContentType, "application/x-www-form-urlencoded");
+        req.body = body.as_bytes().to_vec();
+        req
+    }
+
+    #[test]
+    fn login_form() {
+        let req = form_request("username=john.doe%40example.com&password=p%26ss+w%3Drd&remember=");
+        let params = req.form_params().unwrap();
+        assert_eq!(params["username"], "john.doe@example.com");
+        assert_eq!(params["password"], "p&ss w=rd");
+        assert_eq!(params["remember"], "");
+        assert_eq!(params.len(), 3);
+    }
+
+    #[test]
+    fn form_fields() {
+        assert_eq!(
+            parse_form("color=red&&color=blue&flag&a+b=c%2Bd").unwrap(),
+            [("color", "red"), ("color", "blue"), ("flag", ""), ("a b", "c+d")]
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+        );
+        assert_eq!(parse_form("").unwrap(), []);
+
+        // the first of the repeated names is kept
+        assert_eq!(form_request("color=red&color=blue").form_params().unwrap()["color"], "red");
+    }
+
+    #[test]
+    fn malformed_form() {
+        let err = form_request("user=john&password=100%").form_params().unwrap_err();
+        assert_eq!(err, FormError::InvalidEncoding("password=100%".to_string()));
+        assert_eq!(err.into_http_status(), HttpStatus::BadRequest);
+
+        let mut req = form_request("");
+        req.body = vec![b'a', b'=', 0xff];
+        assert_eq!(req.form_params(), Err(FormError::InvalidEncoding(String::new())));
+
+        let mut req = form_request("user=john");
+        req.set_header(RequestHeader::ContentType, "text/plain");
+        assert_eq!(req.form_params(), Err(FormError::NotForm));
+        assert_eq!(HttpRequest::new().form_params(), Err(FormError::NotForm));
+    }
+
+    #[test]
+    fn multipart_body() {
+        let mut req = HttpRequest::new();
+        req.set_header(RequestHeader::ContentType, "multipart/form-data; boundary=b0undary");
+        req.body = b"--b0undary\r\n\
+            Content-Disposition: form-data; name=\"comment\"\r\n\
+            \r\n\
+            Looks good\r\n\
+            --b0undary\r\n\
+            Content-Disposition: form-data; name=\"report\"; filename=\"report.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            line 1\r\nline 2\r\n\
+            --b0undary--\r\n"
+            .to_vec();
+
+        let fields = req.multipart().unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].part.name.as_deref(), Some("comment"));
+        assert_eq!(fields[0].data, b"Looks good");
+        assert_eq!(fields[1].part.filename.as_deref(), Some("report.txt"));
+        assert_eq!(fields[1].part.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(fields[1].data, b"line 1\r\nline 2");
+
+        req.body.truncate(req.body.len() - 4);
+        assert_eq!(req.multipart(), Err(MultipartError::Incomplete));
+    }
+
+    #[test]
+    fn multipart_content_type() {
+        let mut req = HttpRequest::new();
+        assert_eq!(req.multipart(), Err(MultipartError::NotMultipart));
+        req.set_header(RequestHeader::ContentType, "text/plain; boundary=b0undary");
+        assert_eq!(req.multipart(), Err(MultipartError::NotMultipart));
+        req.set_header(RequestHeader::ContentType, "multipart/form-data");
+        assert_eq!(req.multipart(), Err(MultipartError::MissingBoundary));
+    }
+
+    #[test]
+    fn body_as_string() {
+        let mut req = HttpRequest::new();
+        req.body = "café".as_bytes().to_vec();
+        assert_eq!(req.body_str(), Ok("café"));
+        req.body = vec![0xff, 0xfe];
+        assert!(req.body_str().is_err());
+    }
+
+    #[cfg(feature = "json")]
+    fn json_request(content_type: Option<&str>, body: &str) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        if let Some(content_type) = content_type {
+            req.set_header(RequestHeader::ContentType, content_type);
+        }
+        req.body = body.as_bytes().to_vec();
+        req
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_body() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Login {
+            user: String,
+            remember: bool,
+        }
+
+        let req = json_request(Some("application/json; charset=utf-8"), r#"{"user": "alice", "remember": true}"#);
+        let login: Login = req.body_json().unwrap();
+        assert_eq!(login, Login { user: "alice".to_string(), remember: true });
+
+        let req = json_request(Some("Application/JSON"), r#"{"user": "alice"}"#);
+        let err = req.body_json::<Login>().unwrap_err();
+        assert_eq!(err.into_http_status(), HttpStatus::UnprocessableContent);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_body_errors() {
+        let body = r#"{"user": "alice"}"#;
+        for content_type in [Some("text/plain"), Some("application/json-seq"), None] {
+            let err = json_request(content_type, body).body_json::<serde_json::Value>().unwrap_err();
+            assert!(matches!(err, JsonBodyError::NotJson), "{content_type:?}");
+            assert_eq!(err.into_http_status(), HttpStatus::UnsupportedMediaType);
+        }
 
-        self.headers.set_raw(name, value);
+        let err = json_request(Some("application/json"), "").body_json::<serde_json::Value>().unwrap_err();
+        assert!(matches!(err, JsonBodyError::EmptyBody));
+        assert_eq!(err.to_string(), "the JSON body is empty");
     }
 }
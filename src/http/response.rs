@@ -1,5 +1,7 @@
-use crate::config::config;
+use crate::config::config_or_default;
+use crate::http::cookie::Cookie;
 use crate::http::headers::HttpHeaders;
+use crate::http::parser;
 use crate::http::status::HttpStatus;
 use httpdate;
 
@@ -9,21 +11,138 @@ use httpdate;
 /// [`HttpResponse`] through its safe wrapper API.
 #[allow(dead_code)]
 pub enum ResponseHeader {
+    AccessControlAllowCredentials,
+    AccessControlAllowHeaders,
+    AccessControlAllowMethods,
+    AccessControlAllowOrigin,
+    AccessControlMaxAge,
+    AcceptRanges,
+    Allow,
+    CacheControl,
     ContentLength,
     ContentType,
     ContentEncoding,
+    ContentRange,
     Connection,
+    TransferEncoding,
+    ETag,
+    LastModified,
+    Location,
+    RetryAfter,
+    ServerTiming,
     Date,
     Server,
+    SetCookie,
+    Vary,
+}
+
+impl ResponseHeader {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseHeader::AccessControlAllowCredentials => "Access-Control-Allow-Credentials",
+            ResponseHeader::AccessControlAllowHeaders => "Access-Control-Allow-Headers",
+            ResponseHeader::AccessControlAllowMethods => "Access-Control-Allow-Methods",
+            ResponseHeader::AccessControlAllowOrigin => "Access-Control-Allow-Origin",
+            ResponseHeader::AccessControlMaxAge => "Access-Control-Max-Age",
+            ResponseHeader::AcceptRanges => "Accept-Ranges",
+            ResponseHeader::Allow => "Allow",
+            ResponseHeader::CacheControl => "Cache-Control",
+            ResponseHeader::ContentType => "Content-Type",
+            ResponseHeader::ContentLength => "Content-Length",
+            ResponseHeader::ContentEncoding => "Content-Encoding",
+            ResponseHeader::ContentRange => "Content-Range",
+            ResponseHeader::Connection => "Connection",
+            ResponseHeader::TransferEncoding => "Transfer-Encoding",
+            ResponseHeader::ETag => "ETag",
+            ResponseHeader::LastModified => "Last-Modified",
+            ResponseHeader::Location => "Location",
+            ResponseHeader::RetryAfter => "Retry-After",
+            ResponseHeader::Date => "Date",
+            ResponseHeader::Server => "Server",
+            ResponseHeader::ServerTiming => "Server-Timing",
+            ResponseHeader::SetCookie => "Set-Cookie",
+            ResponseHeader::Vary => "Vary",
+        }
+    }
+}
+
+/// File sent as the body of a response, see [`HttpResponse::file`].
+#[cfg(feature = "sendfile")]
+pub struct FileBody {
+    pub file: std::fs::File,
+    pub len: u64,
+}
+
+#[cfg(feature = "sendfile")]
+impl FileBody {
+    /// Reads the `len` first bytes of the file, whatever the position of the descriptor,
+    /// from a thread of the blocking pool.
+    pub async fn read(&self) -> std::io::Result<Vec<u8>> {
+        use std::os::unix::fs::FileExt;
+
+        let file = self.file.try_clone()?;
+        let len = self.len as usize;
+        async_std::task::spawn_blocking(move || {
+            let mut body = vec![0; len];
+            file.read_exact_at(&mut body, 0)?;
+            Ok(body)
+        })
+        .await
+    }
 }
 
 pub struct HttpResponse {
     pub status: HttpStatus,
     pub headers: HttpHeaders,
+
+    /// Assigning the body directly leaves the `Content-Length` header untouched,
+    /// [`HttpResponse::set_body`] keeps them in sync.
     pub body: Vec<u8>,
+
+    /// File sent as the body with `sendfile` instead of [`body`](Self::body), which stays
+    /// empty, see [`sendfile`](crate::net::sendfile). It is read into `body` by the
+    /// middlewares rewriting the body, see [`HttpResponse::load_file`].
+    #[cfg(feature = "sendfile")]
+    pub file: Option<FileBody>,
+
+    /// Whether the length of the body is known when the headers are sent.
+    /// When it is not, the body is sent with `Transfer-Encoding: chunked` instead of a
+    /// `Content-Length`. Only the framing is chosen from it: no handler streams its body yet.
+    pub length_known: bool,
+
+    /// HTTP version of the response, echoing the one of the request:
+    /// `(1, 0)` for HTTP/1.0 clients, `(1, 1)` otherwise.
+    pub version: (u8, u8),
 }
 
 impl HttpResponse {
+    /// Starts building a response, see [`ResponseBuilder`].
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder {
+            res: HttpResponse::new(),
+            default_content_type: None,
+        }
+    }
+
+    /// Serializes `value` into a `200 OK` response, see [`HttpResponse::json_with_status`].
+    #[cfg(feature = "json")]
+    #[allow(dead_code)]
+    pub fn json<T: serde::Serialize>(value: &T) -> Result<HttpResponse, serde_json::Error> {
+        Self::json_with_status(HttpStatus::Ok, value)
+    }
+
+    /// Serializes `value` into the body of a response sent as `application/json`,
+    /// its `Content-Length` being computed like for any [`ResponseBuilder::body_json`] body.
+    #[cfg(feature = "json")]
+    #[allow(dead_code)]
+    pub fn json_with_status<T: serde::Serialize>(
+        status: HttpStatus,
+        value: &T,
+    ) -> Result<HttpResponse, serde_json::Error> {
+        let body = serde_json::to_string(value)?;
+        Ok(Self::builder().status(status).body_json(body).build())
+    }
+
     /// Creates a new `HttpResponse` with default headers set.
     /// These include the `Server` header with the server name from the configuration
     /// and the `Date` header with the current system time.
@@ -32,10 +151,14 @@ impl HttpResponse {
             status: HttpStatus::Ok,
             headers: HttpHeaders::new(),
             body: Vec::new(),
+            #[cfg(feature = "sendfile")]
+            file: None,
+            length_known: true,
+            version: (1, 1),
         };
 
         // Host system name
-        res.set_header(ResponseHeader::Server, &config().server_name);
+        res.set_header(ResponseHeader::Server, &config_or_default().server_name);
         res.set_header(
             ResponseHeader::Date,
             &httpdate::fmt_http_date(std::time::SystemTime::now()),
@@ -51,63 +174,376 @@ impl HttpResponse {
     ///
     /// No validation is performed on the header value itself.
     pub fn set_header(&mut self, h: ResponseHeader, value: &str) {
-        let name = match h {
-            ResponseHeader::ContentType => "Content-Type",
-            ResponseHeader::ContentLength => "Content-Length",
-            ResponseHeader::ContentEncoding => "Content-Encoding",
-            ResponseHeader::Connection => "Connection",
-            ResponseHeader::Date => "Date",
-            ResponseHeader::Server => "Server",
-        };
+        self.headers.set_raw(h.as_str(), value);
+    }
+
+    /// Same as [`HttpResponse::set_header`] but keeps the values already set for this header.
+    pub fn append_header(&mut self, h: ResponseHeader, value: &str) {
+        self.headers.append_raw(h.as_str(), value);
+    }
+
+    /// Sets a cookie on the client, each cookie being sent in its own `Set-Cookie` header.
+    #[allow(dead_code)]
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.append_header(ResponseHeader::SetCookie, &cookie.to_header_value());
+    }
 
-        self.headers.set_raw(name, value);
+    /// Replaces the body and updates the `Content-Length` header accordingly,
+    /// unless the length of the body is not known (see [`length_known`](Self::length_known)).
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+        if self.length_known {
+            self.set_header(ResponseHeader::ContentLength, &self.body.len().to_string());
+        }
+    }
+
+    /// Discards the body, e.g. of a HEAD response.
+    pub fn clear_body(&mut self) {
+        self.body.clear();
+        #[cfg(feature = "sendfile")]
+        {
+            self.file = None;
+        }
+    }
+
+    /// Sends the `len` first bytes of a file as the body, see [`file`](Self::file).
+    #[cfg(feature = "sendfile")]
+    pub fn set_file(&mut self, file: std::fs::File, len: u64) {
+        self.body.clear();
+        self.file = Some(FileBody { file, len });
+        self.set_header(ResponseHeader::ContentLength, &len.to_string());
+    }
+
+    /// Reads the file sent as the body, if any, into [`body`](Self::body).
+    /// The file is kept when it cannot be read.
+    #[cfg(feature = "sendfile")]
+    pub async fn load_file(&mut self) -> std::io::Result<()> {
+        if let Some(file) = &self.file {
+            self.body = file.read().await?;
+            self.file = None;
+        }
+        Ok(())
+    }
+
+    /// Whether the body is framed with the chunked transfer coding, see
+    /// [`length_known`](Self::length_known). Responses which never carry a body are not,
+    /// nor those to HTTP/1.0 clients which do not know this coding.
+    pub fn is_chunked(&self) -> bool {
+        !self.length_known && self.has_body() && self.version >= (1, 1)
+    }
+
+    fn has_body(&self) -> bool {
+        !matches!(self.status, HttpStatus::NoContent | HttpStatus::NotModified)
     }
 
     /// Builds the HTTP response headers as a formatted string.
-    /// If the response status is not `200 OK`, it generates a minimal
-    /// response with just the status line.
-    /// 
-    /// Otherwise, it includes all headers set in the `HttpHeaders` structure.
+    /// It includes the status line and all headers set in the `HttpHeaders` structure.
+    ///
+    /// At most [`max_set_cookies`](crate::config::ServerConfig::max_set_cookies)
+    /// `Set-Cookie` headers are emitted, the others being dropped with a warning.
+    ///
+    /// The framing header is chosen from [`length_known`](Self::length_known): a chunked
+    /// response announces `Transfer-Encoding: chunked` and never a `Content-Length`.
+    /// Responses are sent with `Connection: close` as the connection is closed after each
+    /// of them, which is also how a body of unknown length is delimited for HTTP/1.0.
     pub fn build_headers(&self) -> String {
-        if self.status != HttpStatus::Ok {
-            let error = error_code_stringify(self.status);
-
-            // HTTP <major>.<minor> <status> <reason>\r\n
-            // \r\n
-            return format!(
-                "HTTP/1.1 {} {}\r\n \
-                            \r\n",
-                self.status as usize, error
+        self.build_headers_with(config_or_default().max_set_cookies)
+    }
+
+    fn build_headers_with(&self, max_set_cookies: usize) -> String {
+        let set_cookies = self.headers.get_all("Set-Cookie").count();
+        if set_cookies > max_set_cookies {
+            eprintln!(
+                "Dropping {} of the {} Set-Cookie headers of the response (max_set_cookies = {})",
+                set_cookies - max_set_cookies,
+                set_cookies,
+                max_set_cookies
             );
         }
 
-        // HTTP <major>.<minor> <status>\r\n
+        let mut headers = self.headers.clone();
+        if !self.length_known && self.has_body() {
+            headers.remove(ResponseHeader::ContentLength.as_str());
+        }
+        if self.is_chunked() {
+            headers.set_raw(ResponseHeader::TransferEncoding.as_str(), "chunked");
+        }
+        // until keep-alive is supported, which HTTP/1.1 clients would otherwise assume
+        headers.set_raw(ResponseHeader::Connection.as_str(), "close");
+
+        // HTTP <major>.<minor> <status> <reason>\r\n
         // <header_name>: <header_value>\r\n
         // ...
         // \r\n
         format!(
-            "HTTP/1.1 {} OK\r\n\
+            "HTTP/{}.{} {} {}\r\n\
                  {}\
                  \r\n",
-            self.status as usize,
-            self.headers.stringify(),
+            self.version.0,
+            self.version.1,
+            self.status.code(),
+            self.status.reason_phrase(),
+            headers.stringify_limited(max_set_cookies),
         )
     }
 }
 
-/// Maps HTTP status codes to their standard reason phrases.
-fn error_code_stringify(code: HttpStatus) -> &'static str {
-    match code {
-        HttpStatus::BadRequest => "Bad Request",                              // 400
-        HttpStatus::Forbidden => "Forbidden",                                  // 403
-        HttpStatus::NotFound => "Not Found",                                  // 404
-        HttpStatus::MethodNotAllowed => "Method Not Allowed",                 // 405
-        HttpStatus::LengthRequired => "Content-Length field required",        // 411
-        HttpStatus::PayloadTooLarge => "Payload Too Large",                   // 413
-        HttpStatus::UriTooLong => "URI Too Long",                             // 414
-
-        HttpStatus::InternalServerError => "Internal Server Error",           // 500
-        HttpStatus::HttpVersionNotSupported => "HTTP Version Not Supported",  // 505
-        HttpStatus::Ok => "",
+/// Fluent construction of an [`HttpResponse`], such as
+/// `HttpResponse::builder().status(HttpStatus::Ok).body_text("hello").build()`.
+///
+/// The `Content-Length` is computed from the body when the response is built, and the
+/// `body_*` methods give a default `Content-Type`, used unless one is set explicitly.
+pub struct ResponseBuilder {
+    res: HttpResponse,
+    default_content_type: Option<&'static str>,
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status: HttpStatus) -> Self {
+        self.res.status = status;
+        self
+    }
+
+    pub fn header(mut self, h: ResponseHeader, value: &str) -> Self {
+        self.res.set_header(h, value);
+        self
+    }
+
+    /// Sets a body of raw bytes, sent as `application/octet-stream` by default.
+    pub fn body_bytes(mut self, body: Vec<u8>) -> Self {
+        self.res.body = body;
+        self.default_content_type = Some("application/octet-stream");
+        self
+    }
+
+    pub fn body_text(self, body: impl Into<String>) -> Self {
+        self.body_typed(body.into(), "text/plain; charset=utf-8")
+    }
+
+    pub fn body_html(self, body: impl Into<String>) -> Self {
+        self.body_typed(body.into(), "text/html; charset=utf-8")
+    }
+
+    /// Sets an already serialized JSON body.
+    #[allow(dead_code)]
+    pub fn body_json(self, body: impl Into<String>) -> Self {
+        self.body_typed(body.into(), "application/json")
+    }
+
+    fn body_typed(mut self, body: String, content_type: &'static str) -> Self {
+        self.res.body = body.into_bytes();
+        self.default_content_type = Some(content_type);
+        self
+    }
+
+    pub fn build(mut self) -> HttpResponse {
+        if let Some(content_type) = self.default_content_type
+            && self.res.headers.get(ResponseHeader::ContentType.as_str()).is_none()
+        {
+            self.res.set_header(ResponseHeader::ContentType, content_type);
+        }
+
+        // these responses never carry a body, nor a length describing it
+        if !matches!(self.res.status, HttpStatus::NoContent | HttpStatus::NotModified) {
+            let len = self.res.body.len().to_string();
+            self.res.set_header(ResponseHeader::ContentLength, &len);
+        }
+        self.res
+    }
+}
+
+/// Encodes a body with the chunked transfer coding, in chunks of at most `chunk_size`
+/// bytes followed by the last (empty) chunk.
+pub fn encode_chunked(body: &[u8], chunk_size: usize) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(body.len() + 16);
+    for chunk in body.chunks(chunk_size.max(1)) {
+        encoded.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+        encoded.extend_from_slice(chunk);
+        encoded.extend_from_slice(b"\r\n");
+    }
+    encoded.extend_from_slice(b"0\r\n\r\n");
+    encoded
+}
+
+/// Decodes a body framed with the chunked transfer coding, the chunk extensions
+/// and the trailer section being ignored.
+/// Returns `None` when the body is malformed or truncated.
+pub fn decode_chunked(mut encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::with_capacity(encoded.len());
+    loop {
+        let line_end = encoded.windows(2).position(|w| w == b"\r\n")?;
+        let size = parser::chunk_size(&encoded[..line_end])?;
+        encoded = &encoded[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+
+        body.extend_from_slice(encoded.get(..size)?);
+        encoded = encoded.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
+
+/// Builds a header-less interim response (1xx), such as `100 Continue`.
+pub fn interim_response(status: HttpStatus) -> String {
+    format!("HTTP/1.1 {} {}\r\n\r\n", status.code(), status.reason_phrase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_responses() {
+        #[derive(serde::Serialize)]
+        struct User {
+            id: u32,
+            name: &'static str,
+            roles: Vec<&'static str>,
+        }
+
+        init_test_config();
+        let user = User { id: 7, name: "Zoé", roles: vec!["admin"] };
+        let res = HttpResponse::json(&user).unwrap();
+        assert_eq!(res.status, HttpStatus::Ok);
+        let body = r#"{"id":7,"name":"Zoé","roles":["admin"]}"#;
+        assert_eq!(res.body, body.as_bytes());
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "application/json");
+        assert_eq!(res.headers.get("Content-Length").unwrap(), &body.len().to_string());
+
+        let res = HttpResponse::json_with_status(HttpStatus::Created, &user).unwrap();
+        assert_eq!(res.status, HttpStatus::Created);
+        assert_eq!(res.body, body.as_bytes());
+
+        // maps with non-string keys cannot be serialized
+        let map = std::collections::HashMap::from([((1, 2), "pair")]);
+        assert!(HttpResponse::json(&map).is_err());
+    }
+
+    #[test]
+    fn builder_computes_length() {
+        init_test_config();
+        let res = HttpResponse::builder()
+            .status(HttpStatus::NotFound)
+            .body_text("héllo")
+            .build();
+        assert_eq!(res.status, HttpStatus::NotFound);
+        assert_eq!(res.body, "héllo".as_bytes());
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "6");
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "text/plain; charset=utf-8");
+
+        let res = HttpResponse::builder().body_json(r#"{"ok":true}"#).build();
+        assert_eq!(res.status, HttpStatus::Ok);
+        assert_eq!(res.headers.get("Content-Length").unwrap(), &res.body.len().to_string());
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "application/json");
+
+        // an explicit content type wins, whatever the order of the calls
+        let res = HttpResponse::builder()
+            .body_bytes(vec![0; 3])
+            .header(ResponseHeader::ContentType, "image/png")
+            .build();
+        assert_eq!(res.headers.get("Content-Type").unwrap(), "image/png");
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "3");
+
+        let res = HttpResponse::builder().build();
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "0");
+        assert!(res.headers.get("Content-Type").is_none());
+
+        let res = HttpResponse::builder().status(HttpStatus::NoContent).build();
+        assert!(res.headers.get("Content-Length").is_none());
+    }
+
+    #[test]
+    fn set_body_updates_length() {
+        init_test_config();
+        let mut res = HttpResponse::new();
+        res.set_body(b"first body".to_vec());
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "10");
+
+        res.set_body(b"short".to_vec());
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "5");
+        assert_eq!(res.headers.get_all("Content-Length").count(), 1);
+
+        res.set_body(Vec::new());
+        assert_eq!(res.headers.get("Content-Length").unwrap(), "0");
+    }
+
+    #[test]
+    fn chunked_framing() {
+        init_test_config();
+        let mut res = HttpResponse::builder().body_text("hello world").build();
+        let headers = res.build_headers();
+        assert!(headers.contains("Content-Length: 11\r\n"));
+        assert!(!headers.contains("Transfer-Encoding"));
+
+        res.length_known = false;
+        let headers = res.build_headers();
+        assert!(headers.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!headers.contains("Content-Length"));
+
+        assert_eq!(encode_chunked(b"hello world", 5), b"5\r\nhello\r\n5\r\n worl\r\n1\r\nd\r\n0\r\n\r\n");
+        assert_eq!(encode_chunked(b"", 5), b"0\r\n\r\n");
+
+        res.status = HttpStatus::NotModified;
+        assert!(!res.is_chunked());
+    }
+
+    #[test]
+    fn chunked_decoding() {
+        assert_eq!(decode_chunked(&encode_chunked(b"hello world", 5)).unwrap(), b"hello world");
+        assert_eq!(decode_chunked(b"5;ext=1\r\nhello\r\n0\r\nTrailer: x\r\n\r\n").unwrap(), b"hello");
+        assert!(decode_chunked(b"5\r\nhel").is_none());
+        assert!(decode_chunked(b"5\r\nhelloX\r\n0\r\n\r\n").is_none());
+        assert!(decode_chunked(b"zz\r\n").is_none());
+        assert!(decode_chunked(b"+5\r\nhello\r\n0\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn http_1_0_framing() {
+        init_test_config();
+        let mut res = HttpResponse::builder().body_text("hello").build();
+        let headers = res.build_headers();
+        assert!(headers.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(headers.contains("Connection: close\r\n"));
+
+        res.version = (1, 0);
+        let headers = res.build_headers();
+        assert!(headers.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(headers.contains("Connection: close\r\n"));
+        assert!(headers.contains("Content-Length: 5\r\n"));
+
+        // unknown lengths are delimited by closing the connection rather than chunked
+        res.length_known = false;
+        assert!(!res.is_chunked());
+        let headers = res.build_headers();
+        assert!(!headers.contains("Transfer-Encoding"));
+        assert!(!headers.contains("Content-Length"));
+        assert!(headers.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn custom_statuses() {
+        init_test_config();
+        let res = HttpResponse::builder().status(HttpStatus::from_code(418).unwrap()).body_text("teapot").build();
+        assert!(res.build_headers().starts_with("HTTP/1.1 418 Client Error\r\n"));
+
+        let res = HttpResponse::builder().status(HttpStatus::from_code(507).unwrap()).build();
+        assert!(res.build_headers().starts_with("HTTP/1.1 507 Server Error\r\n"));
+    }
+
+    #[test]
+    fn set_cookies_are_capped() {
+        init_test_config();
+        let mut res = HttpResponse::new();
+        for i in 0..1000 {
+            res.add_cookie(Cookie::new(&format!("c{i}"), "1").unwrap());
+        }
+
+        let headers = res.build_headers_with(3);
+        assert_eq!(headers.matches("Set-Cookie:").count(), 3);
+        assert!(headers.contains("Set-Cookie: c2=1\r\n"));
+        assert!(!headers.contains("c3=1"));
+        assert!(headers.ends_with("\r\n\r\n"));
     }
 }
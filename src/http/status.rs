@@ -1,17 +1,235 @@
 //! Defines common HTTP status codes used in responses.
+//!
+//! The statuses the server sends itself have a variant of their own, the other codes
+//! being carried by [`HttpStatus::Other`] for their responses to be relayed or built as is.
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpStatus {
-    Ok = 200,
-
-    BadRequest = 400,
-    Forbidden = 403,
-    NotFound = 404,
-    MethodNotAllowed = 405,
-    LengthRequired = 411,
-    PayloadTooLarge = 413,
-    UriTooLong = 414,
-
-    InternalServerError = 500,
-    HttpVersionNotSupported = 505,
+    Continue,
+
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    UnprocessableContent,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+
+    /// Status without a variant of its own, such as `418` or `507` relayed from an upstream,
+    /// see [`HttpStatus::from_code`]
+    Other(UnknownCode),
+}
+
+/// Code of an [`HttpStatus::Other`], only built by [`HttpStatus::from_code`] so that
+/// it is always a valid status code without a variant of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownCode(u16);
+
+impl HttpStatus {
+    /// Every status known to the server, see [`HttpStatus::from_code`].
+    const ALL: [HttpStatus; 35] = [
+        HttpStatus::Continue,
+        HttpStatus::Ok,
+        HttpStatus::Created,
+        HttpStatus::Accepted,
+        HttpStatus::NoContent,
+        HttpStatus::PartialContent,
+        HttpStatus::MovedPermanently,
+        HttpStatus::Found,
+        HttpStatus::SeeOther,
+        HttpStatus::NotModified,
+        HttpStatus::TemporaryRedirect,
+        HttpStatus::PermanentRedirect,
+        HttpStatus::BadRequest,
+        HttpStatus::Unauthorized,
+        HttpStatus::Forbidden,
+        HttpStatus::NotFound,
+        HttpStatus::MethodNotAllowed,
+        HttpStatus::NotAcceptable,
+        HttpStatus::RequestTimeout,
+        HttpStatus::Conflict,
+        HttpStatus::Gone,
+        HttpStatus::LengthRequired,
+        HttpStatus::PayloadTooLarge,
+        HttpStatus::UriTooLong,
+        HttpStatus::UnsupportedMediaType,
+        HttpStatus::RangeNotSatisfiable,
+        HttpStatus::UnprocessableContent,
+        HttpStatus::TooManyRequests,
+        HttpStatus::RequestHeaderFieldsTooLarge,
+        HttpStatus::InternalServerError,
+        HttpStatus::NotImplemented,
+        HttpStatus::BadGateway,
+        HttpStatus::ServiceUnavailable,
+        HttpStatus::GatewayTimeout,
+        HttpStatus::HttpVersionNotSupported,
+    ];
+
+    /// Returns the status of a numeric code, [`HttpStatus::Other`] for the codes without
+    /// a variant of their own, or `None` when the code is not a three-digit status code.
+    pub fn from_code(code: u16) -> Option<HttpStatus> {
+        if !(100..=599).contains(&code) {
+            return None;
+        }
+        let known = Self::ALL.into_iter().find(|status| status.code() == code);
+        Some(known.unwrap_or(Self::Other(UnknownCode(code))))
+    }
+
+    /// Returns the numeric code of the status.
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::Continue => 100,
+            Self::Ok => 200,
+            Self::Created => 201,
+            Self::Accepted => 202,
+            Self::NoContent => 204,
+            Self::PartialContent => 206,
+            Self::MovedPermanently => 301,
+            Self::Found => 302,
+            Self::SeeOther => 303,
+            Self::NotModified => 304,
+            Self::TemporaryRedirect => 307,
+            Self::PermanentRedirect => 308,
+            Self::BadRequest => 400,
+            Self::Unauthorized => 401,
+            Self::Forbidden => 403,
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::NotAcceptable => 406,
+            Self::RequestTimeout => 408,
+            Self::Conflict => 409,
+            Self::Gone => 410,
+            Self::LengthRequired => 411,
+            Self::PayloadTooLarge => 413,
+            Self::UriTooLong => 414,
+            Self::UnsupportedMediaType => 415,
+            Self::RangeNotSatisfiable => 416,
+            Self::UnprocessableContent => 422,
+            Self::TooManyRequests => 429,
+            Self::RequestHeaderFieldsTooLarge => 431,
+            Self::InternalServerError => 500,
+            Self::NotImplemented => 501,
+            Self::BadGateway => 502,
+            Self::ServiceUnavailable => 503,
+            Self::GatewayTimeout => 504,
+            Self::HttpVersionNotSupported => 505,
+            Self::Other(UnknownCode(code)) => *code,
+        }
+    }
+
+    /// Returns the standard reason phrase of the status, as sent in the status line.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            Self::Continue => "Continue",                                         // 100
+            Self::Ok => "OK",                                                     // 200
+            Self::Created => "Created",                                           // 201
+            Self::Accepted => "Accepted",                                         // 202
+            Self::NoContent => "No Content",                                      // 204
+            Self::PartialContent => "Partial Content",                            // 206
+            Self::MovedPermanently => "Moved Permanently",                        // 301
+            Self::Found => "Found",                                               // 302
+            Self::SeeOther => "See Other",                                        // 303
+            Self::NotModified => "Not Modified",                                  // 304
+            Self::TemporaryRedirect => "Temporary Redirect",                      // 307
+            Self::PermanentRedirect => "Permanent Redirect",                      // 308
+
+            Self::BadRequest => "Bad Request",                                    // 400
+            Self::Unauthorized => "Unauthorized",                                 // 401
+            Self::Forbidden => "Forbidden",                                       // 403
+            Self::NotFound => "Not Found",                                        // 404
+            Self::MethodNotAllowed => "Method Not Allowed",                       // 405
+            Self::NotAcceptable => "Not Acceptable",                              // 406
+            Self::RequestTimeout => "Request Timeout",                            // 408
+            Self::Conflict => "Conflict",                                         // 409
+            Self::Gone => "Gone",                                                 // 410
+            Self::LengthRequired => "Content-Length field required",              // 411
+            Self::PayloadTooLarge => "Payload Too Large",                         // 413
+            Self::UriTooLong => "URI Too Long",                                   // 414
+            Self::UnsupportedMediaType => "Unsupported Media Type",               // 415
+            Self::RangeNotSatisfiable => "Range Not Satisfiable",                 // 416
+            Self::UnprocessableContent => "Unprocessable Content",                // 422
+            Self::TooManyRequests => "Too Many Requests",                         // 429
+            Self::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large", // 431
+
+            Self::InternalServerError => "Internal Server Error",                 // 500
+            Self::NotImplemented => "Not Implemented",                            // 501
+            Self::BadGateway => "Bad Gateway",                                    // 502
+            Self::ServiceUnavailable => "Service Unavailable",                    // 503
+            Self::GatewayTimeout => "Gateway Timeout",                            // 504
+            Self::HttpVersionNotSupported => "HTTP Version Not Supported",        // 505
+
+            // the generic phrase of the class, as the phrase is only informative (RFC 9112 section 4)
+            Self::Other(UnknownCode(code)) => match code / 100 {
+                1 => "Informational",
+                2 => "Success",
+                3 => "Redirection",
+                4 => "Client Error",
+                _ => "Server Error",
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        for code in [100, 200, 206, 304, 404, 431, 503] {
+            assert_eq!(HttpStatus::from_code(code).unwrap().code(), code);
+        }
+        for status in HttpStatus::ALL {
+            assert_eq!(HttpStatus::from_code(status.code()), Some(status));
+        }
+        assert_eq!(HttpStatus::from_code(200), Some(HttpStatus::Ok));
+    }
+
+    #[test]
+    fn unknown_codes() {
+        assert_eq!(HttpStatus::from_code(418), Some(HttpStatus::Other(UnknownCode(418))));
+        assert_eq!(HttpStatus::from_code(507).unwrap().code(), 507);
+        assert_eq!(HttpStatus::from_code(0), None);
+        assert_eq!(HttpStatus::from_code(99), None);
+        assert_eq!(HttpStatus::from_code(999), None);
+    }
+
+    #[test]
+    fn reason_phrases() {
+        assert_eq!(HttpStatus::Ok.reason_phrase(), "OK");
+        assert_eq!(HttpStatus::NotFound.reason_phrase(), "Not Found");
+        assert_eq!(HttpStatus::HttpVersionNotSupported.reason_phrase(), "HTTP Version Not Supported");
+        assert_eq!(HttpStatus::from_code(418).unwrap().reason_phrase(), "Client Error");
+        assert_eq!(HttpStatus::from_code(507).unwrap().reason_phrase(), "Server Error");
+    }
 }
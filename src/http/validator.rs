@@ -6,15 +6,18 @@
 //! 
 //! This includes validating:
 //! - HTTP version support
+//! - Host header presence and uniqueness
 //! - HTTP method constraints (e.g., body presence for POST/PUT)
 //! - Content-Length header correctness
+//! - Message framing (Content-Length vs Transfer-Encoding)
 //! - Maximum allowed body size
 
-use crate::config::config;
+use crate::config::config_or_default;
 use crate::http::HttpMethod;
 use crate::http::HttpVersion;
-use crate::http::request::HttpRequest;
+use crate::http::request::{HttpRequest, parse_host};
 use crate::http::status::HttpStatus;
+use std::net::Ipv6Addr;
 
 pub enum ValidatorError {
     Error,
@@ -24,6 +27,12 @@ pub enum ValidatorError {
     MissingContentLength,
     BodyNotAllowed,
     MandatoryBody,
+    MissingHost,
+    MultipleHost,
+    InvalidHost,
+    UnknownHost,
+    ConflictingFraming,
+    UnsupportedTransferEncoding,
 }
 
 impl ValidatorError {
@@ -38,6 +47,12 @@ impl ValidatorError {
             ValidatorError::MandatoryBody => HttpStatus::BadRequest,
             ValidatorError::BodyNotAllowed => HttpStatus::BadRequest,
             ValidatorError::MissingContentLength => HttpStatus::LengthRequired,
+            ValidatorError::MissingHost => HttpStatus::BadRequest,
+            ValidatorError::MultipleHost => HttpStatus::BadRequest,
+            ValidatorError::InvalidHost => HttpStatus::BadRequest,
+            ValidatorError::UnknownHost => HttpStatus::BadRequest,
+            ValidatorError::ConflictingFraming => HttpStatus::BadRequest,
+            ValidatorError::UnsupportedTransferEncoding => HttpStatus::BadRequest,
         }
     }
 }
@@ -50,7 +65,7 @@ impl Validator {
     fn validate_http_version(v: (u8, u8)) -> Result<(), ValidatorError> {
         match HttpVersion::is_valid(v) {
             Ok(http_v) => {
-                if http_v <= config().http_version {
+                if http_v <= config_or_default().http_version {
                     Ok(())
                 } else {
                     Err(ValidatorError::HttpVersionNotSupported)
@@ -60,15 +75,88 @@ impl Validator {
         }
     }
 
+    /// Validates the `Host` header.
+    /// HTTP/1.1 and later requests must carry exactly one non-empty `Host` header
+    /// whereas it is optional for older versions.
+    ///
+    /// The host must be a hostname or an IP literal with an optional port, and fit within
+    /// [`max_host_size`](crate::config::ServerConfig::max_host_size) bytes, as it may later be
+    /// used to build URLs such as redirections.
+    /// When `allowed_hosts` is not empty, the host (without its port) must be part of it.
+    /// (see [`allowed_hosts`](crate::config::ServerConfig::allowed_hosts))
+    fn validate_host(
+        req: &HttpRequest,
+        allowed_hosts: &[String],
+        max_host_size: usize,
+    ) -> Result<(), ValidatorError> {
+        let mut hosts = req.headers.get_all("Host");
+        let host = hosts.next();
+        if hosts.next().is_some() {
+            return Err(ValidatorError::MultipleHost);
+        }
+
+        let host = match host {
+            Some(h) if !h.is_empty() => h,
+            _ if req.http_version >= (1, 1) => return Err(ValidatorError::MissingHost),
+            _ => return Ok(()),
+        };
+
+        if host.len() > max_host_size {
+            return Err(ValidatorError::InvalidHost);
+        }
+
+        let hostname = match parse_host(host) {
+            // brackets are reserved to IPv6 literals
+            Some((hostname, _)) if host.starts_with('[') && hostname.parse::<Ipv6Addr>().is_ok() => {
+                hostname
+            }
+            Some((hostname, _)) if !host.starts_with('[') && is_valid_hostname(hostname) => hostname,
+            _ => return Err(ValidatorError::InvalidHost),
+        };
+
+        if allowed_hosts.is_empty() || allowed_hosts.iter().any(|h| h.eq_ignore_ascii_case(hostname)) {
+            Ok(())
+        } else {
+            Err(ValidatorError::UnknownHost)
+        }
+    }
+
+    /// Validates the body framing headers.
+    /// A request carrying both `Content-Length` and `Transfer-Encoding` is rejected
+    /// as it is a vector for request smuggling (RFC 7230 section 3.3.3).
+    /// Only the `chunked` and `identity` transfer codings are supported.
+    fn validate_framing(req: &HttpRequest) -> Result<(), ValidatorError> {
+        let mut transfer_encodings = req.headers.get_all("Transfer-Encoding").peekable();
+        if transfer_encodings.peek().is_none() {
+            return Ok(());
+        }
+
+        if req.headers.get("Content-Length").is_some() {
+            return Err(ValidatorError::ConflictingFraming);
+        }
+
+        let supported = transfer_encodings
+            .flat_map(|v| v.split(','))
+            .map(|coding| coding.trim())
+            .all(|coding| coding.eq_ignore_ascii_case("chunked") || coding.eq_ignore_ascii_case("identity"));
+
+        if supported {
+            Ok(())
+        } else {
+            Err(ValidatorError::UnsupportedTransferEncoding)
+        }
+    }
+
     /// Applies method-specific validation rules.
-    /// GET/HEAD must not have a body whereas POST/PUT must have one.
-    /// Other methods are not constrained.
+    /// GET/HEAD and TRACE must not have a body whereas POST/PUT must have one.
+    /// DELETE and OPTIONS may optionally have one, and CONNECT is left to the router
+    /// as it is not supported.
     fn validate_http_method(
         content_length: Option<usize>,
         method: &HttpMethod,
     ) -> Result<(), ValidatorError> {
         match method {
-            HttpMethod::Get | HttpMethod::Head => match content_length {
+            HttpMethod::Get | HttpMethod::Head | HttpMethod::Trace => match content_length {
                 Some(n) if n > 0 => Err(ValidatorError::BodyNotAllowed),
                 _ => Ok(()),
             },
@@ -85,6 +173,8 @@ impl Validator {
     /// public interface to enforce all validations on an `HttpRequest`
     pub fn validate_request(req: &HttpRequest) -> Result<(), ValidatorError> {
         Self::validate_http_version(req.http_version)?;
+        Self::validate_host(req, &config_or_default().allowed_hosts, config_or_default().max_host_size)?;
+        Self::validate_framing(req)?;
 
         let content_length = req
             .headers
@@ -93,12 +183,214 @@ impl Validator {
             .transpose()
             .map_err(|_| ValidatorError::MalformedHeaderField)?;
 
-        Self::validate_http_method(content_length, &req.method)?;
+        // the methods without a body cannot announce one with Transfer-Encoding either
+        if matches!(req.method, HttpMethod::Get | HttpMethod::Head | HttpMethod::Trace)
+            && req.headers.get("Transfer-Encoding").is_some()
+        {
+            return Err(ValidatorError::BodyNotAllowed);
+        }
+
+        // a close-delimited body is only known once the connection is closed,
+        // and the length of a chunked one once it is received
+        if !req.is_close_delimited() && !req.is_chunked() {
+            Self::validate_http_method(content_length, &req.method)?;
+        }
 
-        if content_length.is_some() && content_length > Some(config().max_body_size) {
+        if content_length.is_some() && content_length > Some(config_or_default().max_body_size) {
             return Err(ValidatorError::PayloadTooLarge);
         }
 
         Ok(())
     }
 }
+
+/// Checks that a hostname is a sequence of dot-separated labels made of
+/// ASCII letters, digits, hyphens and underscores, which includes IPv4 addresses.
+fn is_valid_hostname(hostname: &str) -> bool {
+    hostname.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(version: (u8, u8), hosts: &[&str]) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.method = HttpMethod::Get;
+        req.http_version = version;
+        for host in hosts {
+            req.headers.append_raw("Host", host);
+        }
+        req
+    }
+
+    mod host {
+        use super::*;
+
+        #[test]
+        fn missing_host() {
+            let req = request((1, 1), &[]);
+            assert!(matches!(
+                Validator::validate_host(&req, &[], 255),
+                Err(ValidatorError::MissingHost)
+            ));
+
+            let req = request((1, 0), &[]);
+            assert!(Validator::validate_host(&req, &[], 255).is_ok());
+        }
+
+        #[test]
+        fn empty_host() {
+            let req = request((1, 1), &[""]);
+            assert!(matches!(
+                Validator::validate_host(&req, &[], 255),
+                Err(ValidatorError::MissingHost)
+            ));
+
+            let req = request((1, 0), &[""]);
+            assert!(Validator::validate_host(&req, &[], 255).is_ok());
+        }
+
+        #[test]
+        fn valid_host() {
+            let req = request((1, 1), &["example.com"]);
+            assert!(Validator::validate_host(&req, &[], 255).is_ok());
+
+            let req = request((1, 0), &["example.com"]);
+            assert!(Validator::validate_host(&req, &[], 255).is_ok());
+        }
+
+        #[test]
+        fn multiple_hosts() {
+            let req = request((1, 1), &["example.com", "evil.com"]);
+            assert!(matches!(
+                Validator::validate_host(&req, &[], 255),
+                Err(ValidatorError::MultipleHost)
+            ));
+        }
+
+        #[test]
+        fn allowed_hosts() {
+            let allowed = vec!["example.com".to_string()];
+
+            let req = request((1, 1), &["example.com:8080"]);
+            assert!(Validator::validate_host(&req, &allowed, 255).is_ok());
+
+            let req = request((1, 1), &["other.com"]);
+            assert!(matches!(
+                Validator::validate_host(&req, &allowed, 255),
+                Err(ValidatorError::UnknownHost)
+            ));
+        }
+
+        #[test]
+        fn invalid_host() {
+            for host in ["exa mple.com", "example.com/path", "evil.com@example.com", "a..b", "[zz]:80"] {
+                let req = request((1, 1), &[host]);
+                assert!(
+                    matches!(Validator::validate_host(&req, &[], 255), Err(ValidatorError::InvalidHost)),
+                    "{host} should be rejected"
+                );
+            }
+
+            for host in ["example.com", "my_host-1.local:8080", "127.0.0.1", "[::1]:443"] {
+                let req = request((1, 1), &[host]);
+                assert!(Validator::validate_host(&req, &[], 255).is_ok(), "{host} should pass");
+            }
+        }
+
+        #[test]
+        fn too_long_host() {
+            let req = request((1, 1), &[&"a".repeat(16)]);
+            assert!(matches!(
+                Validator::validate_host(&req, &[], 15),
+                Err(ValidatorError::InvalidHost)
+            ));
+            assert!(Validator::validate_host(&req, &[], 16).is_ok());
+        }
+    }
+
+    mod framing {
+        use super::*;
+
+        #[test]
+        fn content_length_and_transfer_encoding() {
+            let mut req = request((1, 1), &["example.com"]);
+            req.headers.set_raw("Content-Length", "5");
+            req.headers.set_raw("Transfer-Encoding", "chunked");
+            assert!(matches!(
+                Validator::validate_framing(&req),
+                Err(ValidatorError::ConflictingFraming)
+            ));
+        }
+
+        #[test]
+        fn unknown_transfer_coding() {
+            let mut req = request((1, 1), &["example.com"]);
+            req.headers.set_raw("Transfer-Encoding", "gzip, chunked");
+            assert!(matches!(
+                Validator::validate_framing(&req),
+                Err(ValidatorError::UnsupportedTransferEncoding)
+            ));
+        }
+
+        #[test]
+        fn supported_transfer_codings() {
+            let mut req = request((1, 1), &["example.com"]);
+            req.headers.set_raw("Transfer-Encoding", "Chunked");
+            assert!(Validator::validate_framing(&req).is_ok());
+
+            req.headers.set_raw("Transfer-Encoding", "identity");
+            assert!(Validator::validate_framing(&req).is_ok());
+        }
+
+        #[test]
+        fn content_length_only() {
+            let mut req = request((1, 1), &["example.com"]);
+            req.headers.set_raw("Content-Length", "5");
+            assert!(Validator::validate_framing(&req).is_ok());
+        }
+    }
+
+    mod method {
+        use super::*;
+
+        #[test]
+        fn trace_with_body() {
+            assert!(matches!(
+                Validator::validate_http_method(Some(5), &HttpMethod::Trace),
+                Err(ValidatorError::BodyNotAllowed)
+            ));
+            assert!(Validator::validate_http_method(Some(0), &HttpMethod::Trace).is_ok());
+            assert!(Validator::validate_http_method(None, &HttpMethod::Trace).is_ok());
+        }
+
+        #[test]
+        fn chunked_body_not_allowed() {
+            for method in [HttpMethod::Get, HttpMethod::Head, HttpMethod::Trace] {
+                let mut req = request((1, 1), &["example.com"]);
+                req.method = method;
+                req.headers.set_raw("Transfer-Encoding", "chunked");
+                assert!(matches!(Validator::validate_request(&req), Err(ValidatorError::BodyNotAllowed)));
+            }
+
+            let mut req = request((1, 1), &["example.com"]);
+            req.method = HttpMethod::Post;
+            req.headers.set_raw("Transfer-Encoding", "chunked");
+            assert!(Validator::validate_request(&req).is_ok());
+        }
+
+        #[test]
+        fn optional_body() {
+            for method in [HttpMethod::Delete, HttpMethod::Options] {
+                assert!(Validator::validate_http_method(Some(5), &method).is_ok());
+                assert!(Validator::validate_http_method(None, &method).is_ok());
+            }
+        }
+    }
+}
@@ -2,11 +2,15 @@ mod config;
 mod handler;
 mod http;
 mod net;
+mod util;
+
+#[cfg(all(feature = "sendfile", not(target_os = "linux")))]
+compile_error!("the `sendfile` feature is only supported on Linux");
 
 use std::time::Duration;
 
 use async_std::task;
-use config::{ServerConfig, config, set_config};
+use config::{CONFIG_PATH_ENV, ServerConfig, config, config_path, set_config};
 use net::server::Server;
 
 
@@ -18,9 +22,11 @@ const RESET: &str = "\x1b[0m";
 // Server startup message:
 //
 // <server_name> ready in <time> ms
-// ➜  Local:      http://<address>:<port>
+// ➜  Local:      http(s)://<address>:<port>
+// ➜  Unix:       <unix_socket> (when configured)
 // ➜  File root:  <static_files_root>
-fn ready_msg(time: Duration) {
+// ➜  Config:     <config file>
+fn ready_msg(time: Duration, config_path: &str) {
     let cfg = config();
 
     println!();
@@ -29,24 +35,49 @@ fn ready_msg(time: Duration) {
         cfg.server_name,
         time.as_millis()
     );
+    #[cfg(feature = "tls")]
+    let scheme = if cfg.tls.is_some() { "https" } else { "http" };
+    #[cfg(not(feature = "tls"))]
+    let scheme = "http";
+
     println!(
-        "{GREEN}➜{RESET}  {WHITE}Local{RESET}:      {BLUE}http://{}:{}{RESET}",
+        "{GREEN}➜{RESET}  {WHITE}Local{RESET}:      {BLUE}{}://{}:{}{RESET}",
+        scheme,
         cfg.address,
         cfg.port
     );
+    #[cfg(unix)]
+    if let Some(path) = &cfg.unix_socket {
+        println!("{GREEN}➜{RESET}  {WHITE}Unix{RESET}:       {BLUE}{}{RESET}", path);
+    }
     println!(
         "{GREEN}➜{RESET}  {WHITE}File root{RESET}:  {WHITE}{}{RESET}",
         cfg.static_files_root
     );
+    println!("{GREEN}➜{RESET}  {WHITE}Config{RESET}:     {WHITE}{}{RESET}", config_path);
 }
 
 fn main() -> std::io::Result<()> {
     // Initialize configuration
     let start = std::time::Instant::now();
-    let cfg = ServerConfig::from_file("config.toml");
+    let path = config_path(std::env::args().nth(1), std::env::var(CONFIG_PATH_ENV).ok());
+    let cfg = ServerConfig::from_file(&path).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    if let Err(err) = cfg.validate() {
+        eprintln!("Invalid configuration {}: {err}", path);
+        std::process::exit(1);
+    }
+    // before any task is spawned
+    net::runtime::configure(cfg.worker_threads);
     set_config(cfg);
-    let server = Server;
-    ready_msg(start.elapsed());
+    handler::init();
+    let server = Server::new()?;
+    #[cfg(unix)]
+    net::signal::reload_on_sighup(path.clone());
+    handler::health::mark_ready();
+    ready_msg(start.elapsed(), &path);
     task::block_on(server.run())?;
     Ok(())
 }
@@ -0,0 +1,238 @@
+//! Access log written after each response.
+//!
+//! One line is printed on the standard output per handled request, either in the
+//! [Common Log Format](https://en.wikipedia.org/wiki/Common_Log_Format) extended with
+//! the request duration, or as a JSON object. The format is selected with
+//! [`access_log`](crate::config::ServerConfig::access_log).
+
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::http::request::HttpRequest;
+use crate::http::response::HttpResponse;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    Off,
+    Common,
+    Json,
+}
+
+/// Data recorded for a single request.
+/// The request fields are `None` when the request could not be parsed.
+pub struct AccessLogEntry<'a> {
+    /// Address of the client, unknown for Unix socket connections
+    pub client: Option<IpAddr>,
+    pub time: SystemTime,
+    pub method: Option<&'a str>,
+    pub target: Option<&'a str>,
+    pub http_version: Option<(u8, u8)>,
+    pub status: usize,
+    /// Size of the response body, including a file sent with `sendfile`
+    pub bytes: usize,
+    /// Whether the client did not receive the whole response
+    pub truncated: bool,
+    pub elapsed: Duration,
+}
+
+impl<'a> AccessLogEntry<'a> {
+    pub fn new(
+        client: Option<IpAddr>,
+        req: Option<&'a HttpRequest>,
+        res: &HttpResponse,
+        truncated: bool,
+        elapsed: Duration,
+    ) -> Self {
+        Self {
+            client,
+            time: SystemTime::now(),
+            method: req.map(|r| r.method.as_str()),
+            target: req.map(|r| r.raw_target()),
+            http_version: req.map(|r| r.http_version),
+            status: res.status.code() as usize,
+            bytes: body_len(res),
+            truncated,
+            elapsed,
+        }
+    }
+
+    /// Formats the entry, or returns `None` when the access log is disabled.
+    pub fn format(&self, format: &AccessLogFormat) -> Option<String> {
+        let duration_ms = self.elapsed.as_secs_f64() * 1000.0;
+
+        match format {
+            AccessLogFormat::Off => None,
+
+            // <client> - - [<day>/<month>/<year>:<time> +0000] "<request line>" <status> <bytes> <duration>ms
+            // followed by ` truncated` when the client did not receive the whole response
+            AccessLogFormat::Common => {
+                let request_line = match (self.method, self.target, self.http_version) {
+                    (Some(m), Some(t), Some((maj, min))) => format!("{m} {t} HTTP/{maj}.{min}"),
+                    _ => "-".to_string(),
+                };
+                let client = self.client.map(|ip| ip.to_string());
+                Some(format!(
+                    "{} - - [{}] \"{}\" {} {} {:.3}ms{}",
+                    client.as_deref().unwrap_or("-"),
+                    common_log_date(self.time),
+                    request_line,
+                    self.status,
+                    self.bytes,
+                    duration_ms,
+                    if self.truncated { " truncated" } else { "" },
+                ))
+            }
+
+            AccessLogFormat::Json => {
+                let string_or_null = |v: Option<&str>| match v {
+                    Some(v) => format!("\"{}\"", json_escape(v)),
+                    None => "null".to_string(),
+                };
+                let client = self.client.map(|ip| ip.to_string());
+                Some(format!(
+                    "{{\"client\":{},\"time\":\"{}\",\"method\":{},\"target\":{},\"status\":{},\"bytes\":{},\"duration_ms\":{:.3}{}}}",
+                    string_or_null(client.as_deref()),
+                    httpdate::fmt_http_date(self.time),
+                    string_or_null(self.method),
+                    string_or_null(self.target),
+                    self.status,
+                    self.bytes,
+                    duration_ms,
+                    if self.truncated { ",\"truncated\":true" } else { "" },
+                ))
+            }
+        }
+    }
+}
+
+/// Size of the body of a response, whether it is held in memory or sent from a file.
+fn body_len(res: &HttpResponse) -> usize {
+    #[cfg(feature = "sendfile")]
+    if let Some(file) = &res.file {
+        return file.len as usize;
+    }
+    res.body.len()
+}
+
+/// Prints the access log line of a handled request.
+pub fn log(
+    format: &AccessLogFormat,
+    client: Option<IpAddr>,
+    req: Option<&HttpRequest>,
+    res: &HttpResponse,
+    truncated: bool,
+    elapsed: Duration,
+) {
+    if let Some(line) = AccessLogEntry::new(client, req, res, truncated, elapsed).format(format) {
+        println!("{line}");
+    }
+}
+
+/// Converts an HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`)
+/// to the common log format (`06/Nov/1994:08:49:37 +0000`).
+fn common_log_date(time: SystemTime) -> String {
+    let date = httpdate::fmt_http_date(time);
+    match date.split(' ').collect::<Vec<_>>()[..] {
+        [_, day, month, year, time, _] => format!("{day}/{month}/{year}:{time} +0000"),
+        _ => date,
+    }
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn entry() -> AccessLogEntry<'static> {
+        AccessLogEntry {
+            client: Some(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1))),
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(784111777),
+            method: Some("GET"),
+            target: Some("/index.html?q=\"x\""),
+            http_version: Some((1, 1)),
+            status: 200,
+            bytes: 1234,
+            truncated: false,
+            elapsed: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn common_format() {
+        assert_eq!(
+            entry().format(&AccessLogFormat::Common).unwrap(),
+            "192.168.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /index.html?q=\"x\" HTTP/1.1\" 200 1234 1.500ms"
+        );
+    }
+
+    #[test]
+    fn json_format() {
+        assert_eq!(
+            entry().format(&AccessLogFormat::Json).unwrap(),
+            "{\"client\":\"192.168.0.1\",\"time\":\"Sun, 06 Nov 1994 08:49:37 GMT\",\"method\":\"GET\",\
+             \"target\":\"/index.html?q=\\\"x\\\"\",\"status\":200,\"bytes\":1234,\"duration_ms\":1.500}"
+        );
+    }
+
+    #[test]
+    fn unparsed_request() {
+        let entry = AccessLogEntry { method: None, target: None, http_version: None, status: 400, ..entry() };
+        assert!(entry.format(&AccessLogFormat::Common).unwrap().contains("\"-\" 400"));
+        assert!(entry.format(&AccessLogFormat::Json).unwrap().contains("\"method\":null"));
+    }
+
+    #[test]
+    fn verbatim_target() {
+        let mut req = HttpRequest::new();
+        req.method = crate::http::HttpMethod::Get;
+        req.http_version = (1, 1);
+        req.set_target("/docs/../caf%C3%A9.txt?q=a%20b").unwrap();
+        let entry = AccessLogEntry::new(None, Some(&req), &HttpResponse::new(), false, Duration::ZERO);
+        assert!(entry.format(&AccessLogFormat::Common).unwrap().contains("\"GET /docs/../caf%C3%A9.txt?q=a%20b HTTP/1.1\""));
+    }
+
+    #[test]
+    fn truncated_response() {
+        let entry = AccessLogEntry { truncated: true, ..entry() };
+        assert!(entry.format(&AccessLogFormat::Common).unwrap().ends_with(" 200 1234 1.500ms truncated"));
+        assert!(entry.format(&AccessLogFormat::Json).unwrap().ends_with(",\"duration_ms\":1.500,\"truncated\":true}"));
+    }
+
+    #[cfg(feature = "sendfile")]
+    #[test]
+    fn file_body_size() {
+        let path = std::env::temp_dir().join(format!("rustynet-access-log-{}.txt", std::process::id()));
+        std::fs::write(&path, b"content").unwrap();
+        let mut res = HttpResponse::new();
+        res.set_file(std::fs::File::open(&path).unwrap(), 7);
+        assert_eq!(AccessLogEntry::new(None, None, &res, false, Duration::ZERO).bytes, 7);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unix_socket_client() {
+        let entry = AccessLogEntry { client: None, ..entry() };
+        assert!(entry.format(&AccessLogFormat::Common).unwrap().starts_with("- - - ["));
+        assert!(entry.format(&AccessLogFormat::Json).unwrap().starts_with("{\"client\":null,"));
+    }
+
+    #[test]
+    fn disabled() {
+        assert_eq!(entry().format(&AccessLogFormat::Off), None);
+    }
+}
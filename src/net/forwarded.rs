@@ -0,0 +1,183 @@
+//! Resolution of the client address of the requests relayed by trusted proxies.
+//!
+//! Behind a load balancer or a reverse proxy, the TCP peer is the proxy and the address
+//! of the client is given by the `X-Forwarded-For` header, to which each proxy appends
+//! the address it received the request from. The header is only read when the peer is
+//! one of the [`trusted_proxies`](crate::config::ServerConfig::trusted_proxies), as any
+//! client can send it otherwise.
+//!
+//! The entries are walked from the right, the ones of trusted proxies being skipped:
+//! the first other entry is the client. The entries on its left were received by the
+//! client side and may be forged, which is why the leftmost entry is only used when all
+//! the others are trusted proxies as well.
+
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::http::request::HttpRequest;
+
+/// A block of IP addresses such as `10.0.0.0/8` or `2001:db8::/32`,
+/// a single address being a block of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid CIDR block `{s}`");
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse::<u8>().ok().filter(|len| *len <= max_len).ok_or_else(invalid)?,
+            None => max_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // the mask of a zero prefix would overflow the shift
+        let mask = |bits: u32| match self.prefix_len {
+            0 => 0,
+            len => u128::MAX << (bits - u32::from(len)),
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(block), IpAddr::V4(ip)) => {
+                let mask = mask(32) as u32;
+                u32::from(block) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(block), IpAddr::V6(ip)) => {
+                let mask = mask(128);
+                u128::from(block) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_trusted(ip: IpAddr, trusted: &[Cidr]) -> bool {
+    trusted.iter().any(|block| block.contains(ip))
+}
+
+/// Whether the `X-Forwarded-For` header of the requests received from the peer is read.
+pub fn is_trusted_proxy(peer: Option<IpAddr>, trusted: &[Cidr]) -> bool {
+    peer.is_some_and(|ip| is_trusted(ip, trusted))
+}
+
+/// Returns the address of the client of a request, the peer unless it is a trusted proxy.
+/// An invalid entry of `X-Forwarded-For` ends the walk, the last proxy reached being
+/// returned as the client.
+pub fn client_addr(req: &HttpRequest, trusted: &[Cidr]) -> Option<IpAddr> {
+    let peer = req.peer_addr;
+    if !is_trusted_proxy(peer, trusted) {
+        return peer;
+    }
+
+    let entries: Vec<&str> = req
+        .headers
+        .get_all("X-Forwarded-For")
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+
+    let mut client = peer;
+    for entry in entries.iter().rev() {
+        let Ok(ip) = entry.parse::<IpAddr>() else {
+            break;
+        };
+        client = Some(ip);
+        if !is_trusted(ip, trusted) {
+            break;
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(blocks: &[&str]) -> Vec<Cidr> {
+        blocks.iter().map(|b| b.parse().unwrap()).collect()
+    }
+
+    fn request(peer: &str, forwarded_for: &[&str]) -> HttpRequest {
+        let mut req = HttpRequest::new();
+        req.peer_addr = Some(peer.parse().unwrap());
+        for value in forwarded_for {
+            req.headers.append_raw("X-Forwarded-For", value);
+        }
+        req
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn cidr_blocks() {
+        let block: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(block.contains("10.20.30.40".parse().unwrap()));
+        assert!(!block.contains("11.0.0.1".parse().unwrap()));
+        assert!(!block.contains("::1".parse().unwrap()));
+
+        let single: Cidr = "192.168.1.1".parse().unwrap();
+        assert!(single.contains("192.168.1.1".parse().unwrap()));
+        assert!(!single.contains("192.168.1.2".parse().unwrap()));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8:1::1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains("8.8.8.8".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("proxy".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let trusted = cidrs(&["10.0.0.0/8"]);
+        // a client cannot choose its address by sending the header
+        let req = request("203.0.113.7", &["198.51.100.1"]);
+        assert_eq!(client_addr(&req, &trusted), ip("203.0.113.7"));
+        assert_eq!(client_addr(&req, &[]), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn trusted_peer() {
+        let trusted = cidrs(&["10.0.0.0/8"]);
+        let req = request("10.0.0.1", &["203.0.113.7"]);
+        assert_eq!(client_addr(&req, &trusted), ip("203.0.113.7"));
+
+        // the entries forged by the client are on the left of its own address
+        let req = request("10.0.0.1", &["198.51.100.1, 203.0.113.7", "10.0.0.2"]);
+        assert_eq!(client_addr(&req, &trusted), ip("203.0.113.7"));
+
+        // the leftmost entry is the client when all the proxies are trusted
+        let req = request("10.0.0.1", &["10.0.0.3, 10.0.0.2"]);
+        assert_eq!(client_addr(&req, &trusted), ip("10.0.0.3"));
+
+        let req = request("10.0.0.1", &["garbage, 10.0.0.2"]);
+        assert_eq!(client_addr(&req, &trusted), ip("10.0.0.2"));
+        assert_eq!(client_addr(&request("10.0.0.1", &[]), &trusted), ip("10.0.0.1"));
+    }
+}
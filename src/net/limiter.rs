@@ -0,0 +1,65 @@
+//! Limit of the connections handled simultaneously.
+//!
+//! Each connection holds a [`ConnectionPermit`] while it is handled, releasing
+//! its slot when dropped. Once all the slots are taken, new connections are
+//! answered `503 Service Unavailable` by the server instead of being handled,
+//! bounding the memory and file descriptors used under a connection flood.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct ConnectionLimiter {
+    active: AtomicUsize,
+    /// Maximum number of simultaneous connections, 0 meaning unlimited
+    max: usize,
+}
+
+/// Slot of a connection being handled, released on drop.
+pub struct ConnectionPermit(Arc<ConnectionLimiter>);
+
+impl ConnectionLimiter {
+    pub fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            active: AtomicUsize::new(0),
+            max,
+        })
+    }
+
+    /// Takes a slot for a new connection, or returns `None` when all of them are taken.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<ConnectionPermit> {
+        let taken = self.active.fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+            (self.max == 0 || active < self.max).then_some(active + 1)
+        });
+        taken.ok().map(|_| ConnectionPermit(Arc::clone(self)))
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extra_connection_throttled() {
+        let limiter = ConnectionLimiter::new(2);
+        let first = limiter.try_acquire().unwrap();
+        let _second = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+
+        // a slot is released once a connection is done
+        drop(first);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn unlimited() {
+        let limiter = ConnectionLimiter::new(0);
+        let permits: Vec<_> = (0..1000).map(|_| limiter.try_acquire().unwrap()).collect();
+        assert_eq!(permits.len(), 1000);
+    }
+}
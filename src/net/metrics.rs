@@ -0,0 +1,174 @@
+//! Request metrics exposed in the Prometheus text format.
+//!
+//! The server records every exchange in [`METRICS`]: the number of requests, the
+//! responses by status class, the bytes received and sent, and a histogram of the
+//! request durations. Recording only updates atomic counters, so it is always done,
+//! whereas the endpoint rendering them is enabled with
+//! [`metrics`](crate::config::ServerConfig::metrics).
+
+use async_std::io::{Read, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::http::status::HttpStatus;
+
+/// Upper bounds (in seconds) of the request duration histogram buckets.
+const DURATION_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    requests: AtomicU64,
+    /// Responses by status class, from 1xx to 5xx
+    responses: [AtomicU64; 5],
+    received_bytes: AtomicU64,
+    sent_bytes: AtomicU64,
+    /// Non-cumulative counts of each bucket, the last one counting the durations above all bounds
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_us: AtomicU64,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            responses: [const { AtomicU64::new(0) }; 5],
+            received_bytes: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            duration_buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len() + 1],
+            duration_sum_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a request answered with `status` after `elapsed`.
+    pub fn record(&self, status: HttpStatus, received: u64, sent: u64, elapsed: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let class = (status.code() as usize / 100).clamp(1, 5) - 1;
+        self.responses[class].fetch_add(1, Ordering::Relaxed);
+        self.received_bytes.fetch_add(received, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(sent, Ordering::Relaxed);
+
+        let secs = elapsed.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+
+        out.push_str("# HELP rustynet_requests_total Requests handled.\n");
+        out.push_str("# TYPE rustynet_requests_total counter\n");
+        out.push_str(&format!("rustynet_requests_total {}\n", load(&self.requests)));
+
+        out.push_str("# HELP rustynet_responses_total Responses sent, by status class.\n");
+        out.push_str("# TYPE rustynet_responses_total counter\n");
+        for (i, count) in self.responses.iter().enumerate() {
+            out.push_str(&format!("rustynet_responses_total{{class=\"{}xx\"}} {}\n", i + 1, load(count)));
+        }
+
+        out.push_str("# HELP rustynet_received_bytes_total Bytes received from clients.\n");
+        out.push_str("# TYPE rustynet_received_bytes_total counter\n");
+        out.push_str(&format!("rustynet_received_bytes_total {}\n", load(&self.received_bytes)));
+
+        out.push_str("# HELP rustynet_sent_bytes_total Bytes sent to clients.\n");
+        out.push_str("# TYPE rustynet_sent_bytes_total counter\n");
+        out.push_str(&format!("rustynet_sent_bytes_total {}\n", load(&self.sent_bytes)));
+
+        out.push_str("# HELP rustynet_request_duration_seconds Time to handle a request.\n");
+        out.push_str("# TYPE rustynet_request_duration_seconds histogram\n");
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            cumulative += load(count);
+            out.push_str(&format!("rustynet_request_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}\n"));
+        }
+        cumulative += load(&self.duration_buckets[DURATION_BUCKETS.len()]);
+        out.push_str(&format!("rustynet_request_duration_seconds_bucket{{le=\"+Inf\"}} {cumulative}\n"));
+        let sum = load(&self.duration_sum_us) as f64 / 1_000_000.0;
+        out.push_str(&format!("rustynet_request_duration_seconds_sum {sum}\n"));
+        out.push_str(&format!("rustynet_request_duration_seconds_count {cumulative}\n"));
+        out
+    }
+}
+
+/// Stream wrapper counting the bytes read from and written to the inner stream.
+pub struct CountingStream<S> {
+    inner: S,
+    pub read: u64,
+    pub written: u64,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, read: 0, written: 0 }
+    }
+
+    #[cfg(feature = "sendfile")]
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Read + Unpin> Read for CountingStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.read += n as u64;
+        }
+        poll
+    }
+}
+
+impl<S: Write + Unpin> Write for CountingStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.written += n as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_format() {
+        let metrics = Metrics::new();
+        metrics.record(HttpStatus::Ok, 40, 200, Duration::from_millis(3));
+        metrics.record(HttpStatus::NotFound, 38, 150, Duration::from_millis(70));
+        metrics.record(HttpStatus::Ok, 40, 200, Duration::from_secs(20));
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE rustynet_requests_total counter\nrustynet_requests_total 3\n"));
+        assert!(out.contains("rustynet_responses_total{class=\"2xx\"} 2\n"));
+        assert!(out.contains("rustynet_responses_total{class=\"4xx\"} 1\n"));
+        assert!(out.contains("rustynet_responses_total{class=\"5xx\"} 0\n"));
+        assert!(out.contains("rustynet_received_bytes_total 118\n"));
+        assert!(out.contains("rustynet_sent_bytes_total 550\n"));
+
+        // buckets are cumulative
+        assert!(out.contains("rustynet_request_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(out.contains("rustynet_request_duration_seconds_bucket{le=\"0.1\"} 2\n"));
+        assert!(out.contains("rustynet_request_duration_seconds_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("rustynet_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("rustynet_request_duration_seconds_sum 20.073\n"));
+        assert!(out.contains("rustynet_request_duration_seconds_count 3\n"));
+    }
+}
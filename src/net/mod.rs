@@ -1 +1,17 @@
+pub mod access_log;
+pub mod forwarded;
+pub mod limiter;
+pub mod metrics;
+pub mod rate_limit;
+pub mod runtime;
+#[cfg(feature = "sendfile")]
+pub mod sendfile;
 pub mod server;
+#[cfg(unix)]
+pub mod signal;
+pub mod socket;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod tunnel;
+#[cfg(unix)]
+pub mod unix;
@@ -0,0 +1,174 @@
+//! Rate limiting of the requests per client IP address.
+//!
+//! Each client gets a token bucket holding up to `burst` tokens and refilled at
+//! `requests_per_second`. Every connection takes a token, and once the bucket is
+//! empty the server answers `429 Too Many Requests` with a `Retry-After` header
+//! until a token is available again. Connections from a Unix socket have no
+//! address and are not limited.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use async_std::sync::Mutex;
+use serde::Deserialize;
+
+/// Interval at which the buckets of idle clients are evicted
+pub const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Tokens given back to each client per second
+    pub requests_per_second: f64,
+    /// Maximum number of requests a client can send at once
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 10.0,
+            burst: 20,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(cfg: &RateLimitConfig) -> Self {
+        Self {
+            rate: cfg.requests_per_second,
+            burst: f64::from(cfg.burst),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of the client.
+    /// Returns the time to wait before the next token when the bucket is empty.
+    pub async fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now()).await
+    }
+
+    async fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().await;
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.rate > 0.0 {
+            // a rate so low that the wait overflows
+            Err(Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.rate).unwrap_or(Duration::MAX))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    /// Removes the buckets which have been refilled since their last use,
+    /// a new client getting the same full bucket.
+    pub async fn evict_idle(&self) {
+        self.evict_idle_at(Instant::now()).await
+    }
+
+    async fn evict_idle_at(&self, now: Instant) {
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.lock().await.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * rate < burst
+        });
+    }
+}
+
+/// Value of the `Retry-After` header for a wait, in whole seconds rounded up.
+pub fn retry_after(wait: Duration) -> String {
+    let secs = wait.as_secs().saturating_add(u64::from(wait.subsec_nanos() > 0));
+    secs.max(1).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+
+    fn limiter(requests_per_second: f64, burst: u32) -> RateLimiter {
+        RateLimiter::new(&RateLimitConfig {
+            requests_per_second,
+            burst,
+        })
+    }
+
+    #[test]
+    fn limits_per_client() {
+        task::block_on(async {
+            let limiter = limiter(1.0, 3);
+            let (client, other) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+            let now = Instant::now();
+
+            for _ in 0..3 {
+                assert!(limiter.check_at(client, now).await.is_ok());
+            }
+            let wait = limiter.check_at(client, now).await.unwrap_err();
+            assert_eq!(retry_after(wait), "1");
+            assert!(limiter.check_at(other, now).await.is_ok());
+
+            // a token is given back after a second
+            let later = now + Duration::from_secs(1);
+            assert!(limiter.check_at(client, later).await.is_ok());
+            assert!(limiter.check_at(client, later).await.is_err());
+        });
+    }
+
+    #[test]
+    fn idle_buckets_evicted() {
+        task::block_on(async {
+            let limiter = limiter(2.0, 4);
+            let (idle, active) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+            let now = Instant::now();
+            limiter.check_at(idle, now).await.unwrap();
+            for _ in 0..4 {
+                limiter.check_at(active, now + Duration::from_secs(1)).await.unwrap();
+            }
+
+            // the idle bucket is full again while the other bucket is still being refilled
+            limiter.evict_idle_at(now + Duration::from_secs(1)).await;
+            let buckets = limiter.buckets.lock().await;
+            assert!(!buckets.contains_key(&idle));
+            assert!(buckets.contains_key(&active));
+        });
+    }
+
+    #[test]
+    fn very_low_rate() {
+        task::block_on(async {
+            let limiter = limiter(1e-300, 1);
+            let (client, now) = ("10.0.0.1".parse().unwrap(), Instant::now());
+            limiter.check_at(client, now).await.unwrap();
+            assert_eq!(limiter.check_at(client, now).await, Err(Duration::MAX));
+        });
+    }
+
+    #[test]
+    fn retry_after_rounding() {
+        assert_eq!(retry_after(Duration::from_millis(10)), "1");
+        assert_eq!(retry_after(Duration::from_secs(2)), "2");
+        assert_eq!(retry_after(Duration::from_millis(2500)), "3");
+    }
+}
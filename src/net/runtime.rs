@@ -0,0 +1,38 @@
+//! Sizing of the `async-std` executor.
+//!
+//! The executor threads poll the connections and run the handlers. Its size is read
+//! from the `ASYNC_STD_THREAD_COUNT` variable when the first task is spawned, so
+//! [`configure`] must be called before, and defaults to the number of CPUs.
+//!
+//! The blocking work offloaded with `task::spawn_blocking`, such as reading the static
+//! files and compressing the responses, runs on a separate pool growing on demand,
+//! which the setting does not bound.
+
+/// Variable read by `async-std` to size its executor
+const THREAD_COUNT_ENV: &str = "ASYNC_STD_THREAD_COUNT";
+
+/// Sets the number of executor threads from
+/// [`worker_threads`](crate::config::ServerConfig::worker_threads), 0 keeping the
+/// default of `async-std`. Must be called before any task is spawned.
+pub fn configure(worker_threads: usize) {
+    if let Some(count) = thread_count(worker_threads) {
+        // SAFETY: called at startup, before the executor and the other threads are started
+        unsafe { std::env::set_var(THREAD_COUNT_ENV, count) };
+    }
+}
+
+/// Value of `ASYNC_STD_THREAD_COUNT` for the configured worker threads, if any.
+fn thread_count(worker_threads: usize) -> Option<String> {
+    (worker_threads > 0).then(|| worker_threads.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worker_threads() {
+        assert_eq!(thread_count(3).as_deref(), Some("3"));
+        assert_eq!(thread_count(0), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! Zero-copy transmission of static files with `sendfile(2)`, enabled by the `sendfile` feature.
+//!
+//! The static files too large for the cache are not read into memory: their response
+//! carries the open file (see [`HttpResponse::file`]), which the kernel copies to the
+//! socket without going through the userspace buffers. This is only possible over
+//! plaintext connections and for bodies sent as is: the files sent over TLS or rewritten
+//! by a body transform are read into memory as before, and these files are never compressed.
+//!
+//! [`HttpResponse::file`]: crate::http::response::HttpResponse::file
+
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, OwnedFd};
+use std::time::Duration;
+
+use async_std::net::TcpStream;
+use async_std::os::unix::net::UnixStream;
+use async_std::task;
+
+use crate::http::response::FileBody;
+use crate::net::metrics::CountingStream;
+
+/// Largest number of bytes sent by a single call, as done by the kernel itself
+const MAX_SEND: usize = 0x7fff_f000;
+
+/// Streams whose body can be sent with `sendfile`, those writing to a socket as is.
+pub trait RawSocket {
+    fn raw_socket(&self) -> Option<BorrowedFd<'_>>;
+}
+
+impl RawSocket for TcpStream {
+    fn raw_socket(&self) -> Option<BorrowedFd<'_>> {
+        // SAFETY: the descriptor stays open while the stream is borrowed
+        Some(unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) })
+    }
+}
+
+impl RawSocket for UnixStream {
+    fn raw_socket(&self) -> Option<BorrowedFd<'_>> {
+        // SAFETY: the descriptor stays open while the stream is borrowed
+        Some(unsafe { BorrowedFd::borrow_raw(self.as_raw_fd()) })
+    }
+}
+
+/// The bytes written to a TLS stream are encrypted first.
+#[cfg(feature = "tls")]
+impl<S> RawSocket for futures_rustls::server::TlsStream<S> {
+    fn raw_socket(&self) -> Option<BorrowedFd<'_>> {
+        None
+    }
+}
+
+impl<S: RawSocket> RawSocket for CountingStream<S> {
+    fn raw_socket(&self) -> Option<BorrowedFd<'_>> {
+        self.inner().raw_socket()
+    }
+}
+
+impl<S: RawSocket + ?Sized> RawSocket for &mut S {
+    fn raw_socket(&self) -> Option<BorrowedFd<'_>> {
+        (**self).raw_socket()
+    }
+}
+
+/// Sends the file to the socket from a thread of the blocking pool, waiting at most `timeout`
+/// for the socket to accept more data each time its buffer is full.
+/// Returns the number of bytes sent along with the error interrupting the transmission,
+/// a client which stopped reading being reported as [`io::ErrorKind::TimedOut`].
+///
+/// The socket is a duplicate of the one of the connection, so that a cancelled
+/// transmission never writes to a descriptor reused meanwhile.
+pub async fn send(socket: OwnedFd, body: &FileBody, timeout: Duration) -> (u64, io::Result<()>) {
+    let file = match body.file.try_clone() {
+        Ok(file) => file,
+        Err(err) => return (0, Err(err)),
+    };
+    let len = body.len;
+    task::spawn_blocking(move || send_blocking(&socket, &file, len, timeout)).await
+}
+
+fn send_blocking(socket: &OwnedFd, file: &std::fs::File, len: u64, timeout: Duration) -> (u64, io::Result<()>) {
+    let mut offset: libc::off_t = 0;
+    while (offset as u64) < len {
+        let count = (len - offset as u64).min(MAX_SEND as u64) as usize;
+        // SAFETY: both descriptors are open for the duration of the call
+        let sent = unsafe { libc::sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut offset, count) };
+        let err = match sent {
+            -1 => io::Error::last_os_error(),
+            // the file was truncated since its length was sent
+            0 => return (offset as u64, Err(io::ErrorKind::UnexpectedEof.into())),
+            _ => continue,
+        };
+        match err.kind() {
+            io::ErrorKind::WouldBlock => {
+                if let Err(err) = wait_writable(socket, timeout) {
+                    return (offset as u64, Err(err));
+                }
+            }
+            io::ErrorKind::Interrupted => (),
+            _ => return (offset as u64, Err(err)),
+        }
+    }
+    (offset as u64, Ok(()))
+}
+
+/// Waits for the non-blocking socket to accept more data, within `timeout`.
+fn wait_writable(socket: &OwnedFd, timeout: Duration) -> io::Result<()> {
+    let mut pollfd = libc::pollfd { fd: socket.as_raw_fd(), events: libc::POLLOUT, revents: 0 };
+    let timeout_ms = timeout.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+    loop {
+        // SAFETY: a single valid pollfd is given
+        match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Err(io::ErrorKind::TimedOut.into()),
+            _ => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use async_std::prelude::*;
+
+    #[test]
+    fn file_sent_intact() {
+        let path = std::env::temp_dir().join(format!("rustynet-sendfile-{}.bin", std::process::id()));
+        // larger than the socket buffers, for the transmission to wait for the client
+        let content: Vec<u8> = (0..8 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+        let body = FileBody { file: std::fs::File::open(&path).unwrap(), len: content.len() as u64 };
+
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+
+            let received = task::spawn(async move {
+                let mut received = Vec::new();
+                client.read_to_end(&mut received).await.unwrap();
+                received
+            });
+            let socket = stream.raw_socket().unwrap().try_clone_to_owned().unwrap();
+            let (sent, result) = send(socket, &body, Duration::from_secs(5)).await;
+            result.unwrap();
+            assert_eq!(sent, content.len() as u64);
+            drop(stream);
+            assert!(received.await == content);
+        });
+        std::fs::remove_file(path).unwrap();
+    }
+}
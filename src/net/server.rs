@@ -13,6 +13,10 @@
 //! The server is fully asynchronous and leverages the `async-std` crate
 //! to provide non-blocking I/O and concurrent client handling.
 //!
+//! Connections are handled through the generic `AsyncRead + AsyncWrite` interface,
+//! so that TCP streams and TLS streams (with the `tls` feature, see
+//! [`net::tls`](crate::net::tls)) go through the same request handling.
+//!
 //! ## Request handling flow
 //!
 //! The typical lifecycle of a client connection is as follows:
@@ -26,21 +30,54 @@
 //! 5. Generate an [`HttpResponse`]
 //!    (delegated to [`handler::handle_request`](crate::handler::handle_request))
 //! 6. Serialize and write the response back to the client
+//! 7. Log the request (delegated to [`access_log`](crate::net::access_log))
 //!
 //! Errors at any stage result in appropriate HTTP error responses
 //! being generated and sent back to the client.
 
-use crate::config::config;
+use crate::config::{ServerConfig, config};
 use crate::handler;
+use crate::http::HttpMethod;
 use crate::http::parser::*;
 use crate::http::request::HttpRequest;
-use crate::http::response::HttpResponse;
+use crate::http::response::{self, HttpResponse, ResponseHeader};
+use crate::http::status::HttpStatus;
 use crate::http::validator::{Validator, ValidatorError};
-use async_std::net::{TcpListener, TcpStream};
+use crate::net::access_log;
+use crate::net::forwarded;
+use crate::net::limiter::ConnectionLimiter;
+use crate::net::metrics::{self, CountingStream};
+use crate::net::rate_limit::{self, RateLimiter};
+use crate::net::socket;
+#[cfg(feature = "sendfile")]
+use crate::net::sendfile::{self, RawSocket};
+use crate::net::tunnel::{self, TunnelConfig};
+use async_std::io::{Read, Write};
+#[cfg(any(test, feature = "tls"))]
+use async_std::net::TcpStream;
+use async_std::net::TcpListener;
 use async_std::prelude::*;
 use async_std::task;
+use std::net::IpAddr;
+use std::sync::Arc;
+#[cfg(feature = "tls")]
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tls")]
+use crate::net::tls;
+#[cfg(feature = "tls")]
+use futures_rustls::TlsAcceptor;
+#[cfg(unix)]
+use crate::net::unix;
 
-pub struct Server;
+pub struct Server {
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    /// Shared by the TCP and Unix socket connections
+    limiter: Arc<ConnectionLimiter>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
 
 /// Errors that can occur while reading and parsing an HTTP request from the stream
 /// used to interrupt the flow and return appropriate responses.
@@ -49,106 +86,1195 @@ enum ReadError {
     ConnectionClosed,
     Parser(ParserError),
     Validator(ValidatorError),
+    /// More body bytes than allowed were received, whatever the advertised length.
+    PayloadTooLarge,
+    /// The request line and headers were not received in time.
+    HeaderTimeout,
+    /// The client stopped sending the body for longer than the read timeout.
+    BodyTimeout,
+}
+
+/// Without the `sendfile` feature, the bodies are written through any stream.
+#[cfg(not(feature = "sendfile"))]
+trait RawSocket {}
+#[cfg(not(feature = "sendfile"))]
+impl<S: ?Sized> RawSocket for S {}
+
+/// Errors interrupting the write of a response, the client having received part of it at most.
+#[derive(Debug)]
+enum WriteError {
+    Io(std::io::Error),
+    /// The client did not read the response for `write_timeout`,
+    /// e.g. gone without closing the connection.
+    Timeout,
+}
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::Io(err) => write!(f, "{}", err),
+            WriteError::Timeout => write!(f, "the client stopped reading"),
+        }
+    }
+}
+
+impl From<WriteError> for std::io::Error {
+    fn from(err: WriteError) -> Self {
+        match err {
+            WriteError::Io(err) => err,
+            WriteError::Timeout => std::io::ErrorKind::TimedOut.into(),
+        }
+    }
 }
 
 impl Server {
 
+    /// Creates the server from the configuration.
+    /// When TLS is configured, the certificate and key are loaded and validated
+    /// here so that the server fails at startup rather than on the first connection.
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            #[cfg(feature = "tls")]
+            tls: config().tls.as_ref().map(tls::acceptor).transpose()?,
+            limiter: ConnectionLimiter::new(config().max_connections),
+            rate_limiter: config().rate_limit.as_ref().map(|cfg| Arc::new(RateLimiter::new(cfg))),
+        })
+    }
+
     /// Starts the HTTP server by binding to the configured address and port.
     ///
     /// This method runs indefinitely, accepting incoming TCP connections and
     /// spawning a new asynchronous task for each client.
+    /// When a Unix socket is configured, its connections are accepted alongside.
+    ///
+    /// Beyond [`max_connections`](crate::config::ServerConfig::max_connections)
+    /// simultaneous connections, plaintext clients are answered `503 Service Unavailable`
+    /// and TLS ones are disconnected.
+    /// The same goes for clients exceeding their [`rate_limit`](crate::config::ServerConfig::rate_limit),
+    /// answered `429 Too Many Requests` instead. The connections of the
+    /// [`trusted_proxies`](crate::config::ServerConfig::trusted_proxies) are limited once their
+    /// request is read instead, with the client address it was forwarded for.
     pub async fn run(&self) -> std::io::Result<()> {
         let listener = TcpListener::bind((config().address, config().port)).await?;
 
-        while let Ok((stream, _addr)) = listener.accept().await {
-            task::spawn(Self::handle_client(stream));
+        // the socket file is removed once the server stops
+        #[cfg(unix)]
+        let _socket_file = match &config().unix_socket {
+            Some(path) => {
+                let (unix_listener, socket_file) = unix::bind(path).await?;
+                task::spawn(Self::run_unix(unix_listener, self.limiter.clone()));
+                Some(socket_file)
+            }
+            None => None,
+        };
+
+        if config().metrics
+            && let Some(port) = config().metrics_port
+        {
+            let metrics_listener = TcpListener::bind((config().address, port)).await?;
+            task::spawn(Self::run_metrics(metrics_listener));
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            let rate_limiter = rate_limiter.clone();
+            task::spawn(async move {
+                loop {
+                    task::sleep(rate_limit::EVICTION_INTERVAL).await;
+                    rate_limiter.evict_idle().await;
+                }
+            });
+        }
+
+        while let Ok((stream, addr)) = listener.accept().await {
+            let cfg = config();
+            if let Err(err) = socket::configure(&stream, cfg.tcp_nodelay, cfg.tcp_keepalive) {
+                eprintln!("Fail to set the socket options of {}: {}", addr, err);
+            }
+
+            let behind_proxy = forwarded::is_trusted_proxy(Some(addr.ip()), &config().trusted_proxies);
+            let deferred_limiter = self.rate_limiter.clone().filter(|_| behind_proxy);
+
+            let mut rejection = None;
+            if !behind_proxy
+                && let Some(rate_limiter) = &self.rate_limiter
+                && let Err(wait) = rate_limiter.check(addr.ip()).await
+            {
+                rejection = Some(Self::too_many_requests(wait));
+            }
+            // rate limited clients do not take a slot
+            let permit = match rejection {
+                Some(_) => None,
+                None => self.limiter.try_acquire(),
+            };
+
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = &self.tls {
+                if let Some(permit) = permit {
+                    let acceptor = acceptor.clone();
+                    task::spawn(async move {
+                        let _permit = permit;
+                        Self::handle_tls_client(acceptor, stream, addr, deferred_limiter).await
+                    });
+                }
+                continue;
+            }
+
+            match permit {
+                Some(permit) => task::spawn(async move {
+                    let _permit = permit;
+                    Self::handle_client(stream, Some(addr.ip()), deferred_limiter).await
+                }),
+                None => {
+                    let response = rejection.unwrap_or_else(|| Self::closing_error(HttpStatus::ServiceUnavailable));
+                    task::spawn(Self::reject(stream, response))
+                }
+            };
         }
 
         Ok(())
     }
 
-    /// Reads and incrementally parses an HTTP request from the TCP stream.
+    /// Accepts the connections of the Unix socket, served as plaintext HTTP.
+    #[cfg(unix)]
+    async fn run_unix(listener: async_std::os::unix::net::UnixListener, limiter: Arc<ConnectionLimiter>) {
+        while let Ok((stream, _)) = listener.accept().await {
+            match limiter.try_acquire() {
+                Some(permit) => task::spawn(async move {
+                    let _permit = permit;
+                    Self::handle_client(stream, None, None).await
+                }),
+                None => task::spawn(Self::reject(stream, Self::closing_error(HttpStatus::ServiceUnavailable))),
+            };
+        }
+    }
+
+    /// Accepts the connections of the dedicated metrics port, which only serves
+    /// [`metrics_path`](crate::config::ServerConfig::metrics_path).
+    async fn run_metrics(listener: TcpListener) {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            task::spawn(async move {
+                let read = Self::read_request(&mut stream, 0, config().header_timeout, config().read_timeout).await;
+                let response = match read {
+                    Ok(req) if req.path == config().metrics_path => handler::responses::metrics(),
+                    Ok(_) => handler::handle_error(HttpStatus::NotFound),
+                    Err(_) => Self::closing_error(HttpStatus::BadRequest),
+                };
+                Self::write_response(&mut stream, &response).await?;
+                std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_close(cx)).await
+            });
+        }
+    }
+
+    /// Answers a connection exceeding a limit without reading its request.
+    async fn reject<S: Write + Unpin>(mut stream: S, response: HttpResponse) -> std::io::Result<()> {
+        Self::write_response(&mut stream, &response).await?;
+        std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_close(cx)).await
+    }
+
+    /// Performs the TLS handshake before handling the client as usual.
+    #[cfg(feature = "tls")]
+    async fn handle_tls_client(
+        acceptor: TlsAcceptor,
+        stream: TcpStream,
+        addr: SocketAddr,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> std::io::Result<()> {
+        match acceptor.accept(stream).await {
+            Ok(stream) => Self::handle_client(stream, Some(addr.ip()), rate_limiter).await,
+            Err(err) => {
+                eprintln!("TLS handshake with {} failed: {}", addr, err);
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads and incrementally parses an HTTP request from the stream.
     ///
     /// The request is parsed as data becomes available. Once all headers are read,
     /// the request is validated. If a body is expected, it is read until completion.
     ///
+    /// The request line and headers must be entirely received within `header_timeout`,
+    /// however slowly their bytes are sent, so that a client dribbling them cannot hold
+    /// the connection indefinitely. The body, possibly large, is not subject to it:
+    /// each of its reads must only complete within `read_timeout`.
+    ///
     /// Returns a fully constructed [`HttpRequest`] or a [`ReadError`] in case of
     /// I/O, parsing, or validation failure.
-    async fn read_request(stream: &mut TcpStream) -> Result<HttpRequest, ReadError> {
-        let mut parser = Parser::new();
+    async fn read_request<S: Read + Write + Unpin>(
+        stream: &mut S,
+        max_body_size: usize,
+        header_timeout: Duration,
+        read_timeout: Duration,
+    ) -> Result<HttpRequest, ReadError> {
+        let headers_deadline = Instant::now() + header_timeout;
+        // the same snapshot sizes both buffers, only what the parser can hold being read
+        let cfg = config();
+        let mut buffer = vec![0; cfg.buffer_size];
+        let mut parser = Parser::with_config(cfg);
         let mut req = HttpRequest::new();
-        let mut buffer = vec![0; config().buffer_size];
 
+        // Bytes received after the headers, counted separately from the parser so that
+        // a client sending more than its advertised Content-Length is cut off as well.
+        let mut body_bytes: Option<usize> = None;
+        let check_body_size = |received: usize| {
+            if received > max_body_size { Err(ReadError::PayloadTooLarge) } else { Ok(()) }
+        };
+
+        // Data is read from the stream only when the parser needs more of it:
+        // after the headers, the parser may already hold (part of) the body.
+        let mut parser_res = ParserOk::Incomplete;
         loop {
-            // If parser buffer is empty, read more data from the stream
-            if parser.is_buffer_empty() {
-                let n = match stream.read(&mut buffer).await {
-                    Ok(0) => return Err(ReadError::ConnectionClosed),
-                    Ok(n) => n,
-                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(ReadError::Io(e)),
-                };
-                
-                // Feed newly read bytes into the parser.
-                parser
-                    .feed(&buffer[..n], &mut req)
-                    .map_err(ReadError::Parser)?;
-            }
+            parser_res = match parser_res {
+                ParserOk::Incomplete => {
+                    // the parser still holds an incomplete field filling its whole buffer
+                    let free = parser.free_capacity().min(buffer.len());
+                    if free == 0 {
+                        return Err(ReadError::Parser(ParserError::BufferFull));
+                    }
+                    let read = match body_bytes {
+                        // still reading the request line and headers
+                        None => {
+                            let remaining = headers_deadline.saturating_duration_since(Instant::now());
+                            async_std::future::timeout(remaining, stream.read(&mut buffer[..free]))
+                                .await
+                                .map_err(|_| ReadError::HeaderTimeout)?
+                        }
+                        Some(_) => async_std::future::timeout(read_timeout, stream.read(&mut buffer[..free]))
+                            .await
+                            .map_err(|_| ReadError::BodyTimeout)?,
+                    };
+                    let n = match read {
+                        Ok(0) if body_bytes.is_some() && parser.finish(&req) => break,
+                        Ok(0) => return Err(ReadError::ConnectionClosed),
+                        Ok(n) => n,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(ReadError::Io(e)),
+                    };
 
-            // Continue parsing using any remaining buffered data.
-            // Feeding an empty slice allows the parser to progress without
-            // requiring a new network read.
-            let parser_res = parser
-                .feed(&[], &mut req)
-                .map_err(ReadError::Parser)?;
-
-            match parser_res {
-                ParserOk::Incomplete | ParserOk::Ok => {
-                    // The parser needs more data to make progress.
-                    continue;
+                    if let Some(received) = &mut body_bytes {
+                        *received += n;
+                        check_body_size(*received)?;
+                    }
+
+                    // Feed newly read bytes into the parser.
+                    parser
+                        .feed(&buffer[..n], &mut req)
+                        .map_err(ReadError::Parser)?
                 }
                 ParserOk::HeadersDone => {
                     // All headers have been parsed.
                     // Validate the request early, before reading the body.
                     Validator::validate_request(&req).map_err(ReadError::Validator)?;
-                    
-                    // Continue the loop to read and parse the request body, if any.
-                    continue;
+
+                    body_bytes = Some(parser.buffered());
+                    check_body_size(parser.buffered())?;
+
+                    // The client waits for the request to be accepted before sending
+                    // the body, unless it already started to send it anyway.
+                    if Self::expects_continue(&req) && parser.buffered() == 0 {
+                        let interim = response::interim_response(HttpStatus::Continue);
+                        Self::write_in_slices(stream, &[interim.as_bytes()], buffer.len(), config().write_timeout)
+                            .await
+                            .map_err(|err| match err {
+                                WriteError::Io(err) => ReadError::Io(err),
+                                // a client which does not read cannot be answered either
+                                WriteError::Timeout => ReadError::Io(std::io::ErrorKind::TimedOut.into()),
+                            })?;
+                    }
+
+                    // Continue parsing the request body, if any, using the remaining
+                    // buffered data. Feeding an empty slice allows the parser to
+                    // progress without requiring a new network read.
+                    parser.feed(&[], &mut req).map_err(ReadError::Parser)?
                 }
+                ParserOk::Ok => parser.feed(&[], &mut req).map_err(ReadError::Parser)?,
                 ParserOk::Done => break, // request is fully parsed
-            }
+            };
         }
 
         Ok(req)
     }
 
 
-    /// Writes the given `HttpResponse` back to the TCP stream.
+    /// Checks whether the client sent `Expect: 100-continue` for a request with a body.
+    fn expects_continue(req: &HttpRequest) -> bool {
+        let has_body = req.is_chunked()
+            || req
+                .headers
+                .get("Content-Length")
+                .is_some_and(|len| len.parse::<usize>().is_ok_and(|len| len > 0));
+
+        has_body
+            && req
+                .headers
+                .get("Expect")
+                .is_some_and(|e| e.trim().eq_ignore_ascii_case("100-continue"))
+    }
+
+    /// Writes the given `HttpResponse` back to the stream.
     /// Serializes the response headers and body appropriately.
-    async fn write_response(
-        stream: &mut TcpStream,
+    async fn write_response<S: Write + Unpin>(
+        stream: &mut S,
         response: &HttpResponse,
-    ) -> std::io::Result<()> {
+    ) -> Result<(), WriteError> {
         let headers = response.build_headers();
-        stream.write_all(headers.as_bytes()).await?;
-        stream.write_all(&response.body).await?;
-        Ok(())
+        let mut body = &response.body;
+        // read into memory for the streams sendfile cannot write to, e.g. the TLS ones
+        #[cfg(feature = "sendfile")]
+        let file_body;
+        #[cfg(feature = "sendfile")]
+        if let Some(file) = &response.file {
+            file_body = file.read().await.map_err(WriteError::Io)?;
+            body = &file_body;
+        }
+        let chunked_body;
+        if response.is_chunked() {
+            chunked_body = response::encode_chunked(body, config().buffer_size);
+            body = &chunked_body;
+        }
+        Self::write_in_slices(stream, &[headers.as_bytes(), body], config().buffer_size, config().write_timeout).await
+    }
+
+    /// Writes the response to a request, sending its file body with `sendfile` when
+    /// the stream is a plain socket, see [`sendfile`].
+    async fn send_response<S: Read + Write + Unpin + RawSocket>(
+        stream: &mut CountingStream<S>,
+        response: &HttpResponse,
+    ) -> Result<(), WriteError> {
+        #[cfg(feature = "sendfile")]
+        if let Some(file) = &response.file
+            && let Some(socket) = stream.raw_socket()
+        {
+            let socket = socket.try_clone_to_owned().map_err(WriteError::Io)?;
+            let headers = response.build_headers();
+            Self::write_in_slices(stream, &[headers.as_bytes()], config().buffer_size, config().write_timeout).await?;
+
+            let (sent, result) = sendfile::send(socket, file, config().write_timeout).await;
+            stream.written += sent;
+            return result.map_err(|err| match err.kind() {
+                std::io::ErrorKind::TimedOut => WriteError::Timeout,
+                _ => WriteError::Io(err),
+            });
+        }
+        Self::write_response(stream, response).await
+    }
+
+    /// Writes the parts in slices of `slice_size` bytes, each one within `timeout`.
+    /// A slow client is given the time it needs as long as it keeps reading, while one
+    /// which stops reading is given up on instead of holding the connection.
+    async fn write_in_slices<S: Write + Unpin>(
+        stream: &mut S,
+        parts: &[&[u8]],
+        slice_size: usize,
+        timeout: Duration,
+    ) -> Result<(), WriteError> {
+        for slice in parts.iter().flat_map(|part| part.chunks(slice_size)) {
+            async_std::future::timeout(timeout, stream.write_all(slice))
+                .await
+                .map_err(|_| WriteError::Timeout)?
+                .map_err(WriteError::Io)?;
+        }
+        async_std::future::timeout(timeout, stream.flush())
+            .await
+            .map_err(|_| WriteError::Timeout)?
+            .map_err(WriteError::Io)
     }
     
+    /// Builds the response to a request which could not be read.
+    ///
+    /// The rest of such a request, like a body sent without `Content-Length`,
+    /// is left unread on the stream and cannot be told apart from a following request,
+    /// so the client is told that the connection is closed after the response.
+    fn closing_error(status: HttpStatus) -> HttpResponse {
+        let mut response = handler::handle_error(status);
+        response.set_header(ResponseHeader::Connection, "close");
+        response
+    }
+
+    /// Builds the response to a client exceeding its rate limit,
+    /// telling it when to retry.
+    fn too_many_requests(wait: Duration) -> HttpResponse {
+        let mut response = Self::closing_error(HttpStatus::TooManyRequests);
+        response.set_header(ResponseHeader::RetryAfter, &rate_limit::retry_after(wait));
+        response
+    }
+
+    /// Formats the `Server-Timing` header value of a response, in milliseconds:
+    /// `parse` for reading the request and `handle` for building the response, when it was
+    /// handled. The time spent writing the response cannot be part of its own header.
+    fn server_timing(parse: Duration, handle: Option<Duration>) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        match handle {
+            Some(handle) => format!("parse;dur={:.1}, handle;dur={:.1}", ms(parse), ms(handle)),
+            None => format!("parse;dur={:.1}", ms(parse)),
+        }
+    }
+
+    /// Adds the `Server-Timing` header to a response when [`server_timing`](ServerConfig::server_timing) is enabled.
+    fn set_server_timing(cfg: &ServerConfig, response: &mut HttpResponse, parse: Duration, handle: Option<Duration>) {
+        if cfg.server_timing {
+            response.set_header(ResponseHeader::ServerTiming, &Self::server_timing(parse, handle));
+        }
+    }
+
     /// Handles a single client connection.
-    /// Reads the HTTP request, processes it via the handler, and writes back the response.
-    async fn handle_client(mut stream: TcpStream) -> std::io::Result<()> {
-        let response = match Self::read_request(&mut stream).await {
-            Ok(r) => handler::handle_request(&r),
+    /// Reads the HTTP request, processes it via the handler, writes back the response
+    /// and logs the exchange, which is also recorded in the [`metrics`].
+    ///
+    /// The client is the peer unless it is a trusted proxy, see [`forwarded`].
+    /// The `rate_limiter` is given for the peers whose clients are only known from their request.
+    async fn handle_client<S: Read + Write + Unpin + RawSocket>(
+        stream: S,
+        peer: Option<IpAddr>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> std::io::Result<()> {
+        let start = Instant::now();
+        let mut stream = CountingStream::new(stream);
+        let cfg = config();
+        let mut read = Self::read_request(&mut stream, cfg.max_body_size, cfg.header_timeout, cfg.read_timeout).await;
+        let parse_time = start.elapsed();
+        let mut client = peer;
+        if let Ok(req) = &mut read {
+            req.peer_addr = peer;
+            client = forwarded::client_addr(req, &config().trusted_proxies);
+        }
+
+        if let Ok(req) = &read
+            && let (Some(rate_limiter), Some(ip)) = (&rate_limiter, client)
+            && let Err(wait) = rate_limiter.check(ip).await
+        {
+            let response = Self::too_many_requests(wait);
+            let written = Self::write_response(&mut stream, &response).await;
+            return Self::finish(stream, client, Some(req), &response, start, written).await;
+        }
+        if let Ok(req) = &read
+            && req.method == HttpMethod::Connect
+            && let Some(tunnel_cfg) = &config().connect_tunnel
+        {
+            return Self::handle_connect(stream, req, tunnel_cfg, client, start).await;
+        }
+
+        let mut handle_time = None;
+        let (mut response, request) = match read {
+            Ok(r) => {
+                let handle_start = Instant::now();
+                let response = handler::handle_request(&r).await;
+                handle_time = Some(handle_start.elapsed());
+                (response, Some(r))
+            }
             Err(ReadError::Io(err)) => {
                 eprintln!("I/O error while reading request: {:?}", err);
                 return Ok(());
             }
             Err(ReadError::ConnectionClosed) => return Ok(()),
-            Err(ReadError::Parser(err)) => handler::handle_error(err.into_http_status()),
-            Err(ReadError::Validator(err)) => handler::handle_error(err.into_http_status()),
+            Err(ReadError::Parser(err)) => (Self::closing_error(err.into_http_status()), None),
+            Err(ReadError::Validator(err)) => (Self::closing_error(err.into_http_status()), None),
+            Err(ReadError::PayloadTooLarge) => (Self::closing_error(HttpStatus::PayloadTooLarge), None),
+            Err(ReadError::HeaderTimeout | ReadError::BodyTimeout) => {
+                (Self::closing_error(HttpStatus::RequestTimeout), None)
+            }
+        };
+        Self::set_server_timing(&config(), &mut response, parse_time, handle_time);
+
+        let written = Self::send_response(&mut stream, &response).await;
+        Self::finish(stream, client, request.as_ref(), &response, start, written).await
+    }
+
+    /// Handles a CONNECT request when [`connect_tunnel`](crate::config::ServerConfig::connect_tunnel)
+    /// is configured, relaying the connection to its target until it is closed, see [`tunnel`].
+    /// The exchange is logged once the tunnel is closed.
+    async fn handle_connect<S: Read + Write + Unpin>(
+        mut stream: CountingStream<S>,
+        req: &HttpRequest,
+        tunnel_cfg: &TunnelConfig,
+        client: Option<IpAddr>,
+        start: Instant,
+    ) -> std::io::Result<()> {
+        let (response, written) = match tunnel::open(tunnel_cfg, req, config().read_timeout).await {
+            Ok(upstream) => {
+                let cfg = config();
+                let established = tunnel::ESTABLISHED.as_bytes();
+                let written = Self::write_in_slices(&mut stream, &[established], cfg.buffer_size, cfg.write_timeout).await;
+                if written.is_ok() {
+                    let (idle_timeout, write_timeout) = (tunnel_cfg.idle_timeout, cfg.write_timeout);
+                    let relayed = tunnel::relay(&mut stream, upstream, cfg.buffer_size, idle_timeout, write_timeout);
+                    if let Err(err) = relayed.await {
+                        eprintln!("I/O error in the tunnel to {}: {:?}", req.uri, err);
+                    }
+                }
+                (HttpResponse::new(), written)
+            }
+            Err(status) => {
+                let response = Self::closing_error(status);
+                let written = Self::write_response(&mut stream, &response).await;
+                (response, written)
+            }
+        };
+        Self::finish(stream, client, Some(req), &response, start, written).await
+    }
+
+    /// Logs the exchange, records it in the [`metrics`] and closes the connection.
+    /// A response whose write failed is logged as truncated, the connection being
+    /// dropped without waiting for the client to acknowledge its closing.
+    async fn finish<S: Read + Write + Unpin>(
+        mut stream: CountingStream<S>,
+        client: Option<IpAddr>,
+        request: Option<&HttpRequest>,
+        response: &HttpResponse,
+        start: Instant,
+        written: Result<(), WriteError>,
+    ) -> std::io::Result<()> {
+        if let Err(err) = &written {
+            eprintln!("Response truncated after {} bytes: {}", stream.written, err);
+        }
+        if !request.is_some_and(handler::health::is_health_check) {
+            access_log::log(&config().access_log, client, request, response, written.is_err(), start.elapsed());
+        }
+        metrics::METRICS.record(response.status, stream.read, stream.written, start.elapsed());
+        written?;
+
+        // the connection is closed after each response, which for TLS
+        // notifies the client that the response is complete
+        std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_close(cx)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init_test_config;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// In-memory stream reading from the given request bytes and recording everything written.
+    /// The input is delivered chunk by chunk, one chunk at most per read.
+    struct FakeStream {
+        input: std::collections::VecDeque<Vec<u8>>,
+        output: Vec<u8>,
+        /// Length of the output at the time of each read
+        output_at_read: Vec<usize>,
+    }
+
+    impl FakeStream {
+        fn new(input: &[u8]) -> Self {
+            Self::chunked(&[input])
+        }
+
+        fn chunked(chunks: &[&[u8]]) -> Self {
+            Self {
+                input: chunks.iter().map(|c| c.to_vec()).collect(),
+                output: Vec::new(),
+                output_at_read: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for FakeStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let output_len = self.output.len();
+            self.output_at_read.push(output_len);
+
+            let Some(chunk) = self.input.front_mut() else {
+                return Poll::Ready(Ok(0));
+            };
+            let n = buf.len().min(chunk.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            if chunk.is_empty() {
+                self.input.pop_front();
+            }
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl Write for FakeStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// The output is recorded in memory.
+    #[cfg(feature = "sendfile")]
+    impl RawSocket for FakeStream {
+        fn raw_socket(&self) -> Option<std::os::fd::BorrowedFd<'_>> {
+            None
+        }
+    }
+
+    /// Stream of a client receiving `rate` bytes at most per write, which stops reading once
+    /// it received `capacity` bytes, like a vanished client whose socket buffers are full.
+    struct DrainingStream {
+        received: usize,
+        rate: usize,
+        capacity: usize,
+    }
+
+    impl Write for DrainingStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if self.received == self.capacity {
+                // never woken, as no more data will be read
+                return Poll::Pending;
+            }
+            let n = buf.len().min(self.rate).min(self.capacity - self.received);
+            self.received += n;
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Sends raw bytes to a connection handled by the server and returns everything
+    /// received until the server closes the connection.
+    async fn exchange(request: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        let server = task::spawn(Server::handle_client(stream, Some(addr.ip()), None));
+
+        client.write_all(request).await.unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        server.await.unwrap();
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn slow_client_write() {
+        let body = vec![b'x'; 64 * 1024];
+        let parts: [&[u8]; 2] = [b"HTTP/1.1 200 OK\r\n\r\n", &body];
+        let timeout = Duration::from_millis(50);
+        task::block_on(async {
+            // a slow client is served as long as it keeps reading
+            let mut slow = DrainingStream { received: 0, rate: 7, capacity: usize::MAX };
+            Server::write_in_slices(&mut slow, &parts, 4096, timeout).await.unwrap();
+            assert_eq!(slow.received, parts[0].len() + body.len());
+
+            let mut stalled = DrainingStream { received: 0, rate: 1024, capacity: 10_000 };
+            let start = Instant::now();
+            let written = Server::write_in_slices(&mut stalled, &parts, 4096, timeout).await;
+            assert!(matches!(written, Err(WriteError::Timeout)), "{:?}", written);
+            assert_eq!(stalled.received, 10_000);
+            assert!(start.elapsed() < Duration::from_secs(1));
+        });
+    }
+
+    #[test]
+    fn in_memory_stream() {
+        init_test_config();
+        task::block_on(async {
+            let mut stream = FakeStream::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            Server::handle_client(&mut stream, None, None).await.unwrap();
+
+            let response = String::from_utf8(stream.output).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let mut lines = head.split("\r\n");
+            assert_eq!(lines.next(), Some("HTTP/1.1 200 OK"));
+            assert!(lines.any(|l| l == format!("Content-Length: {}", body.len())));
+            assert_eq!(body, "<h1>Welcome to rustynet/0.1!</h1>");
+        });
+    }
+
+    #[test]
+    fn http_versions() {
+        init_test_config();
+        let response = task::block_on(exchange(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.contains("\r\nConnection: close\r\n"));
+
+        let response = task::block_on(exchange(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        // the connection is closed after each response
+        assert_eq!(response.matches("\r\nConnection: close\r\n").count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn connection_limit() {
+        use async_std::os::unix::net::UnixStream;
+
+        init_test_config();
+        let path = std::env::temp_dir().join(format!("rustynet-limit-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        task::block_on(async {
+            let (listener, _socket_file) = unix::bind(path).await.unwrap();
+            let limiter = ConnectionLimiter::new(1);
+            task::spawn(Server::run_unix(listener, limiter.clone()));
+
+            // the first connection keeps its slot while its request is incomplete
+            let mut first = UnixStream::connect(path).await.unwrap();
+            first.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+            // held by the test, the accept loop and the permit of the connection
+            while Arc::strong_count(&limiter) < 3 {
+                task::yield_now().await;
+            }
+
+            let mut second = UnixStream::connect(path).await.unwrap();
+            let mut response = String::new();
+            second.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+            assert!(response.contains("Connection: close\r\n"));
+
+            first.write_all(b"Host: localhost\r\n\r\n").await.unwrap();
+            let mut response = String::new();
+            first.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        });
+    }
+
+    #[test]
+    fn rate_limited_client() {
+        init_test_config();
+        task::block_on(async {
+            let mut stream = FakeStream::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            Server::reject(&mut stream, Server::too_many_requests(Duration::from_millis(1500)))
+                .await
+                .unwrap();
+            let response = String::from_utf8(stream.output).unwrap();
+            assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+            assert!(response.contains("Retry-After: 2\r\n"));
+            assert!(response.contains("Connection: close\r\n"));
+        });
+    }
+
+    #[test]
+    fn header_timeout() {
+        init_test_config();
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // each byte arrives well within the timeout, but not the whole headers
+            let slowloris = task::spawn(async move {
+                for &b in b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n" {
+                    if client.write_all(&[b]).await.is_err() {
+                        break;
+                    }
+                    task::sleep(Duration::from_millis(20)).await;
+                }
+            });
+
+            let start = Instant::now();
+            let read = Server::read_request(&mut stream, 1024, Duration::from_millis(100), Duration::from_secs(5)).await;
+            assert!(matches!(read, Err(ReadError::HeaderTimeout)));
+            assert!(start.elapsed() < Duration::from_millis(500));
+            drop(stream);
+            slowloris.await;
+        });
+
+        let response = Server::closing_error(HttpStatus::RequestTimeout);
+        assert!(response.build_headers().starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn body_timeout() {
+        init_test_config();
+        task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // the headers arrive at once, then the body stops halfway
+            client.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nhello").await.unwrap();
+
+            let start = Instant::now();
+            let read = Server::read_request(&mut stream, 1024, Duration::from_secs(5), Duration::from_millis(100)).await;
+            assert!(matches!(read, Err(ReadError::BodyTimeout)));
+            assert!(start.elapsed() < Duration::from_millis(500));
+            drop(client);
+        });
+    }
+
+    #[test]
+    fn metrics_count_requests() {
+        init_test_config();
+        let requests_total = || {
+            let rendered = metrics::METRICS.render();
+            let line = rendered.lines().find(|l| l.starts_with("rustynet_requests_total ")).unwrap();
+            line.rsplit(' ').next().unwrap().parse::<u64>().unwrap()
+        };
+
+        let before = requests_total();
+        task::block_on(async {
+            exchange(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+            exchange(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        });
+        // other tests may handle requests concurrently
+        assert!(requests_total() >= before + 2);
+
+        let rendered = metrics::METRICS.render();
+        assert!(rendered.contains("# TYPE rustynet_request_duration_seconds histogram\n"));
+        assert!(!rendered.contains("rustynet_responses_total{class=\"2xx\"} 0\n"));
+        assert!(!rendered.contains("rustynet_responses_total{class=\"4xx\"} 0\n"));
+        assert!(!rendered.contains("rustynet_sent_bytes_total 0\n"));
+    }
+
+    #[test]
+    fn async_handler() {
+        use crate::handler::router::{self, Route};
+        use crate::http::HttpMethod;
+        use crate::http::response::HttpResponse;
+        use std::time::Duration;
+
+        init_test_config();
+        router::register_route(Route::new_async(HttpMethod::Get, "/async-handler", |req: HttpRequest| async move {
+            task::sleep(Duration::from_millis(20)).await;
+            HttpResponse::builder().body_text(format!("slept for {}", req.uri)).build()
+        }));
+
+        let response = task::block_on(exchange(b"GET /async-handler HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nslept for /async-handler"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use async_std::os::unix::net::UnixStream;
+
+        init_test_config();
+        let path = std::env::temp_dir().join(format!("rustynet-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        // a stale socket file is replaced
+        std::fs::write(path, b"").unwrap();
+
+        task::block_on(async {
+            let (listener, socket_file) = unix::bind(path).await.unwrap();
+            assert!(unix::bind(path).await.is_err());
+            task::spawn(Server::run_unix(listener, ConnectionLimiter::new(0)));
+
+            let mut client = UnixStream::connect(path).await.unwrap();
+            client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with("<h1>Welcome to rustynet/0.1!</h1>"));
+
+            drop(socket_file);
+            assert!(!std::path::Path::new(path).exists());
+        });
+    }
+
+    #[test]
+    fn body_larger_than_declared() {
+        init_test_config();
+        task::block_on(async {
+            let head = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\n";
+
+            let mut stream = FakeStream::new(&[&head[..], &[b'a'; 100]].concat());
+            let res = Server::read_request(&mut stream, 16, Duration::from_secs(5), Duration::from_secs(5)).await;
+            assert!(matches!(res, Err(ReadError::PayloadTooLarge)));
+
+            let mut stream = FakeStream::new(&[&head[..], b"hello"].concat());
+            let req = Server::read_request(&mut stream, 16, Duration::from_secs(5), Duration::from_secs(5)).await.ok().unwrap();
+            assert_eq!(req.body, b"hello");
+        });
+    }
+
+    #[test]
+    fn request_split_within_a_field() {
+        init_test_config();
+        task::block_on(async {
+            // the second read fills the buffer, the parser still holding the start of the Host line
+            let body = vec![b'a'; config().buffer_size];
+            let rest = [&b"alhost\r\nContent-Length: "[..], body.len().to_string().as_bytes(), b"\r\n\r\n", &body].concat();
+            let mut stream = FakeStream::chunked(&[b"POST /upload HTTP/1.1\r\nHost: loc", &rest]);
+            let req = Server::read_request(&mut stream, body.len(), Duration::from_secs(5), Duration::from_secs(5)).await.ok().unwrap();
+            assert_eq!(req.headers.get("Host").unwrap(), "localhost");
+            assert_eq!(req.body, body);
+        });
+    }
+
+    #[test]
+    fn chunked_body() {
+        init_test_config();
+        task::block_on(async {
+            let head = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+            let mut stream = FakeStream::chunked(&[head, b"5\r\nhello\r\n", b"0\r\n\r\n"]);
+            let req = Server::read_request(&mut stream, 16, Duration::from_secs(5), Duration::from_secs(5)).await.ok().unwrap();
+            assert_eq!(req.body, b"hello");
+        });
+    }
+
+    #[test]
+    fn expect_continue() {
+        init_test_config();
+        task::block_on(async {
+            let head = b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n";
+            let mut stream = FakeStream::chunked(&[head, b"hello"]);
+            let req = Server::read_request(&mut stream, 16, Duration::from_secs(5), Duration::from_secs(5)).await.ok().unwrap();
+            assert_eq!(req.body, b"hello");
+
+            // the body was read after the 100 Continue was written
+            let continue_line = b"HTTP/1.1 100 Continue\r\n\r\n";
+            assert_eq!(stream.output, continue_line);
+            assert_eq!(stream.output_at_read, [0, continue_line.len()]);
+        });
+    }
+
+    #[test]
+    fn expect_continue_rejected() {
+        init_test_config();
+        task::block_on(async {
+            let mut stream = FakeStream::chunked(&[
+                b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100000000\r\nExpect: 100-continue\r\n\r\n",
+                b"body never read",
+            ]);
+            Server::handle_client(&mut stream, None, None).await.unwrap();
+
+            let response = String::from_utf8(stream.output.clone()).unwrap();
+            assert!(response.starts_with("HTTP/1.1 413 "));
+            assert!(!response.contains("100 Continue"));
+            assert_eq!(stream.input.len(), 1);
+        });
+    }
+
+    #[test]
+    fn close_delimited_body() {
+        init_test_config();
+        task::block_on(async {
+            let head = b"POST /upload HTTP/1.0\r\nConnection: close\r\n\r\nhello";
+            let mut stream = FakeStream::chunked(&[head, b" world"]);
+            let req = Server::read_request(&mut stream, 16, Duration::from_secs(5), Duration::from_secs(5)).await.ok().unwrap();
+            assert_eq!(req.body, b"hello world");
+
+            let mut stream = FakeStream::chunked(&[head, b" and more than sixteen bytes"]);
+            let res = Server::read_request(&mut stream, 16, Duration::from_secs(5), Duration::from_secs(5)).await;
+            assert!(matches!(res, Err(ReadError::PayloadTooLarge)));
+        });
+    }
+
+    #[test]
+    fn body_without_content_length() {
+        init_test_config();
+        task::block_on(async {
+            let response = exchange(b"POST / HTTP/1.1\r\nHost: localhost\r\n\r\nunframed body").await;
+            assert!(response.starts_with("HTTP/1.1 411 "));
+            assert!(response.contains("Connection: close\r\n"));
+        });
+    }
+
+    /// Tunnels a CONNECT request to `target` and returns the response head along
+    /// with what the target echoed back, or the whole response when it is refused.
+    async fn connect_through(target: &str, tunnel_cfg: TunnelConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let server = task::spawn(async move {
+            let mut stream = CountingStream::new(stream);
+            let req = Server::read_request(&mut stream, 0, Duration::from_secs(5), Duration::from_secs(5)).await.ok().unwrap();
+            Server::handle_connect(stream, &req, &tunnel_cfg, None, Instant::now()).await
+        });
+
+        let head = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n");
+        client.write_all(head.as_bytes()).await.unwrap();
+        let mut response = vec![0; tunnel::ESTABLISHED.len()];
+        client.read_exact(&mut response).await.unwrap();
+        if response == tunnel::ESTABLISHED.as_bytes() {
+            client.write_all(b"ping").await.unwrap();
+            client.shutdown(std::net::Shutdown::Write).unwrap();
+        }
+        client.read_to_end(&mut response).await.unwrap();
+        server.await.unwrap();
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn connect_tunnel() {
+        init_test_config();
+        task::block_on(async {
+            // echoes everything received until the client is done
+            let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let target = echo.local_addr().unwrap().to_string();
+            task::spawn(async move {
+                let (stream, _) = echo.accept().await.unwrap();
+                let (mut reader, mut writer) = (&stream, &stream);
+                async_std::io::copy(&mut reader, &mut writer).await.unwrap();
+            });
+
+            let tunnel_cfg = TunnelConfig { allowed_targets: vec![target.clone()], ..TunnelConfig::default() };
+            let response = connect_through(&target, tunnel_cfg).await;
+            assert_eq!(response, format!("{}ping", tunnel::ESTABLISHED));
+
+            let response = connect_through(&target, TunnelConfig::default()).await;
+            assert!(response.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{response}");
+        });
+    }
+
+    #[test]
+    fn server_timing_header() {
+        let timing = Server::server_timing(Duration::from_micros(400), Some(Duration::from_micros(1240)));
+        assert_eq!(timing, "parse;dur=0.4, handle;dur=1.2");
+        assert_eq!(Server::server_timing(Duration::from_millis(3), None), "parse;dur=3.0");
+
+        // each metric is `name;dur=<milliseconds>`
+        for metric in timing.split(", ") {
+            let (name, dur) = metric.split_once(";dur=").unwrap();
+            assert!(name == "parse" || name == "handle");
+            assert!(dur.parse::<f64>().unwrap() >= 0.0);
+        }
+
+        // the header is disabled by default
+        init_test_config();
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = task::block_on(exchange(request));
+        assert!(response.starts_with("HTTP/1.1 "), "{response}");
+        assert!(!response.contains("Server-Timing"), "{response}");
+
+        let cfg = ServerConfig { server_timing: true, ..ServerConfig::default() };
+        let mut response = HttpResponse::new();
+        Server::set_server_timing(&cfg, &mut response, Duration::from_micros(400), Some(Duration::from_micros(1240)));
+        let head = response.build_headers();
+        assert!(head.contains("\r\nServer-Timing: parse;dur=0.4, handle;dur=1.2\r\n"), "{head}");
+    }
+
+    #[test]
+    fn connect_without_tunnel() {
+        init_test_config();
+        let response = task::block_on(exchange(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n"));
+        assert!(response.starts_with("HTTP/1.1 501 "), "{response}");
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{name}", env!("CARGO_MANIFEST_DIR"))
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_config(cert: &str, key: &str) -> tls::TlsConfig {
+        tls::TlsConfig {
+            cert_path: tls_fixture(cert),
+            key_path: tls_fixture(key),
+            key_passphrase: None,
+            min_version: tls::TlsVersion::V1_2,
+            cipher_suites: None,
+        }
+    }
+
+    /// Client trusting the given certificate and offering only the given protocol versions.
+    #[cfg(feature = "tls")]
+    fn tls_connector(
+        trusted: &str,
+        versions: &[&'static futures_rustls::rustls::SupportedProtocolVersion],
+    ) -> futures_rustls::TlsConnector {
+        use futures_rustls::rustls::{self, RootCertStore, crypto::ring};
+        use std::sync::Arc;
+
+        let mut roots = RootCertStore::empty();
+        let cert_pem = std::fs::read(tls_fixture(trusted)).unwrap();
+        for cert in rustls_pemfile::certs(&mut cert_pem.as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        futures_rustls::TlsConnector::from(Arc::new(client_config))
+    }
+
+    /// Performs a GET request over TLS, returning the response or the handshake error.
+    #[cfg(feature = "tls")]
+    async fn tls_exchange(
+        acceptor: TlsAcceptor,
+        connector: futures_rustls::TlsConnector,
+    ) -> std::io::Result<String> {
+        use futures_rustls::rustls::pki_types::ServerName;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        let server = task::spawn(Server::handle_tls_client(acceptor, stream, addr, None));
+
+        let domain = ServerName::try_from("localhost").unwrap();
+        let mut client = match connector.connect(domain, tcp).await {
+            Ok(client) => client,
+            Err(err) => {
+                server.await.unwrap();
+                return Err(err);
+            }
+        };
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        server.await.unwrap();
+        Ok(String::from_utf8(response).unwrap())
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_handshake() {
+        use futures_rustls::rustls;
+
+        init_test_config();
+        let acceptor = tls::acceptor(&tls_config("localhost.crt", "localhost.key")).unwrap();
+        let connector = tls_connector("localhost.crt", rustls::DEFAULT_VERSIONS);
+
+        let response = task::block_on(tls_exchange(acceptor, connector)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("<h1>Welcome to rustynet/0.1!</h1>"));
+    }
+
+    /// The client only trusts the root, so the intermediate must be presented by the server.
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_certificate_chain() {
+        use futures_rustls::rustls;
+
+        init_test_config();
+        let cfg = tls::TlsConfig {
+            key_passphrase: Some("rustynet".to_string()),
+            ..tls_config("chain.crt", "chain.key")
+        };
+        let acceptor = tls::acceptor(&cfg).unwrap();
+        let connector = tls_connector("root.crt", rustls::DEFAULT_VERSIONS);
+
+        let response = task::block_on(tls_exchange(acceptor, connector)).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls_disallowed_version() {
+        use futures_rustls::rustls;
+
+        init_test_config();
+        let cfg = tls::TlsConfig {
+            min_version: tls::TlsVersion::V1_3,
+            ..tls_config("localhost.crt", "localhost.key")
         };
+        let acceptor = tls::acceptor(&cfg).unwrap();
+        let connector = tls_connector("localhost.crt", &[&rustls::version::TLS12]);
 
-        Self::write_response(&mut stream, &response).await
+        assert!(task::block_on(tls_exchange(acceptor, connector)).is_err());
     }
 }
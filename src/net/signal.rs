@@ -0,0 +1,42 @@
+//! Reloading of the configuration file on `SIGHUP`.
+//!
+//! The signal handler only raises a flag, which a background task polls
+//! to reload the file outside of the signal context, see [`config::reload`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_std::task;
+
+use crate::config;
+
+/// Interval at which the reload flag is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Installs the `SIGHUP` handler and spawns the task reloading the configuration file at `path`.
+pub fn reload_on_sighup(path: String) {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    let previous = unsafe { libc::signal(libc::SIGHUP, on_sighup as *const () as libc::sighandler_t) };
+    if previous == libc::SIG_ERR {
+        eprintln!("Fail to install the SIGHUP handler, the config will not be reloaded");
+        return;
+    }
+
+    task::spawn(async move {
+        loop {
+            task::sleep(POLL_INTERVAL).await;
+            if RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+                match config::reload(&path) {
+                    Ok(()) => println!("Reloaded config from {}", path),
+                    Err(err) => eprintln!("Keeping the current config, reload failed: {err}"),
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,109 @@
+//! Options of the accepted TCP sockets.
+//!
+//! Nagle's algorithm holds back the small writes until the previous ones are acknowledged,
+//! which delays the responses written in several parts (headers then body) by up to the
+//! delayed ACK timeout of the client. It is disabled unless
+//! [`tcp_nodelay`](crate::config::ServerConfig::tcp_nodelay) is turned off.
+//!
+//! TCP keep-alive probes the idle connections after
+//! [`tcp_keepalive`](crate::config::ServerConfig::tcp_keepalive), closing the ones whose
+//! peer vanished without a FIN (e.g. behind a NAT dropping the mapping). It is only
+//! supported on Unix, the setting being ignored elsewhere.
+
+use std::io;
+use std::time::Duration;
+
+use async_std::net::TcpStream;
+
+/// Option setting the idle time before the first keep-alive probe
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const KEEPALIVE_IDLE: libc::c_int = libc::TCP_KEEPALIVE;
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "ios"))))]
+const KEEPALIVE_IDLE: libc::c_int = libc::TCP_KEEPIDLE;
+
+/// Sets the options of an accepted stream, a zero `keepalive` leaving TCP keep-alive disabled.
+pub fn configure(stream: &TcpStream, nodelay: bool, keepalive: Duration) -> io::Result<()> {
+    stream.set_nodelay(nodelay)?;
+    if !keepalive.is_zero() {
+        set_keepalive(stream, keepalive)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_keepalive(stream: &TcpStream, idle: Duration) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // the option takes whole seconds, at least one
+    let idle_secs = idle.as_secs().clamp(1, libc::c_int::MAX as u64) as libc::c_int;
+    setsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    setsockopt(stream.as_raw_fd(), libc::IPPROTO_TCP, KEEPALIVE_IDLE, idle_secs)
+}
+
+#[cfg(not(unix))]
+fn set_keepalive(_: &TcpStream, _: Duration) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn setsockopt(fd: libc::c_int, level: libc::c_int, name: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    // SAFETY: the value outlives the call, its size being the one given
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res == -1 { Err(io::Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use std::os::fd::AsRawFd;
+
+    fn getsockopt(stream: &TcpStream, level: libc::c_int, name: libc::c_int) -> libc::c_int {
+        let mut value: libc::c_int = 0;
+        let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: the value and its length outlive the call
+        let res = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                level,
+                name,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(res, 0, "{}", io::Error::last_os_error());
+        value
+    }
+
+    async fn accepted() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        (client, stream)
+    }
+
+    #[test]
+    fn socket_options() {
+        task::block_on(async {
+            let (_client, stream) = accepted().await;
+            configure(&stream, true, Duration::from_secs(75)).unwrap();
+            assert!(stream.nodelay().unwrap());
+            assert_eq!(getsockopt(&stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 1);
+            assert_eq!(getsockopt(&stream, libc::IPPROTO_TCP, KEEPALIVE_IDLE), 75);
+
+            let (_client, stream) = accepted().await;
+            configure(&stream, false, Duration::ZERO).unwrap();
+            assert!(!stream.nodelay().unwrap());
+            assert_eq!(getsockopt(&stream, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 0);
+        });
+    }
+}
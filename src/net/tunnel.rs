@@ -0,0 +1,169 @@
+//! Tunnels opened by CONNECT requests, for the server to be used as a forward proxy.
+//!
+//! When a [`connect_tunnel`](crate::config::ServerConfig::connect_tunnel) section is
+//! configured, a CONNECT request whose `host:port` target is part of the allow-list
+//! opens a TCP connection to it. Once the client is answered `200 Connection Established`,
+//! the bytes are copied both ways until the target closes the connection, or until neither
+//! end sent anything for [`idle_timeout`](TunnelConfig::idle_timeout).
+//! Other targets are answered `403 Forbidden` and unreachable ones `502 Bad Gateway`.
+//!
+//! Without the section, CONNECT requests are answered `501 Not Implemented` by the router.
+//! Bytes sent by the client before the tunnel is established are discarded.
+
+use std::net::Shutdown;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use async_std::io::{Read, Write};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use serde::Deserialize;
+
+use crate::http::request::{HttpRequest, parse_host};
+use crate::http::status::HttpStatus;
+
+/// Response sent once the connection to the target is open, without the headers
+/// of the other responses as a `Content-Length` would be meaningless (RFC 9110 section 9.3.6)
+pub const ESTABLISHED: &str = "HTTP/1.1 200 Connection Established\r\n\r\n";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TunnelConfig {
+    /// Targets which can be tunneled to, as `host:port`
+    pub allowed_targets: Vec<String>,
+    /// Time after which a tunnel through which no byte was sent either way is closed
+    #[serde(deserialize_with = "crate::config::deserialize_duration")]
+    pub idle_timeout: Duration,
+}
+
+impl Default for TunnelConfig {
+    fn default() -> Self {
+        Self {
+            allowed_targets: Vec::new(),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl TunnelConfig {
+    /// Whether the `host:port` target of a CONNECT request is part of the allow-list,
+    /// the hostnames being compared case-insensitively.
+    pub fn allows(&self, target: &str) -> bool {
+        let Some((host, Some(port))) = parse_host(target) else {
+            return false;
+        };
+        self.allowed_targets.iter().any(|allowed| {
+            matches!(parse_host(allowed), Some((h, Some(p))) if p == port && h.eq_ignore_ascii_case(host))
+        })
+    }
+}
+
+/// Opens the connection to the target of a CONNECT request, within `timeout`.
+/// Returns the status answered to the client when the target is not allowed or unreachable.
+pub async fn open(cfg: &TunnelConfig, req: &HttpRequest, timeout: Duration) -> Result<TcpStream, HttpStatus> {
+    if !cfg.allows(&req.uri) {
+        return Err(HttpStatus::Forbidden);
+    }
+    match async_std::future::timeout(timeout, TcpStream::connect(req.uri.as_str())).await {
+        Ok(Ok(upstream)) => Ok(upstream),
+        _ => Err(HttpStatus::BadGateway),
+    }
+}
+
+/// Data received from either end of a tunnel.
+enum Received {
+    Client(std::io::Result<usize>),
+    Upstream(std::io::Result<usize>),
+}
+
+/// Copies the bytes between the client and the target until the target closes the connection,
+/// or until neither end sent anything within `idle_timeout`. The end of the client data is
+/// forwarded to the target by shutting down its write side, the responses still being relayed
+/// afterwards. Each write must complete within `write_timeout`, an end which stopped reading
+/// being reported as [`std::io::ErrorKind::TimedOut`].
+pub async fn relay<S: Read + Write + Unpin>(
+    client: &mut S,
+    mut upstream: TcpStream,
+    buffer_size: usize,
+    idle_timeout: Duration,
+    write_timeout: Duration,
+) -> std::io::Result<()> {
+    let mut to_upstream = vec![0; buffer_size];
+    let mut to_client = vec![0; buffer_size];
+    let mut client_open = true;
+
+    loop {
+        // a pending read is dropped without losing data when the other end is ready first
+        let receiving = std::future::poll_fn(|cx| {
+            if client_open && let Poll::Ready(r) = Pin::new(&mut *client).poll_read(cx, &mut to_upstream) {
+                return Poll::Ready(Received::Client(r));
+            }
+            match Pin::new(&mut upstream).poll_read(cx, &mut to_client) {
+                Poll::Ready(r) => Poll::Ready(Received::Upstream(r)),
+                Poll::Pending => Poll::Pending,
+            }
+        });
+        let Ok(received) = async_std::future::timeout(idle_timeout, receiving).await else {
+            return within(write_timeout, client.flush()).await;
+        };
+
+        match received {
+            Received::Client(Ok(0)) => {
+                client_open = false;
+                upstream.shutdown(Shutdown::Write)?;
+            }
+            Received::Client(n) => within(write_timeout, upstream.write_all(&to_upstream[..n?])).await?,
+            Received::Upstream(Ok(0)) => return within(write_timeout, client.flush()).await,
+            Received::Upstream(n) => within(write_timeout, client.write_all(&to_client[..n?])).await?,
+        }
+    }
+}
+
+/// Completes a write within `timeout`.
+async fn within(timeout: Duration, write: impl Future<Output = std::io::Result<()>>) -> std::io::Result<()> {
+    async_std::future::timeout(timeout, write)
+        .await
+        .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_targets() {
+        let cfg = TunnelConfig {
+            allowed_targets: vec!["example.com:443".to_string(), "[::1]:8443".to_string()],
+            ..TunnelConfig::default()
+        };
+        assert!(cfg.allows("example.com:443"));
+        assert!(cfg.allows("EXAMPLE.com:443"));
+        assert!(cfg.allows("[::1]:8443"));
+        assert!(!cfg.allows("example.com:80"));
+        assert!(!cfg.allows("example.com"));
+        assert!(!cfg.allows("other.com:443"));
+        assert!(!TunnelConfig::default().allows("example.com:443"));
+    }
+
+    #[test]
+    fn idle_tunnel_is_closed() {
+        use async_std::net::TcpListener;
+        use std::time::Instant;
+
+        async_std::task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _target = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+            let (upstream, _) = listener.accept().await.unwrap();
+
+            // both ends stay silent
+            let start = Instant::now();
+            let idle = Duration::from_millis(100);
+            relay(&mut stream, upstream, 1024, idle, Duration::from_secs(5)).await.unwrap();
+            assert!(start.elapsed() >= idle);
+            assert!(start.elapsed() < Duration::from_secs(1));
+        });
+    }
+}
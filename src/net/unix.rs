@@ -0,0 +1,40 @@
+//! Unix domain socket listening.
+//!
+//! When [`unix_socket`](crate::config::ServerConfig::unix_socket) is configured, the server
+//! also accepts connections on a Unix socket, typically used by a local reverse proxy
+//! to avoid the TCP overhead.
+
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Socket file bound by the server, removed when dropped.
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Binds a Unix listener to the given path.
+///
+/// A socket file left over by a server which did not shut down cleanly is unlinked,
+/// whereas a socket on which a server is still listening is reported as in use.
+pub async fn bind(path: &str) -> std::io::Result<(UnixListener, SocketFile)> {
+    let listener = match UnixListener::bind(path).await {
+        Err(err) if err.kind() == ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(std::io::Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("Unix socket {path} is already in use"),
+                ));
+            }
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path).await?
+        }
+        result => result?,
+    };
+
+    Ok((listener, SocketFile(Path::new(path).to_path_buf())))
+}